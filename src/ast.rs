@@ -1,6 +1,14 @@
+pub mod format;
+pub mod trivia;
+
+use bumpalo::Bump;
 use lalrpop_util::lalrpop_mod;
 
-lalrpop_mod!(pub parser, "/ast/parser.rs");
+lalrpop_mod!(
+    #[allow(clippy::all, unused_parens)]
+    pub parser,
+    "/ast/parser.rs"
+);
 
 #[derive(Debug, Clone)]
 pub struct OwnedToken(pub usize, pub String);
@@ -16,6 +24,8 @@ impl std::fmt::Display for OwnedToken {
         parser::Token(self.0, &self.1).fmt(f)
     }
 }
+pub use crate::span::{Span, Spanned};
+
 pub type Slice<'ctx, T> = &'ctx [T];
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -27,25 +37,130 @@ pub enum Atom<'ctx, T> {
     Unit,
     Number(f64),
     Ident(Ident<'ctx>),
+    Qualified(&'ctx [Ident<'ctx>], Ident<'ctx>),
     String(&'ctx str),
     Nested(&'ctx T),
 }
 
 #[derive(Debug, Copy, Clone)]
 pub enum Expr<'ctx> {
-    Flat(&'ctx [Atom<'ctx, Expr<'ctx>>]),
-    Func(&'ctx Patn<'ctx>, &'ctx Expr<'ctx>),
-    Match(&'ctx Expr<'ctx>, &'ctx [(Patn<'ctx>, Expr<'ctx>)]),
-    Scoped(&'ctx [Decl<'ctx>], &'ctx Expr<'ctx>),
+    Flat(&'ctx [Spanned<Atom<'ctx, Spanned<Expr<'ctx>>>>]),
+    Func(&'ctx Spanned<Patn<'ctx>>, &'ctx Spanned<Expr<'ctx>>),
+    Match(
+        &'ctx Spanned<Expr<'ctx>>,
+        &'ctx [(Spanned<Patn<'ctx>>, Spanned<Expr<'ctx>>)],
+    ),
+    Scoped(&'ctx [Spanned<Decl<'ctx>>], &'ctx Spanned<Expr<'ctx>>),
 }
 
 #[derive(Debug, Copy, Clone)]
 pub enum Decl<'ctx> {
-    Let(&'ctx Patn<'ctx>, &'ctx Expr<'ctx>),
-    Def(&'ctx [Sign<'ctx>], &'ctx Expr<'ctx>),
-    Con(&'ctx [Sign<'ctx>]),
-    Mod(Ident<'ctx>, &'ctx Modl<'ctx>),
-    Use(&'ctx Modl<'ctx>),
+    Let(&'ctx Spanned<Patn<'ctx>>, &'ctx Spanned<Expr<'ctx>>),
+    Def(
+        &'ctx [Spanned<Sign<'ctx>>],
+        Option<&'ctx Spanned<Type<'ctx>>>,
+        &'ctx Spanned<Expr<'ctx>>,
+    ),
+    Con(&'ctx [Spanned<Sign<'ctx>>]),
+    Mod(Ident<'ctx>, &'ctx Spanned<Modl<'ctx>>),
+    Use(&'ctx Spanned<Modl<'ctx>>, Import<'ctx>),
+    Fixity(Fixity, &'ctx [Spanned<Sign<'ctx>>]),
+    Data(
+        Ident<'ctx>,
+        &'ctx [Ident<'ctx>],
+        &'ctx [Spanned<DataCons<'ctx>>],
+    ),
+}
+
+// A constructor of a data type, with the type of each of its holes.
+#[derive(Debug, Copy, Clone)]
+pub struct DataCons<'ctx> {
+    pub sig: &'ctx [Spanned<Sign<'ctx>>],
+    pub fields: &'ctx [Spanned<Type<'ctx>>],
+}
+
+impl<'ctx> DataCons<'ctx> {
+    /*
+        A word that names one of the type's parameters is a field of that
+        type, as if it were in parentheses, so `con Cons a, (List a)` has
+        two fields. Only where a field could go, though: between words,
+        and not as the only word.
+    */
+    pub fn with_params(self, arena: &'ctx Bump, params: &[Ident<'ctx>]) -> Self {
+        let is_word = |sign: &Spanned<Sign>| matches!(sign.node, Sign::Word(_));
+        let mut sig: Vec<Spanned<Sign<'ctx>>> = Vec::new();
+        let mut fields = Vec::new();
+        let mut old_fields = self.fields.iter();
+        for (ix, &sign) in self.sig.iter().enumerate() {
+            match sign.node {
+                Sign::Word(id)
+                    if self.sig.len() > 1
+                        && params.iter().any(|param| param.0 == id.0)
+                        && sig.last().is_none_or(is_word)
+                        && self.sig.get(ix + 1).is_none_or(is_word) =>
+                {
+                    let span = sign.span;
+                    let hole = Spanned {
+                        span,
+                        node: Atom::Hole,
+                    };
+                    let patn = Patn::Flat(arena.alloc_slice_copy(&[hole]));
+                    let node = Sign::Patn(arena.alloc(Spanned { span, node: patn }));
+                    sig.push(Spanned { span, node });
+                    let param = Spanned {
+                        span,
+                        node: Atom::Ident(id),
+                    };
+                    let node = Type::Flat(arena.alloc_slice_copy(&[param]));
+                    fields.push(Spanned { span, node });
+                }
+                Sign::Word(_) => sig.push(sign),
+                Sign::Patn(_) => {
+                    sig.push(sign);
+                    fields.push(*old_fields.next().unwrap());
+                }
+            }
+        }
+        DataCons {
+            sig: arena.alloc_slice_copy(&sig),
+            fields: arena.alloc_slice_copy(&fields),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Type<'ctx> {
+    Flat(&'ctx [Spanned<Atom<'ctx, Spanned<Type<'ctx>>>>]),
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Import<'ctx> {
+    Open,
+    Only(&'ctx [ImportItem<'ctx>]),
+    Hiding(&'ctx [ImportItem<'ctx>]),
+    As(Ident<'ctx>),
+}
+
+// Names the signatures starting with a word, like `len`,
+// or only those with the given holes, like `Cons _ _`.
+#[derive(Debug, Copy, Clone)]
+pub struct ImportItem<'ctx> {
+    pub leading: usize,
+    pub word: Ident<'ctx>,
+    pub trailing: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Assoc {
+    Left,
+    Right,
+    Non,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Fixity {
+    pub assoc: Assoc,
+    pub level: u32,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -56,20 +171,21 @@ pub struct ModlPath<'ctx> {
 
 #[derive(Debug, Copy, Clone)]
 pub enum Modl<'ctx> {
-    ModExp(&'ctx [Decl<'ctx>]),
+    ModExp(&'ctx [Spanned<Decl<'ctx>>]),
     Named(ModlPath<'ctx>),
 }
 
 #[derive(Debug, Copy, Clone)]
 pub enum Patn<'ctx> {
-    Flat(&'ctx [Atom<'ctx, Patn<'ctx>>]),
-    Scoped(&'ctx [Decl<'ctx>], &'ctx Patn<'ctx>),
+    Flat(&'ctx [Spanned<Atom<'ctx, Spanned<Patn<'ctx>>>>]),
+    Scoped(&'ctx [Spanned<Decl<'ctx>>], &'ctx Spanned<Patn<'ctx>>),
+    Annotated(&'ctx Spanned<Patn<'ctx>>, &'ctx Spanned<Type<'ctx>>),
 }
 
 #[derive(Debug, Copy, Clone)]
 pub enum Sign<'ctx> {
     Word(Ident<'ctx>),
-    Patn(&'ctx Patn<'ctx>),
+    Patn(&'ctx Spanned<Patn<'ctx>>),
 }
 
 use crate::refs::*;
@@ -81,6 +197,7 @@ pub struct AstStorage<'ctx> {
     pub patn: VecStorage<self::Patn<'ctx>, PatnRef>,
     pub decl: VecStorage<self::Decl<'ctx>, DeclRef>,
     pub cons: VecStorage<self::Decl<'ctx>, ConsRef>,
+    pub data: VecStorage<self::Decl<'ctx>, DataRef>,
     pub modl: VecStorage<self::Modl<'ctx>, ModlRef>,
 }
 
@@ -91,6 +208,7 @@ impl AstStorage<'_> {
             patn: VecStorage::new(),
             decl: VecStorage::new(),
             cons: VecStorage::new(),
+            data: VecStorage::new(),
             modl: VecStorage::new(),
         }
     }
@@ -114,7 +232,13 @@ where
             Atom::Unit => write!(f, "()"),
             Atom::Number(n) => write!(f, "{}", n),
             Atom::Ident(id) => write!(f, "{}", id),
-            Atom::String(s) => write!(f, "{}", s),
+            Atom::Qualified(path, id) => {
+                for modl in path.iter() {
+                    write!(f, "{}.", modl)?;
+                }
+                write!(f, "{}", id)
+            }
+            Atom::String(s) => write!(f, "\"{}\"", s),
             Atom::Nested(t) => write!(f, "({})", t),
         }
     }
@@ -157,15 +281,18 @@ impl Display for Decl<'_> {
         match self {
             Decl::Let(pat, exp) => write!(f, "let {} = {}", pat, exp),
             Decl::Mod(id, modl) => write!(f, "mod {} = {}", id, modl),
-            Decl::Use(modl) => write!(f, "use {}", modl),
-            Decl::Def(sig, exp) => {
+            Decl::Use(modl, import) => write!(f, "use {}{}", modl, import),
+            Decl::Def(sig, result, exp) => {
                 write!(f, "def ")?;
                 for sign in sig.iter() {
-                    match sign {
+                    match sign.node {
                         Sign::Word(id) => write!(f, "{} ", id)?,
                         Sign::Patn(pat) => write!(f, "({}) ", pat)?,
                     }
                 }
+                if let Some(result) = result {
+                    write!(f, ": {} ", result)?;
+                }
                 write!(f, "= {}", exp)
             }
             Decl::Con(sig) => {
@@ -175,6 +302,101 @@ impl Display for Decl<'_> {
                 }
                 Ok(())
             }
+            Decl::Fixity(fixity, sig) => {
+                write!(f, "{}", fixity)?;
+                for sign in sig.iter() {
+                    write!(f, " {}", sign)?;
+                }
+                Ok(())
+            }
+            Decl::Data(name, params, cons) => {
+                write!(f, "data {}", name)?;
+                for param in params.iter() {
+                    write!(f, " {}", param)?;
+                }
+                write!(f, " =")?;
+                for (ix, cons) in cons.iter().enumerate() {
+                    if ix > 0 {
+                        write!(f, " |")?;
+                    }
+                    write!(f, " {}", cons)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Display for DataCons<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "con")?;
+        let mut fields = self.fields.iter();
+        for sign in self.sig.iter() {
+            match sign.node {
+                Sign::Word(id) => write!(f, " {}", id)?,
+                Sign::Patn(_) => write!(f, " ({})", fields.next().unwrap())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for Type<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Type::Flat(ts) => {
+                let (first, rest) = ts.split_first().unwrap();
+                write!(f, "{}", first)?;
+                for ty in rest {
+                    write!(f, " {}", ty)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Display for Import<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let items = match self {
+            Import::Open => return Ok(()),
+            Import::As(id) => return write!(f, " as {}", id),
+            Import::Only(items) => items,
+            Import::Hiding(items) => {
+                write!(f, " hiding")?;
+                items
+            }
+        };
+        write!(f, " (")?;
+        for (ix, item) in items.iter().enumerate() {
+            if ix > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", item)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl Display for ImportItem<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for _ in 0..self.leading {
+            write!(f, "_ ")?;
+        }
+        write!(f, "{}", self.word)?;
+        for _ in 0..self.trailing {
+            write!(f, " _")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Fixity {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.assoc {
+            Assoc::Left => write!(f, "infixl {}", self.level),
+            Assoc::Right => write!(f, "infixr {}", self.level),
+            Assoc::Non => write!(f, "infix {}", self.level),
         }
     }
 }
@@ -227,6 +449,7 @@ impl Display for Patn<'_> {
                 }
                 write!(f, "in {}", pat)
             }
+            Patn::Annotated(pat, ty) => write!(f, "{} : {}", pat, ty),
         }
     }
 }
@@ -239,3 +462,42 @@ impl Display for Sign<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::parser::SequenceParser;
+    use super::*;
+    use crate::refs::FileRef;
+
+    fn parse(arena: &Bump, source: &str) -> Option<String> {
+        let decls = SequenceParser::new().parse(arena, FileRef::from(0), source);
+        let decls = decls.ok()?;
+        Some(decls.iter().map(|decl| format!("{}\n", decl)).collect())
+    }
+
+    #[test]
+    fn annotations() {
+        let arena = Bump::new();
+        let source = "def len (l : List a) : Num = 0 let x : Num = 10";
+        let parsed = parse(&arena, source).unwrap();
+        assert_eq!(parsed, "def len (l : List a) : Num = 0\nlet x : Num = 10\n");
+        let decls = SequenceParser::new()
+            .parse(&arena, FileRef::from(0), source)
+            .unwrap();
+        match decls[0].node {
+            Decl::Def(sig, Some(_), _) => match sig[1].node {
+                Sign::Patn(patn) => assert!(matches!(patn.node, Patn::Annotated(..))),
+                Sign::Word(_) => panic!("The parameter is a pattern."),
+            },
+            decl => panic!("Not a definition with a result type: {}", decl),
+        }
+        assert!(
+            matches!(decls[1].node, Decl::Let(patn, _) if matches!(patn.node, Patn::Annotated(..)))
+        );
+
+        // Only a `:` on its own is kept for annotations.
+        assert!(parse(&arena, "infixr 5 _ :: _ def (x) :: (xs) = xs").is_some());
+        assert!(parse(&arena, "infixr 5 _ : _").is_none());
+        assert!(parse(&arena, "def (x) : (xs) = xs").is_none());
+    }
+}
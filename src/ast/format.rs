@@ -0,0 +1,756 @@
+use super::trivia::{Comment, Trivia};
+use super::*;
+
+use std::iter;
+
+/*
+    Lays out a file's declarations the same way every time. Everything
+    is kept on one line while it fits in `WIDTH` columns, and broken
+    otherwise: `match` arms go on their own lines between `match` and
+    `end`, as they do wherever they were written that way, bodies that
+    don't fit after `=` go on the next line indented further, and module
+    bodies are always indented between `mod` and `end`. Literals are
+    copied from the source so they keep the way they were written, and
+    so are comments, which go with the declarations they're attached to.
+*/
+const WIDTH: usize = 80;
+const INDENT: usize = 2;
+
+pub fn format(source: &str, decls: &[Spanned<Decl<'_>>], trivia: &Trivia) -> String {
+    let mut printer = Printer {
+        source,
+        trivia,
+        out: String::new(),
+    };
+    printer.decls(decls, 0);
+    if decls.is_empty() {
+        let mut last = None;
+        for comment in trivia.inner.iter() {
+            printer.line(last, comment.span.start, 0);
+            printer.comment(comment);
+            last = Some(comment.span.end);
+        }
+    }
+    if !printer.out.is_empty() {
+        printer.out.push('\n');
+    }
+    printer.out
+}
+
+// Whether two parses have the same structure, wherever things are in them.
+pub fn same_structure(a: &[Spanned<Decl<'_>>], b: &[Spanned<Decl<'_>>]) -> bool {
+    a.same(b)
+}
+
+// Equality that leaves out spans, and how literals were written.
+trait Same {
+    fn same(&self, other: &Self) -> bool;
+}
+
+impl<T: Same> Same for Spanned<T> {
+    fn same(&self, other: &Self) -> bool {
+        self.node.same(&other.node)
+    }
+}
+
+impl<T: Same> Same for [T] {
+    fn same(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().zip(other).all(|(a, b)| a.same(b))
+    }
+}
+
+impl<T: Same + ?Sized> Same for &T {
+    fn same(&self, other: &Self) -> bool {
+        (**self).same(*other)
+    }
+}
+
+impl<T: Same> Same for Option<T> {
+    fn same(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Some(a), Some(b)) => a.same(b),
+            (a, b) => a.is_none() && b.is_none(),
+        }
+    }
+}
+
+impl<A: Same, B: Same> Same for (A, B) {
+    fn same(&self, other: &Self) -> bool {
+        self.0.same(&other.0) && self.1.same(&other.1)
+    }
+}
+
+impl Same for Ident<'_> {
+    fn same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl<T: Same> Same for Atom<'_, T> {
+    fn same(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Atom::Hole, Atom::Hole) | (Atom::Unit, Atom::Unit) => true,
+            (Atom::Number(a), Atom::Number(b)) => a == b,
+            (Atom::Ident(a), Atom::Ident(b)) => a == b,
+            (Atom::Qualified(a, x), Atom::Qualified(b, y)) => a == b && x == y,
+            (Atom::String(a), Atom::String(b)) => a == b,
+            (Atom::Nested(a), Atom::Nested(b)) => a.same(b),
+            _ => false,
+        }
+    }
+}
+
+impl Same for Expr<'_> {
+    fn same(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Expr::Flat(a), Expr::Flat(b)) => a.same(b),
+            (Expr::Func(p, x), Expr::Func(q, y)) => p.same(q) && x.same(y),
+            (Expr::Match(x, a), Expr::Match(y, b)) => x.same(y) && a.same(b),
+            (Expr::Scoped(a, x), Expr::Scoped(b, y)) => a.same(b) && x.same(y),
+            _ => false,
+        }
+    }
+}
+
+impl Same for Patn<'_> {
+    fn same(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Patn::Flat(a), Patn::Flat(b)) => a.same(b),
+            (Patn::Scoped(a, p), Patn::Scoped(b, q)) => a.same(b) && p.same(q),
+            (Patn::Annotated(p, s), Patn::Annotated(q, t)) => p.same(q) && s.same(t),
+            _ => false,
+        }
+    }
+}
+
+impl Same for Type<'_> {
+    fn same(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Type::Flat(a), Type::Flat(b)) => a.same(b),
+        }
+    }
+}
+
+impl Same for Sign<'_> {
+    fn same(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Sign::Word(a), Sign::Word(b)) => a == b,
+            (Sign::Patn(p), Sign::Patn(q)) => p.same(q),
+            _ => false,
+        }
+    }
+}
+
+impl Same for DataCons<'_> {
+    fn same(&self, other: &Self) -> bool {
+        self.sig.same(other.sig) && self.fields.same(other.fields)
+    }
+}
+
+impl Same for Modl<'_> {
+    fn same(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Modl::ModExp(a), Modl::ModExp(b)) => a.same(b),
+            (Modl::Named(a), Modl::Named(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Same for Import<'_> {
+    fn same(&self, other: &Self) -> bool {
+        let item = |a: &ImportItem, b: &ImportItem| {
+            (a.leading, a.word, a.trailing) == (b.leading, b.word, b.trailing)
+        };
+        let items = |a: &[ImportItem], b: &[ImportItem]| {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| item(a, b))
+        };
+        match (self, other) {
+            (Import::Open, Import::Open) => true,
+            (Import::Only(a), Import::Only(b)) => items(a, b),
+            (Import::Hiding(a), Import::Hiding(b)) => items(a, b),
+            (Import::As(a), Import::As(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Same for Decl<'_> {
+    fn same(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Decl::Let(p, x), Decl::Let(q, y)) => p.same(q) && x.same(y),
+            (Decl::Def(a, s, x), Decl::Def(b, t, y)) => a.same(b) && s.same(t) && x.same(y),
+            (Decl::Con(a), Decl::Con(b)) => a.same(b),
+            (Decl::Mod(m, a), Decl::Mod(n, b)) => m == n && a.same(b),
+            (Decl::Use(a, i), Decl::Use(b, j)) => a.same(b) && i.same(j),
+            (Decl::Fixity(f, a), Decl::Fixity(g, b)) => f == g && a.same(b),
+            (Decl::Data(m, ps, a), Decl::Data(n, qs, b)) => m == n && ps == qs && a.same(b),
+            _ => false,
+        }
+    }
+}
+
+struct Printer<'s> {
+    source: &'s str,
+    trivia: &'s Trivia,
+    out: String,
+}
+
+trait Layout {
+    // How it looks on one line, unless it always has to be broken.
+    fn flat(&self, p: &Printer<'_>) -> Option<String>;
+    fn print(&self, p: &mut Printer<'_>);
+}
+
+impl<'s> Printer<'s> {
+    fn text(&self, span: Span) -> &'s str {
+        &self.source[span.start..span.end]
+    }
+
+    fn column(&self) -> usize {
+        let start = self.out.rfind('\n').map_or(0, |ix| ix + 1);
+        self.out[start..].chars().count()
+    }
+
+    fn fits(&self, text: &str) -> bool {
+        self.column() + text.chars().count() <= WIDTH
+    }
+
+    fn indent(&self) -> usize {
+        let start = self.out.rfind('\n').map_or(0, |ix| ix + 1);
+        self.out[start..].chars().take_while(|&c| c == ' ').count()
+    }
+
+    fn space(&mut self, next: Option<&str>) {
+        if !glued(&self.out, next) {
+            self.push(" ");
+        }
+    }
+
+    fn push(&mut self, text: &str) {
+        self.out.push_str(text);
+    }
+
+    fn newline(&mut self, indent: usize) {
+        let len = self.out.trim_end_matches(' ').len();
+        self.out.truncate(len);
+        self.out.push('\n');
+        self.out.extend(iter::repeat_n(' ', indent));
+    }
+
+    // Prints it on the rest of the line if it fits, or else broken up.
+    fn print(&mut self, node: &impl Layout) {
+        match node.flat(self) {
+            Some(text) if self.fits(&text) => self.push(&text),
+            _ => node.print(self),
+        }
+    }
+
+    // Prints what comes after `=`, on the next line if it doesn't fit.
+    fn body(&mut self, body: &impl Layout, base: usize) {
+        match body.flat(self).map(|text| format!(" {}", text)) {
+            Some(text) if self.fits(&text) => self.push(&text),
+            _ => {
+                self.newline(base + INDENT);
+                self.print(body);
+            }
+        }
+    }
+
+    fn flat_atom<T: Layout>(&self, atom: &Spanned<Atom<'_, T>>) -> Option<String> {
+        Some(match atom.node {
+            Atom::Hole | Atom::Number(_) | Atom::String(_) => self.text(atom.span).to_owned(),
+            Atom::Unit => "()".to_owned(),
+            Atom::Ident(id) => id.0.to_owned(),
+            Atom::Qualified(path, id) => {
+                let mut text = String::new();
+                for modl in path.iter() {
+                    text.push_str(modl.0);
+                    text.push('.');
+                }
+                text.push_str(id.0);
+                text
+            }
+            Atom::Nested(t) => format!("({})", t.flat(self)?),
+        })
+    }
+
+    fn flat_atoms<T: Layout>(&self, atoms: &[Spanned<Atom<'_, T>>]) -> Option<String> {
+        let mut text = String::new();
+        for atom in atoms.iter() {
+            if !text.is_empty() && !glued(&text, atom_word(atom)) {
+                text.push(' ');
+            }
+            text.push_str(&self.flat_atom(atom)?);
+        }
+        Some(text)
+    }
+
+    // Long sequences carry on over the following lines, broken after
+    // operators where they can be, and never before one.
+    fn atoms<T: Layout>(&mut self, atoms: &[Spanned<Atom<'_, T>>]) {
+        let indent = self.indent() + INDENT;
+        let mut start = 0;
+        for (ix, atom) in atoms.iter().enumerate() {
+            if ix + 1 < atoms.len() && !is_operator(atom) {
+                continue;
+            }
+            let run = &atoms[start..=ix];
+            if start > 0 {
+                let fits = self.flat_atoms(run).is_some_and(|text| self.fits(&text));
+                if fits && self.column() < WIDTH {
+                    self.space(atom_word(&run[0]));
+                } else {
+                    self.newline(indent);
+                }
+            }
+            self.run(run, indent);
+            start = ix + 1;
+        }
+    }
+
+    fn run<T: Layout>(&mut self, atoms: &[Spanned<Atom<'_, T>>], indent: usize) {
+        for (ix, atom) in atoms.iter().enumerate() {
+            let text = self.flat_atom(atom);
+            if ix > 0 {
+                let fits = text.as_ref().is_some_and(|text| self.fits(text));
+                if (fits && self.column() < WIDTH) || is_operator(atom) {
+                    self.space(atom_word(atom));
+                } else {
+                    self.newline(indent);
+                }
+            }
+            match (text, &atom.node) {
+                (Some(ref text), _) if self.fits(text) => self.push(text),
+                (_, Atom::Nested(t)) => {
+                    self.push("(");
+                    self.print(*t);
+                    self.push(")");
+                }
+                (text, _) => self.push(&text.unwrap()),
+            }
+        }
+    }
+
+    // Keeps a blank line between declarations and comments wherever there was one.
+    fn decls(&mut self, decls: &[Spanned<Decl<'_>>], indent: usize) {
+        let trivia = self.trivia;
+        let mut last = None;
+        for decl in decls.iter() {
+            for comment in trivia.leading(decl.span) {
+                self.line(last, comment.span.start, indent);
+                self.comment(comment);
+                last = Some(comment.span.end);
+            }
+            self.line(last, decl.span.start, indent);
+            self.decl(decl);
+            last = Some(decl.span.end);
+            for comment in trivia.trailing(decl.span) {
+                let end = last.unwrap();
+                if self.source[end..comment.span.start].contains('\n') {
+                    self.line(last, comment.span.start, indent);
+                } else {
+                    self.push(" ");
+                }
+                self.comment(comment);
+                last = Some(comment.span.end);
+            }
+        }
+    }
+
+    // Starts a line for what comes next, after a blank one if there was one.
+    fn line(&mut self, last: Option<usize>, next: usize, indent: usize) {
+        if let Some(last) = last {
+            if self.source[last..next].matches('\n').count() > 1 {
+                self.newline(0);
+            }
+        }
+        if !self.out.is_empty() {
+            self.newline(indent);
+        }
+    }
+
+    fn comment(&mut self, comment: &Comment) {
+        self.push(comment.text(self.source));
+    }
+
+    // Declarations in a scope go one to a line, then `in` and what's in scope.
+    fn scoped(&mut self, decls: &[Spanned<Decl<'_>>], body: &impl Layout) {
+        let base = self.column();
+        for (ix, decl) in decls.iter().enumerate() {
+            if ix > 0 {
+                self.newline(base);
+            }
+            self.decl(decl);
+        }
+        self.newline(base);
+        self.push("in ");
+        self.print(body);
+    }
+
+    fn sig(&mut self, sig: &[Spanned<Sign<'_>>]) {
+        for (ix, sign) in sig.iter().enumerate() {
+            if ix > 0 {
+                self.space(sign_word(sign));
+            }
+            match sign.node {
+                Sign::Word(id) => self.push(id.0),
+                Sign::Patn(patn) => {
+                    self.push("(");
+                    self.print(patn);
+                    self.push(")");
+                }
+            }
+        }
+    }
+
+    // Constructors and fixity declarations only have holes in their signatures.
+    fn holes(&mut self, sig: &[Spanned<Sign<'_>>]) {
+        for sign in sig.iter() {
+            self.space(sign_word(sign));
+            match sign.node {
+                Sign::Word(id) => self.push(id.0),
+                Sign::Patn(_) => self.push(self.text(sign.span)),
+            }
+        }
+    }
+
+    fn data_cons(&self, cons: &DataCons<'_>) -> String {
+        let mut text = "con".to_owned();
+        let mut fields = cons.fields.iter();
+        for sign in cons.sig.iter() {
+            if !glued(&text, sign_word(sign)) {
+                text.push(' ');
+            }
+            match sign.node {
+                Sign::Word(id) => text.push_str(id.0),
+                // Only a parameter can be written without parentheses,
+                // and then it has the same span as its hole.
+                Sign::Patn(_) => {
+                    let field = fields.next().unwrap();
+                    let flat = field.flat(self).unwrap();
+                    if field.span == sign.span {
+                        text.push_str(&flat);
+                    } else {
+                        text.push_str(&format!("({})", flat));
+                    }
+                }
+            }
+        }
+        text
+    }
+
+    fn modl(&mut self, modl: &Modl<'_>, base: usize) {
+        match modl {
+            Modl::Named(path) => self.push(&path.to_string()),
+            Modl::ModExp([]) => self.push("mod end"),
+            Modl::ModExp(decls) => {
+                self.push("mod");
+                self.decls(decls, base + INDENT);
+                self.newline(base);
+                self.push("end");
+            }
+        }
+    }
+
+    // Declarations with comments inside them are kept as they were written.
+    fn decl(&mut self, decl: &Spanned<Decl<'_>>) {
+        if self.trivia.has_inner(decl.span) {
+            self.push(self.text(decl.span));
+            return;
+        }
+
+        let base = self.column();
+        match decl.node {
+            Decl::Let(patn, expr) => {
+                self.push("let ");
+                self.print(patn);
+                self.push(" =");
+                self.body(expr, base);
+            }
+            Decl::Def(sig, result, expr) => {
+                self.push("def ");
+                self.sig(sig);
+                if let Some(result) = result {
+                    self.push(" : ");
+                    self.print(result);
+                }
+                self.push(" =");
+                self.body(expr, base);
+            }
+            Decl::Con(sig) => {
+                self.push("con");
+                self.holes(sig);
+            }
+            Decl::Fixity(fixity, sig) => {
+                self.push(&fixity.to_string());
+                self.holes(sig);
+            }
+            Decl::Mod(id, modl) => {
+                self.push(&format!("mod {} = ", id));
+                self.modl(&modl.node, base);
+            }
+            Decl::Use(modl, import) => {
+                self.push("use ");
+                self.modl(&modl.node, base);
+                self.push(&import.to_string());
+            }
+            Decl::Data(name, params, cons) => {
+                let mut head = format!("data {}", name);
+                for param in params.iter() {
+                    head.push_str(&format!(" {}", param));
+                }
+                let cons: Vec<_> = cons.iter().map(|cons| self.data_cons(&cons.node)).collect();
+                let flat = format!("{} = {}", head, cons.join(" | "));
+                if self.fits(&flat) {
+                    self.push(&flat);
+                    return;
+                }
+                self.push(&head);
+                for (ix, cons) in cons.iter().enumerate() {
+                    self.newline(base + INDENT);
+                    self.push(if ix == 0 { "= " } else { "| " });
+                    self.push(cons);
+                }
+            }
+        }
+    }
+}
+
+fn is_symbol(word: &str) -> bool {
+    word.chars()
+        .all(|c| "~!@#$%^&*+=<>,:?`/|;[]{}-".contains(c))
+}
+
+// Commas go straight after words, unless they'd be read as one operator.
+fn glued(before: &str, next: Option<&str>) -> bool {
+    next == Some(",") && before.ends_with(|c: char| !is_symbol(&c.to_string()) && c != ' ')
+}
+
+fn is_operator<T>(atom: &Spanned<Atom<'_, T>>) -> bool {
+    atom_word(atom).is_some_and(is_symbol)
+}
+
+fn atom_word<'a, T>(atom: &Spanned<Atom<'a, T>>) -> Option<&'a str> {
+    match atom.node {
+        Atom::Ident(id) => Some(id.0),
+        _ => None,
+    }
+}
+
+fn sign_word<'a>(sign: &Spanned<Sign<'a>>) -> Option<&'a str> {
+    match sign.node {
+        Sign::Word(id) => Some(id.0),
+        Sign::Patn(_) => None,
+    }
+}
+
+impl Layout for Spanned<Expr<'_>> {
+    fn flat(&self, p: &Printer<'_>) -> Option<String> {
+        match self.node {
+            Expr::Flat(atoms) => p.flat_atoms(atoms),
+            Expr::Func(patn, body) => Some(format!("fun {} = {}", patn.flat(p)?, body.flat(p)?)),
+            // Arms written on lines of their own are kept that way.
+            Expr::Match(expr, _) if p.source[expr.span.end..self.span.end].contains('\n') => None,
+            Expr::Match(expr, arms) => {
+                let mut text = format!("match {}", expr.flat(p)?);
+                for (patn, body) in arms.iter() {
+                    text.push_str(&format!(" | {} = {}", patn.flat(p)?, body.flat(p)?));
+                }
+                text.push_str(" end");
+                Some(text)
+            }
+            Expr::Scoped(..) => None,
+        }
+    }
+
+    fn print(&self, p: &mut Printer<'_>) {
+        let base = p.column();
+        match self.node {
+            Expr::Flat(atoms) => p.atoms(atoms),
+            Expr::Func(patn, body) => {
+                p.push("fun ");
+                p.print(patn);
+                p.push(" =");
+                p.body(body, base);
+            }
+            Expr::Match(expr, arms) => {
+                p.push("match ");
+                p.print(expr);
+                for (patn, body) in arms.iter() {
+                    p.newline(base);
+                    p.push("| ");
+                    p.print(patn);
+                    p.push(" =");
+                    p.body(body, base);
+                }
+                p.newline(base);
+                p.push("end");
+            }
+            Expr::Scoped(decls, body) => p.scoped(decls, body),
+        }
+    }
+}
+
+impl Layout for Spanned<Patn<'_>> {
+    fn flat(&self, p: &Printer<'_>) -> Option<String> {
+        match self.node {
+            Patn::Flat(atoms) => p.flat_atoms(atoms),
+            Patn::Annotated(patn, ty) => Some(format!("{} : {}", patn.flat(p)?, ty.flat(p)?)),
+            Patn::Scoped(..) => None,
+        }
+    }
+
+    fn print(&self, p: &mut Printer<'_>) {
+        match self.node {
+            Patn::Flat(atoms) => p.atoms(atoms),
+            Patn::Annotated(patn, ty) => {
+                p.print(patn);
+                p.push(" : ");
+                p.print(ty);
+            }
+            Patn::Scoped(decls, patn) => p.scoped(decls, patn),
+        }
+    }
+}
+
+impl Layout for Spanned<Type<'_>> {
+    fn flat(&self, p: &Printer<'_>) -> Option<String> {
+        match self.node {
+            Type::Flat(atoms) => p.flat_atoms(atoms),
+        }
+    }
+
+    fn print(&self, p: &mut Printer<'_>) {
+        match self.node {
+            Type::Flat(atoms) => p.atoms(atoms),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::parser::SequenceParser;
+    use crate::refs::FileRef;
+
+    use bumpalo::Bump;
+
+    const SOURCE: &str = r#"
+-- Lists, and what can be done with them.
+data List a = con Nil | con Cons a, (List a)
+data Side = con Left
+    --| The other one.
+    | con Right
+infixr 5 _ ++ _
+infixr 1 _ --> _ // not a comment
+
+
+--| How many things a list has.
+--| None for `Nil`.
+def len (Nil) = 0
+def len (Cons _, xs) = 1 + len xs -- counts them
+def (Nil) ++ (ys) = ys
+def (Cons x, xs) ++ (ys) = Cons x, xs ++ ys
+def (a) --> (b) = b
+def half (n) = n / 2 /* halved */ + 0
+mod Shapes = mod
+        /* Shapes /* of all */ kinds. */
+        data Shape = con Circle (Num) | con Rect (Num) by (Num) | con Polygon (List Num) with (Num)
+    def area (s : Shape) : Num = match s | Circle r = 3.14_159 * r * r | Rect w by h = w * h | Polygon _ with a = a end
+    -- more to come
+end
+use Shapes hiding (_ by _, area)
+let (def y = 2 in Cons y, z) = Cons 2, 5
+def main = match len (Cons "a -- spaced // string", Nil) | 1 = (def n = 1_000 in n + len (Cons 1, Nil) ++ (Cons 2, Nil)) | _ = (fun x = x) 0 end
+def kept = 1 + -- left alone
+        2
+"#;
+
+    fn parse<'a>(arena: &'a Bump, source: &str) -> (Vec<Spanned<Decl<'a>>>, Trivia) {
+        let (stripped, comments) = trivia::strip(FileRef::from(0), source).unwrap();
+        let decls = SequenceParser::new()
+            .parse(arena, FileRef::from(0), &stripped)
+            .unwrap();
+        let trivia = trivia::attach(source, comments, &decls);
+        (decls, trivia)
+    }
+
+    fn comments<'a>(source: &'a str, trivia: &Trivia) -> Vec<&'a str> {
+        let comments = trivia.comments.iter();
+        comments.map(|comment| comment.text(source)).collect()
+    }
+
+    // Formats it, checking the result means the same and is formatted already.
+    fn formatted(source: &str) -> String {
+        let arena = Bump::new();
+        let (decls, trivia) = parse(&arena, source);
+        let formatted = format(source, &decls, &trivia);
+        let (reparsed, retrivia) = parse(&arena, &formatted);
+        assert!(same_structure(&decls, &reparsed), "{}", formatted);
+        assert_eq!(format(&formatted, &reparsed, &retrivia), formatted);
+        formatted
+    }
+
+    #[test]
+    fn formatting_keeps_structure() {
+        let arena = Bump::new();
+        let (decls, trivia) = parse(&arena, SOURCE);
+        let formatted = formatted(SOURCE);
+        assert_eq!(trivia.comments.len(), 10);
+        let len = decls
+            .iter()
+            .find(|decl| decl.to_string().starts_with("def len"));
+        let doc = trivia.doc(SOURCE, len.unwrap().span);
+        assert_eq!(doc.unwrap(), "How many things a list has.\nNone for `Nil`.");
+        let side = decls.iter().find_map(|decl| match decl.node {
+            Decl::Data(id, _, cons) if id.0 == "Side" => Some(cons),
+            _ => None,
+        });
+        let side = side.unwrap();
+        assert_eq!(trivia.cons_doc(SOURCE, side[0].span), None);
+        let doc = trivia.cons_doc(SOURCE, side[1].span);
+        assert_eq!(doc.unwrap(), "The other one.");
+        assert!(formatted.lines().all(|line| line.len() <= WIDTH));
+        assert!(formatted.contains("3.14_159") && formatted.contains("def (a) --> (b) = b"));
+        assert!(formatted.contains("data List a = con Nil | con Cons a, (List a)\n"));
+    }
+
+    #[test]
+    fn formatting_twice_changes_nothing() {
+        let once = formatted(SOURCE);
+        assert_eq!(formatted(&once), once);
+    }
+
+    #[test]
+    fn comments_survive() {
+        let arena = Bump::new();
+        let (_, trivia) = parse(&arena, SOURCE);
+        let formatted = formatted(SOURCE);
+        let (_, retrivia) = parse(&arena, &formatted);
+        assert_eq!(comments(SOURCE, &trivia), comments(&formatted, &retrivia));
+        assert!(formatted.contains("def len (Cons _, xs) = 1 + len xs -- counts them\n"));
+        assert!(formatted.contains("def kept = 1 + -- left alone\n        2\n"));
+    }
+
+    #[test]
+    fn strings_keep_their_quotes() {
+        let source = r#"def said = Cons "say \"hi\"", (Cons "", Nil)"#;
+        assert_eq!(formatted(source), format!("{}\n", source));
+        let source = r#"let   quoted="\"" "#;
+        assert_eq!(formatted(source), "let quoted = \"\\\"\"\n");
+    }
+
+    #[test]
+    fn match_arms_keep_their_lines() {
+        let source = "
+def fib (n) =
+  match n
+  | 0 = 0
+  | n = fib (n - 1) + fib (n - 2)
+  end
+def short (n) = match n | 0 = 0 | _ = 1 end
+";
+        assert_eq!(formatted(source), source.trim_start());
+    }
+}
@@ -0,0 +1,265 @@
+use super::*;
+use crate::phases::ParseError;
+use crate::refs::FileRef;
+
+use std::collections::HashMap;
+
+/*
+    Comments are taken out of the text before it's parsed, by writing
+    spaces over them so that everything keeps its offsets. A line comment
+    starts with `//`, or with `--` where that isn't part of a longer
+    operator like `-->`, and block comments go from `/*` to `*/`, and
+    can have other block comments inside them. Operators can have a `/`
+    in them, like `/` itself, but not followed by another `/` or a `*`,
+    since those start a comment wherever they are. Doc comments start with
+    `--|`, and say what the declaration after them is for.
+
+    Each comment is then attached to a declaration next to it: the one
+    after it, unless it's on the same line as the end of the one before,
+    or nothing comes after it in the module. Comments anywhere else, like
+    inside an expression, are left with the declaration around them.
+*/
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CommentKind {
+    Line,
+    Block,
+    Doc,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Comment {
+    pub kind: CommentKind,
+    pub span: Span,
+}
+
+impl Comment {
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        source[self.span.start..self.span.end].trim_end()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Trivia {
+    pub comments: Vec<Comment>,
+    // By where the declarations start.
+    pub leading: HashMap<usize, Vec<Comment>>,
+    // By where the declarations end.
+    pub trailing: HashMap<usize, Vec<Comment>>,
+    // Comments inside declarations, or in modules without any.
+    pub inner: Vec<Comment>,
+}
+
+impl Trivia {
+    pub fn leading(&self, decl: Span) -> &[Comment] {
+        self.leading.get(&decl.start).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn trailing(&self, decl: Span) -> &[Comment] {
+        self.trailing.get(&decl.end).map_or(&[], Vec::as_slice)
+    }
+
+    // What the doc comments just above a declaration say, without their `--|`.
+    pub fn doc(&self, source: &str, decl: Span) -> Option<String> {
+        let docs = self.leading(decl).iter().rev();
+        doc_lines(
+            source,
+            docs.take_while(|comment| comment.kind == CommentKind::Doc),
+        )
+    }
+
+    // Constructors in a `data` find theirs inside it, with nothing
+    // but the `=` or `|` before the constructor after them.
+    pub fn cons_doc(&self, source: &str, cons: Span) -> Option<String> {
+        let mut next = cons.start;
+        let docs = self.inner.iter().rev();
+        let docs = docs.skip_while(|comment| comment.span.end > cons.start);
+        doc_lines(
+            source,
+            docs.take_while(|comment| {
+                let between = &source[comment.span.end..next];
+                next = comment.span.start;
+                comment.kind == CommentKind::Doc
+                    && between
+                        .chars()
+                        .all(|c| c.is_whitespace() || c == '=' || c == '|')
+            }),
+        )
+    }
+
+    pub fn has_inner(&self, span: Span) -> bool {
+        self.inner
+            .iter()
+            .any(|comment| span.start <= comment.span.start && comment.span.end <= span.end)
+    }
+}
+
+// Takes the comments from the last up.
+fn doc_lines<'a>(source: &str, docs: impl Iterator<Item = &'a Comment>) -> Option<String> {
+    let mut lines: Vec<_> = docs
+        .map(|comment| {
+            let text = &comment.text(source)["--|".len()..];
+            text.strip_prefix(' ').unwrap_or(text)
+        })
+        .collect();
+    if lines.is_empty() {
+        return None;
+    }
+    lines.reverse();
+    Some(lines.join("\n"))
+}
+
+fn is_symbol(c: u8) -> bool {
+    b"~!@#$%^&*+=<>,:?`/|;[]{}-".contains(&c)
+}
+
+// Gives the text with its comments blanked out, and the comments.
+pub fn strip(file: FileRef, text: &str) -> Result<(String, Vec<Comment>), ParseError> {
+    let bytes = text.as_bytes();
+    let mut stripped = bytes.to_vec();
+    let mut comments = Vec::new();
+    let mut ix = 0;
+    while ix < bytes.len() {
+        let rest = &bytes[ix..];
+        let start = ix;
+        let kind = if rest.starts_with(b"//") {
+            CommentKind::Line
+        } else if rest.starts_with(b"/*") {
+            CommentKind::Block
+        } else if rest.starts_with(b"--") && (ix == 0 || !is_symbol(bytes[ix - 1])) {
+            let len = rest.iter().take_while(|&&c| is_symbol(c)).count();
+            match &rest[..len] {
+                b"--|" => CommentKind::Doc,
+                run if run.iter().all(|&c| c == b'-') => CommentKind::Line,
+                _ => {
+                    ix += len;
+                    continue;
+                }
+            }
+        } else if rest[0] == b'"' {
+            // Strings end at the next quote, the same as in the lexer,
+            // skipping whatever comes straight after a backslash.
+            let mut end = 1;
+            while end < rest.len() && rest[end] != b'"' {
+                end += if rest[end] == b'\\' { 2 } else { 1 };
+            }
+            ix += rest.len().min(end + 1);
+            continue;
+        } else {
+            ix += 1;
+            continue;
+        };
+
+        match kind {
+            CommentKind::Line | CommentKind::Doc => {
+                ix += rest.iter().position(|&c| c == b'\n').unwrap_or(rest.len());
+            }
+            CommentKind::Block => {
+                let mut depth = 0;
+                loop {
+                    if bytes[ix..].starts_with(b"/*") {
+                        depth += 1;
+                        ix += 2;
+                    } else if bytes[ix..].starts_with(b"*/") {
+                        depth -= 1;
+                        ix += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else if ix < bytes.len() {
+                        ix += 1;
+                    } else {
+                        return Err(ParseError::UnrecognizedEOF {
+                            location: text.len(),
+                            expected: vec!["\"*/\"".to_owned()],
+                        });
+                    }
+                }
+            }
+        }
+
+        for c in stripped[start..ix].iter_mut() {
+            if *c != b'\n' {
+                *c = b' ';
+            }
+        }
+        let span = Span {
+            file,
+            start,
+            end: ix,
+        };
+        comments.push(Comment { kind, span });
+    }
+
+    // Only whole characters were replaced, so it's still UTF-8.
+    Ok((String::from_utf8(stripped).unwrap(), comments))
+}
+
+pub fn attach(source: &str, comments: Vec<Comment>, decls: &[Spanned<Decl<'_>>]) -> Trivia {
+    let mut trivia = Trivia::default();
+    for &comment in comments.iter() {
+        attach_comment(&mut trivia, source, comment, decls);
+    }
+    trivia.comments = comments;
+    trivia
+}
+
+fn attach_comment(trivia: &mut Trivia, source: &str, comment: Comment, decls: &[Spanned<Decl>]) {
+    let span = comment.span;
+    let around = decls
+        .iter()
+        .find(|decl| decl.span.start <= span.start && span.end <= decl.span.end);
+    if let Some(decl) = around {
+        match decl.node {
+            Decl::Mod(_, modl) if modl.span.start <= span.start => {
+                if let Modl::ModExp(decls) = modl.node {
+                    return attach_comment(trivia, source, comment, decls);
+                }
+            }
+            _ => {}
+        }
+        trivia.inner.push(comment);
+        return;
+    }
+
+    let before = decls.iter().rev().find(|decl| decl.span.end <= span.start);
+    let after = decls.iter().find(|decl| span.end <= decl.span.start);
+    match (before, after) {
+        (Some(before), _) if !source[before.span.end..span.start].contains('\n') => trivia
+            .trailing
+            .entry(before.span.end)
+            .or_default()
+            .push(comment),
+        (_, Some(after)) => trivia
+            .leading
+            .entry(after.span.start)
+            .or_default()
+            .push(comment),
+        (Some(before), None) => trivia
+            .trailing
+            .entry(before.span.end)
+            .or_default()
+            .push(comment),
+        (None, None) => trivia.inner.push(comment),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stripped(text: &str) -> (String, Vec<&str>) {
+        let (stripped, comments) = strip(FileRef::from(0), text).unwrap();
+        let comments = comments.iter().map(|comment| comment.text(text));
+        (stripped, comments.collect())
+    }
+
+    #[test]
+    fn escaped_quotes_keep_strings_going() {
+        for &text in [r#"let s = "a\" -- x""#, r#"let s = "a\" /* x""#].iter() {
+            assert_eq!(stripped(text), (text.to_owned(), vec![]));
+        }
+        let (text, comments) = stripped(r#"let s = "a\\" -- x"#);
+        assert_eq!(text.trim_end(), r#"let s = "a\\""#);
+        assert_eq!(comments, ["-- x"]);
+    }
+}
@@ -0,0 +1,235 @@
+use crate::ast::{Assoc, Fixity};
+use crate::ctx::Context;
+use crate::ir::types::TypeCon;
+use crate::ir::{self, decision, Expr, Patn, Sign};
+use crate::storage::*;
+
+use std::fmt;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Prim {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Prim {
+    pub const ALL: [Prim; 11] = [
+        Prim::Add,
+        Prim::Sub,
+        Prim::Mul,
+        Prim::Div,
+        Prim::Rem,
+        Prim::Eq,
+        Prim::Ne,
+        Prim::Lt,
+        Prim::Le,
+        Prim::Gt,
+        Prim::Ge,
+    ];
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Prim::Add => "+",
+            Prim::Sub => "-",
+            Prim::Mul => "*",
+            Prim::Div => "/",
+            Prim::Rem => "%",
+            Prim::Eq => "==",
+            Prim::Ne => "!=",
+            Prim::Lt => "<",
+            Prim::Le => "<=",
+            Prim::Gt => ">",
+            Prim::Ge => ">=",
+        }
+    }
+
+    pub fn fixity(self) -> Fixity {
+        let (assoc, level) = match self {
+            Prim::Add | Prim::Sub => (Assoc::Left, 6),
+            Prim::Mul | Prim::Div | Prim::Rem => (Assoc::Left, 7),
+            _ => (Assoc::Non, 4),
+        };
+        Fixity { assoc, level }
+    }
+}
+
+/*
+    Both the interpreter and the machine apply primitives the same way,
+    to whatever their values look like through `Operand`: arithmetic on
+    numbers, ordering of numbers or strings, and equality of anything
+    but functions, which no primitive takes.
+*/
+pub enum View<'a, V, T> {
+    Unit,
+    Number(f64),
+    String(&'a str),
+    Data(T, &'a [V]),
+    Func,
+}
+
+pub trait Operand: Sized {
+    type Cons: PartialEq;
+    fn view(&self) -> View<'_, Self, Self::Cons>;
+}
+
+pub enum Outcome {
+    Number(f64),
+    Truth(bool),
+}
+
+impl Prim {
+    // Nothing if the primitive doesn't take these operands.
+    pub fn apply<V: Operand>(self, lhs: &V, rhs: &V) -> Option<Outcome> {
+        use std::cmp::Ordering;
+
+        let ordering = match (lhs.view(), rhs.view()) {
+            (View::Number(a), View::Number(b)) => a.partial_cmp(&b),
+            (View::String(a), View::String(b)) => Some(a.cmp(b)),
+            _ => None,
+        };
+        let number = match (lhs.view(), rhs.view()) {
+            (View::Number(a), View::Number(b)) => Some((a, b)),
+            _ => None,
+        };
+        Some(match (self, number) {
+            (Prim::Add, Some((a, b))) => Outcome::Number(a + b),
+            (Prim::Sub, Some((a, b))) => Outcome::Number(a - b),
+            (Prim::Mul, Some((a, b))) => Outcome::Number(a * b),
+            (Prim::Div, Some((a, b))) => Outcome::Number(a / b),
+            (Prim::Rem, Some((a, b))) => Outcome::Number(a % b),
+            (Prim::Eq, _) => Outcome::Truth(equal(lhs, rhs)?),
+            (Prim::Ne, _) => Outcome::Truth(!equal(lhs, rhs)?),
+            (Prim::Lt, _) => Outcome::Truth(ordering? == Ordering::Less),
+            (Prim::Le, _) => Outcome::Truth(ordering? != Ordering::Greater),
+            (Prim::Gt, _) => Outcome::Truth(ordering? == Ordering::Greater),
+            (Prim::Ge, _) => Outcome::Truth(ordering? != Ordering::Less),
+            _ => return None,
+        })
+    }
+
+    pub fn invalid_operands(self, lhs: impl fmt::Display, rhs: impl fmt::Display) -> String {
+        format!("Cannot apply {} to {} and {}", self.symbol(), lhs, rhs)
+    }
+}
+
+fn equal<V: Operand>(lhs: &V, rhs: &V) -> Option<bool> {
+    Some(match (lhs.view(), rhs.view()) {
+        (View::Unit, View::Unit) => true,
+        (View::Number(a), View::Number(b)) => a == b,
+        (View::String(a), View::String(b)) => a == b,
+        (View::Data(a, xs), View::Data(b, ys)) => {
+            if a != b || xs.len() != ys.len() {
+                return Some(false);
+            }
+            for (x, y) in xs.iter().zip(ys.iter()) {
+                if !equal(x, y)? {
+                    return Some(false);
+                }
+            }
+            true
+        }
+        (View::Func, _) | (_, View::Func) => return None,
+        _ => false,
+    })
+}
+
+pub const CONSTRUCTORS: [&str; 2] = ["True", "False"];
+
+// Every primitive is an ordinary infix declaration in the global
+// module, whose body applies the primitive to its two arguments.
+// Comparisons give a `Bool`, declared like any other data type.
+pub fn declare_builtins(ctx: &Context<'_>) {
+    let mut refs = ctx.refs.borrow_mut();
+    let mut ir = ctx.ir.borrow_mut();
+    let mut names = ctx.names.borrow_mut();
+
+    let lhs = names.make_ident("a");
+    let rhs = names.make_ident("b");
+
+    let mut decls = Vec::new();
+    for &prim in Prim::ALL.iter() {
+        let lhs_ref = refs.patn.make_ref();
+        let rhs_ref = refs.patn.make_ref();
+        ir.patn.set(lhs_ref, Patn::Binding(vec![lhs]));
+        ir.patn.set(rhs_ref, Patn::Binding(vec![rhs]));
+
+        let body = refs.expr.make_ref();
+        ir.expr.set(
+            body,
+            Expr::Prim(prim, vec![Expr::Local(lhs), Expr::Local(rhs)]),
+        );
+
+        let sig = vec![
+            Sign::Patn(lhs_ref),
+            Sign::Word(names.make_ident(prim.symbol())),
+            Sign::Patn(rhs_ref),
+        ];
+        let decl_ref = refs.decl.make_ref();
+        decls.push((
+            decl_ref,
+            prim.fixity(),
+            sig.iter().cloned().map(Sign::forget).collect::<Vec<_>>(),
+        ));
+        let decl = ir::Decl {
+            sig,
+            body,
+            result: None,
+            type_vars: Vec::new(),
+        };
+        ir.decl.set(decl_ref, decl);
+        let tree = decision::compile_clauses(&ir, decl_ref);
+        ir.decisions.set(decl_ref, tree);
+    }
+
+    let bool_ref = refs.data.make_ref();
+    let mut cons = Vec::new();
+    for &name in CONSTRUCTORS.iter() {
+        let sig = vec![Sign::Word(names.make_ident(name))];
+        let cons_ref = refs.cons.make_ref();
+        cons.push((cons_ref, sig.clone()));
+        ir.cons.set(
+            cons_ref,
+            ir::Cons {
+                sig,
+                data: bool_ref,
+                fields: Some(Vec::new()),
+            },
+        );
+    }
+    let bool_data = ir::Data {
+        name: Some(names.make_ident("Bool")),
+        params: Vec::new(),
+        cons: cons.iter().map(|&(cons_ref, _)| cons_ref).collect(),
+    };
+    let types = [
+        ("Num", TypeCon::Number),
+        ("Str", TypeCon::String),
+        ("Bool", TypeCon::Data(bool_ref)),
+    ];
+    ir.data.set(bool_ref, bool_data);
+
+    let global = ir.modl.get_mut(ctx.global_modl()).unwrap();
+    let record = global.as_record_mut().unwrap();
+    for (decl_ref, fixity, sig) in decls {
+        record.symbols.new_fixity(sig.clone(), fixity);
+        record.symbols.new_decl(decl_ref, sig);
+        record.decls.push(decl_ref);
+    }
+    for (cons_ref, sig) in cons {
+        record.symbols.new_cons(cons_ref, sig);
+        record.cons.push(cons_ref);
+    }
+    record.data.push(bool_ref);
+    for &(name, con) in types.iter() {
+        record.symbols.new_type(names.make_ident(name), con);
+    }
+}
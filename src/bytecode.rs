@@ -0,0 +1,125 @@
+pub mod compile;
+pub mod file;
+
+use crate::builtin::Prim;
+use crate::ctx::Context;
+use crate::error::{self, Diagnostic};
+use crate::id::Ident;
+use crate::ir::{Literal, Sign};
+use crate::span::Span;
+use crate::storage::*;
+
+/*
+    Code runs on a stack of values. A call's frame begins with
+    a slot for each argument, then one for each local it binds
+    or value it needs to keep, and the values being worked on
+    sit above those. Functions are curried, so a closure's code
+    only runs once it has been given all of its arguments.
+
+    Operands are indices into the program's tables, or into the
+    code's own instructions and jump tables for targets.
+*/
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Op {
+    Const(u32),
+    Load(u32),
+    Store(u32),
+    // One of the values the running closure captured.
+    Capture(u32),
+    // The scope holding the running declaration.
+    ThisScope,
+    // A global declaration, whose value is made the first time it's used
+    // if it takes no arguments.
+    Global(u32),
+    // Takes a scope, and pushes one of its members in the same way.
+    Member(u32),
+    // Takes the given number of values as the closure's captures.
+    Closure(u32, u32),
+    // Takes the given number of values as the scope's captures.
+    Scope(u32, u32),
+    Data(u32, u32),
+    // Takes data, and pushes one of its fields.
+    Field(u32),
+    // Takes data, and jumps to wherever the table says for its constructor.
+    Switch(u32),
+    // Takes a value, and jumps if it equals the constant.
+    JumpIf(u32, u32),
+    Jump(u32),
+    // Takes a function and the arguments to give it.
+    Call(u32),
+    // Calls a function in place of the running one.
+    TailCall(u32),
+    Return,
+    Prim(Prim),
+    Hole,
+    // Fails when no clause matches the arguments,
+    // or no arm matches the value in a slot.
+    NoClause,
+    NoMatch(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Table {
+    pub cases: Vec<(u32, u32)>,
+    pub default: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Code {
+    pub arity: u32,
+    pub slots: u32,
+    pub ops: Vec<Op>,
+    pub tables: Vec<Table>,
+    // Where the code came from, for pointing at errors.
+    pub span: Option<Span>,
+}
+
+// A declaration within a scope, and where the scope keeps its value.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Member {
+    pub code: u32,
+    pub slot: u32,
+}
+
+// A file's closed definitions, which can be run by name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub name: String,
+    pub entries: Vec<(Ident, u32)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Program {
+    pub consts: Vec<Literal>,
+    pub codes: Vec<Code>,
+    pub globals: Vec<u32>,
+    pub members: Vec<Member>,
+    // How many members each scope has.
+    pub scopes: Vec<u32>,
+    // The signature of each constructor, for showing data.
+    pub cons: Vec<Vec<Sign>>,
+    // What comparisons give: `True`, then `False`.
+    pub truth: (u32, u32),
+    pub modules: Vec<Module>,
+}
+
+impl Program {
+    // Finds the global a closed definition was compiled to, like `eval::find_entry`.
+    pub fn find_entry(&self, ctx: &Context<'_>, name: &str) -> error::Result<u32> {
+        let names = ctx.names.borrow();
+        let entries = self.modules.iter().flat_map(|module| module.entries.iter());
+        if let Some(id) = names.get_ident(name) {
+            if let Some(&(_, global)) = entries.clone().find(|&&(entry, _)| entry == id) {
+                return Ok(global);
+            }
+        }
+
+        let defined = entries.map(|&(id, _)| names.get(id).unwrap());
+        let similar = error::similar_names(name, defined);
+        Err(Diagnostic::new(
+            error::UndefinedEntryPoint,
+            format!("No definition named {} to evaluate", name),
+        )
+        .with_suggestions(&similar))?
+    }
+}
@@ -0,0 +1,534 @@
+use crate::bytecode::{Code, Member, Module, Op, Program, Table};
+use crate::ctx::Context;
+use crate::eval;
+use crate::id::Ident;
+use crate::ir::decision::{self, Case, DecisionTree, Node, NodeId};
+use crate::ir::{Expr, IrStorage, Literal, Modl, Patn, Sign};
+use crate::refs::*;
+use crate::storage::*;
+
+use std::collections::{HashMap, HashSet};
+
+// What a slot or capture holds: a local, or a scope for its members.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Name {
+    Local(Ident),
+    Scope(ModlRef),
+}
+
+// Where code finds the names it doesn't bind itself.
+enum Env {
+    Global,
+    Lambda(Vec<Name>),
+    // Every member of a scope shares the scope's captures.
+    Member(ModlRef),
+}
+
+struct Level {
+    code: Code,
+    // What each slot holds, if it's there for a name.
+    slots: Vec<Option<Name>>,
+    env: Env,
+}
+
+/*
+    Only what the files' closed definitions can reach is compiled.
+    Global declarations are queued up the first time they're used,
+    while a scoped module's members are all compiled wherever the
+    module is entered, since that's where their captures are found.
+*/
+struct Compiler<'ir> {
+    ir: &'ir IrStorage,
+    scopes: HashMap<DeclRef, Option<ModlRef>>,
+    // Clauses other than the first of their function.
+    later: HashSet<DeclRef>,
+    program: Program,
+    globals: HashMap<DeclRef, u32>,
+    pending: Vec<(DeclRef, u32)>,
+    members: HashMap<DeclRef, u32>,
+    entered: HashMap<ModlRef, u32>,
+    captures: HashMap<ModlRef, Vec<Name>>,
+    cons: HashMap<ConsRef, u32>,
+    consts: HashMap<Literal, u32>,
+    levels: Vec<Level>,
+}
+
+pub fn compile(ctx: &Context<'_>, ir: &IrStorage, modls: &[ModlRef]) -> Program {
+    let later = (&ir.clauses)
+        .into_iter()
+        .flat_map(|(_, clauses)| clauses[1..].iter().copied())
+        .collect();
+    let mut compiler = Compiler {
+        ir,
+        scopes: ir.scopes(),
+        later,
+        program: Program::default(),
+        globals: HashMap::new(),
+        pending: Vec::new(),
+        members: HashMap::new(),
+        entered: HashMap::new(),
+        captures: HashMap::new(),
+        cons: HashMap::new(),
+        consts: HashMap::new(),
+        levels: Vec::new(),
+    };
+
+    let (true_ref, false_ref) = eval::truth(ctx, ir);
+    compiler.program.truth = (compiler.cons(true_ref), compiler.cons(false_ref));
+    for &modl_ref in modls.iter() {
+        let record = match ir.modl.get(modl_ref) {
+            Some(Modl::Record(record)) => record,
+            _ => continue,
+        };
+        let mut closed: Vec<_> = record
+            .symbols
+            .iter_decl_signs()
+            .filter_map(|(sig, decls)| match sig {
+                [Sign::Word(id)] => Some((decls[0], *id)),
+                _ => None,
+            })
+            .collect();
+        closed.sort_by_key(|&(decl_ref, _)| usize::from(decl_ref));
+        let entries = closed
+            .into_iter()
+            .map(|(decl_ref, id)| (id, compiler.global(decl_ref)))
+            .collect();
+        compiler.program.modules.push(Module {
+            name: record.name.clone(),
+            entries,
+        });
+    }
+    while let Some((decl_ref, code)) = compiler.pending.pop() {
+        let compiled = compiler.decl(decl_ref, Env::Global);
+        compiler.program.codes[code as usize] = compiled;
+    }
+    compiler.program
+}
+
+impl<'ir> Compiler<'ir> {
+    fn level(&mut self) -> &mut Level {
+        self.levels.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let ops = &mut self.level().code.ops;
+        ops.push(op);
+        ops.len() - 1
+    }
+
+    fn pc(&mut self) -> u32 {
+        self.level().code.ops.len() as u32
+    }
+
+    fn patch(&mut self, at: usize, pc: u32) {
+        match &mut self.level().code.ops[at] {
+            Op::Jump(target) | Op::JumpIf(_, target) => *target = pc,
+            _ => unreachable!("Only jumps are patched."),
+        }
+    }
+
+    fn bind(&mut self, name: Option<Name>) -> u32 {
+        let level = self.level();
+        level.slots.push(name);
+        let count = level.slots.len() as u32;
+        level.code.slots = level.code.slots.max(count);
+        count - 1
+    }
+
+    fn mark(&mut self) -> usize {
+        self.level().slots.len()
+    }
+
+    fn unbind(&mut self, mark: usize) {
+        self.level().slots.truncate(mark);
+    }
+
+    fn reserve(&mut self) -> u32 {
+        self.program.codes.push(Code::default());
+        self.program.codes.len() as u32 - 1
+    }
+
+    fn global(&mut self, decl_ref: DeclRef) -> u32 {
+        if let Some(&global) = self.globals.get(&decl_ref) {
+            return global;
+        }
+        let code = self.reserve();
+        let global = self.program.globals.len() as u32;
+        self.program.globals.push(code);
+        self.globals.insert(decl_ref, global);
+        self.pending.push((decl_ref, code));
+        global
+    }
+
+    fn cons(&mut self, cons_ref: ConsRef) -> u32 {
+        let (ir, program) = (self.ir, &mut self.program);
+        *self.cons.entry(cons_ref).or_insert_with(|| {
+            program
+                .cons
+                .push(ir.cons.get(cons_ref).unwrap().sig.clone());
+            program.cons.len() as u32 - 1
+        })
+    }
+
+    fn constant(&mut self, lit: &Literal) -> u32 {
+        let program = &mut self.program;
+        *self.consts.entry(lit.clone()).or_insert_with(|| {
+            program.consts.push(lit.clone());
+            program.consts.len() as u32 - 1
+        })
+    }
+
+    fn decl(&mut self, decl_ref: DeclRef, env: Env) -> Code {
+        let ir = self.ir;
+        let decl = ir.decl.get(decl_ref).unwrap();
+        // A `let` is matched where it's used, so it takes no arguments.
+        let is_let = decl.is_let();
+        let arity = match is_let {
+            true => 0,
+            false => decl
+                .sig
+                .iter()
+                .filter(|sign| matches!(sign, Sign::Patn(_)))
+                .count(),
+        };
+
+        self.levels.push(Level {
+            code: Code {
+                arity: arity as u32,
+                slots: arity as u32,
+                span: ir.spans.decl.get(decl_ref).copied(),
+                ..Code::default()
+            },
+            slots: vec![None; arity],
+            env,
+        });
+        match ir.decisions.get(decl_ref) {
+            Some(tree) if !is_let => {
+                let roots: Vec<_> = (0..arity as u32).collect();
+                let clauses = ir.clauses(decl_ref);
+                self.tree(tree, &roots, Op::NoClause, &mut |this, arm| {
+                    let clause = ir.decl.get(clauses[arm]).unwrap();
+                    this.expr(ir.expr.get(clause.body).unwrap(), true);
+                });
+            }
+            _ => self.expr(ir.expr.get(decl.body).unwrap(), true),
+        }
+        self.levels.pop().unwrap().code
+    }
+
+    // Every node is entered with the same slots bound, so a node
+    // reached along several branches only needs compiling once.
+    fn tree(
+        &mut self,
+        tree: &DecisionTree,
+        roots: &[u32],
+        fail: Op,
+        arm: &mut dyn FnMut(&mut Self, usize),
+    ) {
+        let mut labels = HashMap::new();
+        self.node(tree, tree.root, roots, fail, arm, &mut labels);
+    }
+
+    fn node(
+        &mut self,
+        tree: &DecisionTree,
+        node: NodeId,
+        roots: &[u32],
+        fail: Op,
+        arm: &mut dyn FnMut(&mut Self, usize),
+        labels: &mut HashMap<NodeId, u32>,
+    ) -> u32 {
+        if let Some(&pc) = labels.get(&node) {
+            return pc;
+        }
+        let pc = self.pc();
+        labels.insert(node, pc);
+
+        match &tree.nodes[node] {
+            Node::Fail => {
+                self.emit(fail);
+            }
+            Node::Leaf {
+                arm: chosen,
+                bindings,
+            } => {
+                let mark = self.mark();
+                for (id, path) in bindings.iter() {
+                    self.path(roots, path);
+                    let slot = self.bind(Some(Name::Local(*id)));
+                    self.emit(Op::Store(slot));
+                }
+                arm(self, *chosen);
+                self.unbind(mark);
+            }
+            Node::Switch {
+                path,
+                cases,
+                default,
+            } => {
+                let mark = self.mark();
+                let slot = self.bind(None);
+                self.path(roots, path);
+                self.emit(Op::Store(slot));
+
+                let cons: Vec<_> = cases
+                    .iter()
+                    .filter_map(|(case, next)| match case {
+                        Case::Cons(cons_ref, _) => Some((*cons_ref, *next)),
+                        Case::Literal(_) => None,
+                    })
+                    .collect();
+                let table = if cons.is_empty() {
+                    None
+                } else {
+                    let tables = &mut self.level().code.tables;
+                    tables.push(Table::default());
+                    let table = tables.len() - 1;
+                    self.emit(Op::Load(slot));
+                    self.emit(Op::Switch(table as u32));
+                    let pc = self.pc();
+                    self.level().code.tables[table].default = pc;
+                    Some(table)
+                };
+
+                let mut jumps = Vec::new();
+                for (case, next) in cases.iter() {
+                    if let Case::Literal(lit) = case {
+                        let lit = self.constant(lit);
+                        self.emit(Op::Load(slot));
+                        jumps.push((self.emit(Op::JumpIf(lit, 0)), *next));
+                    }
+                }
+                jumps.push((self.emit(Op::Jump(0)), *default));
+                self.unbind(mark);
+
+                if let Some(table) = table {
+                    for (cons_ref, next) in cons {
+                        let target = self.node(tree, next, roots, fail, arm, labels);
+                        let cons = self.cons(cons_ref);
+                        self.level().code.tables[table].cases.push((cons, target));
+                    }
+                }
+                for (at, next) in jumps {
+                    let target = self.node(tree, next, roots, fail, arm, labels);
+                    self.patch(at, target);
+                }
+            }
+        }
+        pc
+    }
+
+    fn path(&mut self, roots: &[u32], path: &[usize]) {
+        self.emit(Op::Load(roots[path[0]]));
+        for &field in path[1..].iter() {
+            self.emit(Op::Field(field as u32));
+        }
+    }
+
+    fn load(&mut self, name: Name) {
+        let level = self.levels.last_mut().unwrap();
+        if let Some(slot) = level.slots.iter().rposition(|slot| *slot == Some(name)) {
+            self.emit(Op::Load(slot as u32));
+            return;
+        }
+        let captures = match &mut level.env {
+            Env::Global => unreachable!("Locals are bound when lowered."),
+            Env::Lambda(captures) => captures,
+            Env::Member(scope_ref) if name == Name::Scope(*scope_ref) => {
+                self.emit(Op::ThisScope);
+                return;
+            }
+            Env::Member(scope_ref) => self.captures.get_mut(scope_ref).unwrap(),
+        };
+        let ix = match captures.iter().position(|&captured| captured == name) {
+            Some(ix) => ix,
+            None => {
+                captures.push(name);
+                captures.len() - 1
+            }
+        };
+        self.emit(Op::Capture(ix as u32));
+    }
+
+    fn var(&mut self, decl_ref: DeclRef) {
+        match self.scopes.get(&decl_ref).copied().flatten() {
+            Some(scope_ref) => {
+                self.load(Name::Scope(scope_ref));
+                let member = self.members[&decl_ref];
+                self.emit(Op::Member(member));
+            }
+            None => {
+                let global = self.global(decl_ref);
+                self.emit(Op::Global(global));
+            }
+        }
+    }
+
+    // Makes the scope and keeps it in a slot for the rest of the level.
+    fn enter(&mut self, scope_ref: ModlRef) {
+        let scope = match self.entered.get(&scope_ref) {
+            Some(&scope) => scope,
+            None => self.scope(scope_ref),
+        };
+        let captures = self.captures[&scope_ref].clone();
+        for &name in captures.iter() {
+            self.load(name);
+        }
+        self.emit(Op::Scope(scope, captures.len() as u32));
+        let slot = self.bind(Some(Name::Scope(scope_ref)));
+        self.emit(Op::Store(slot));
+    }
+
+    fn scope(&mut self, scope_ref: ModlRef) -> u32 {
+        let mut members: Vec<_> = self
+            .scopes
+            .iter()
+            .filter(|&(decl_ref, &scope)| {
+                scope == Some(scope_ref) && !self.later.contains(decl_ref)
+            })
+            .map(|(&decl_ref, _)| decl_ref)
+            .collect();
+        members.sort_by_key(|&decl_ref| usize::from(decl_ref));
+
+        let scope = self.program.scopes.len() as u32;
+        self.program.scopes.push(members.len() as u32);
+        self.entered.insert(scope_ref, scope);
+        self.captures.insert(scope_ref, Vec::new());
+
+        // Members can refer to each other, so they all need indices first.
+        let mut codes = Vec::new();
+        for (slot, &decl_ref) in members.iter().enumerate() {
+            let code = self.reserve();
+            let member = self.program.members.len() as u32;
+            self.program.members.push(Member {
+                code,
+                slot: slot as u32,
+            });
+            self.members.insert(decl_ref, member);
+            codes.push(code);
+        }
+        for (decl_ref, code) in members.into_iter().zip(codes) {
+            let compiled = self.decl(decl_ref, Env::Member(scope_ref));
+            self.program.codes[code as usize] = compiled;
+        }
+        scope
+    }
+
+    fn lambda(&mut self, patn: &Patn, body: &Expr) {
+        self.levels.push(Level {
+            code: Code {
+                arity: 1,
+                slots: 1,
+                ..Code::default()
+            },
+            slots: vec![None],
+            env: Env::Lambda(Vec::new()),
+        });
+        let tree = decision::compile(vec![vec![patn]]);
+        self.tree(&tree, &[0], Op::NoMatch(0), &mut |this, _| {
+            this.expr(body, true)
+        });
+
+        let level = self.levels.pop().unwrap();
+        let captures = match level.env {
+            Env::Lambda(captures) => captures,
+            _ => unreachable!("The level was pushed for a lambda."),
+        };
+        self.program.codes.push(level.code);
+        let code = self.program.codes.len() as u32 - 1;
+        for &name in captures.iter() {
+            self.load(name);
+        }
+        self.emit(Op::Closure(code, captures.len() as u32));
+    }
+
+    // Code in tail position returns its value, or calls in place of
+    // the running code, rather than leaving the value on the stack.
+    fn expr(&mut self, expr: &Expr, tail: bool) {
+        match expr {
+            Expr::Hole => {
+                self.emit(Op::Hole);
+                return;
+            }
+            Expr::Literal(lit) => {
+                let lit = self.constant(lit);
+                self.emit(Op::Const(lit));
+            }
+            Expr::Local(id) => self.load(Name::Local(*id)),
+            Expr::Var(decl_ref) => self.var(*decl_ref),
+            Expr::Data(cons_ref, args) => {
+                for arg in args.iter() {
+                    self.expr(arg, false);
+                }
+                let cons = self.cons(*cons_ref);
+                self.emit(Op::Data(cons, args.len() as u32));
+            }
+            Expr::Apply(..) => {
+                // Every argument in a row of applications is passed at once.
+                let mut func = expr;
+                let mut args = Vec::new();
+                loop {
+                    match func {
+                        Expr::Apply(inner, arg) => {
+                            args.push(&**arg);
+                            func = inner;
+                        }
+                        Expr::Spanned(_, inner) if matches!(**inner, Expr::Apply(..)) => {
+                            func = inner
+                        }
+                        _ => break,
+                    }
+                }
+                self.expr(func, false);
+                for arg in args.iter().rev() {
+                    self.expr(arg, false);
+                }
+                let count = args.len() as u32;
+                self.emit(if tail {
+                    Op::TailCall(count)
+                } else {
+                    Op::Call(count)
+                });
+                return;
+            }
+            Expr::Func(patn, body) => self.lambda(patn, body),
+            Expr::Match(scrut, arms, tree) => {
+                self.expr(scrut, false);
+                let mark = self.mark();
+                let slot = self.bind(None);
+                self.emit(Op::Store(slot));
+
+                let mut ends = Vec::new();
+                self.tree(tree, &[slot], Op::NoMatch(slot), &mut |this, arm| {
+                    this.expr(&arms[arm].1, tail);
+                    if !tail {
+                        ends.push(this.emit(Op::Jump(0)));
+                    }
+                });
+                self.unbind(mark);
+                let end = self.pc();
+                for at in ends {
+                    self.patch(at, end);
+                }
+                return;
+            }
+            Expr::Scoped(modl_ref, body) => {
+                let mark = self.mark();
+                self.enter(*modl_ref);
+                self.expr(body, tail);
+                self.unbind(mark);
+                return;
+            }
+            Expr::Prim(prim, args) => {
+                for arg in args.iter() {
+                    self.expr(arg, false);
+                }
+                self.emit(Op::Prim(*prim));
+            }
+            Expr::Spanned(_, expr) => return self.expr(expr, tail),
+        }
+        if tail {
+            self.emit(Op::Return);
+        }
+    }
+}
@@ -0,0 +1,802 @@
+use crate::builtin::Prim;
+use crate::bytecode::{Code, Member, Module, Op, Program, Table};
+use crate::ctx::Context;
+use crate::error::{self, Diagnostic};
+use crate::id::Ident;
+use crate::ir::{Literal, Sign};
+use crate::storage::*;
+
+use std::collections::HashMap;
+use std::fs;
+
+/*
+    A `.fbc` file holds a compiled program, so it can be run without
+    its source. After the magic bytes and the format's version come
+    the strings every name and string literal refers to by index,
+    then the constant pool, the constructors, the module table of
+    definitions to run by name, and the code section. Numbers are
+    written in little-endian order, and each list is preceded by
+    its length.
+
+    Anything can be handed to the reader, so it checks everything the
+    machine relies on before handing the program over; see `validate`.
+*/
+pub const MAGIC: &[u8; 4] = b"FBC\0";
+pub const VERSION: u32 = 1;
+
+pub fn is_bytecode(file_name: &str) -> bool {
+    std::path::Path::new(file_name).extension() == Some("fbc".as_ref())
+}
+
+pub fn save(ctx: &Context<'_>, program: &Program, file_name: &str) -> error::Result<()> {
+    fs::write(file_name, write(ctx, program)).map_err(|err| {
+        Diagnostic::new(
+            error::UnreadableFile,
+            format!("Could not write {}: {}", file_name, err),
+        )
+    })?;
+    Ok(())
+}
+
+pub fn load(ctx: &Context<'_>, file_name: &str) -> error::Result<Program> {
+    let bytes = fs::read(file_name).map_err(|err| {
+        Diagnostic::new(
+            error::UnreadableFile,
+            format!("Could not read {}: {}", file_name, err),
+        )
+    })?;
+    read(ctx, &bytes).map_err(|err| {
+        let diag = Diagnostic::new(
+            error::InvalidBytecode,
+            format!("Could not load {}: {}", file_name, err.message),
+        );
+        Box::new(match err.outdated {
+            true => diag.with_help("Compile it again with `friday build`."),
+            false => diag,
+        })
+    })
+}
+
+#[derive(Default)]
+struct Writer<'a> {
+    bytes: Vec<u8>,
+    strings: Vec<&'a str>,
+    string_ixs: HashMap<&'a str, u32>,
+}
+
+impl<'a> Writer<'a> {
+    fn u8(&mut self, n: u8) {
+        self.bytes.push(n);
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.bytes.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn len(&mut self, len: usize) {
+        self.u32(len as u32);
+    }
+
+    fn string(&mut self, s: &'a str) {
+        let (strings, ix) = (&mut self.strings, self.string_ixs.len() as u32);
+        let ix = *self.string_ixs.entry(s).or_insert_with(|| {
+            strings.push(s);
+            ix
+        });
+        self.u32(ix);
+    }
+}
+
+fn write(ctx: &Context<'_>, program: &Program) -> Vec<u8> {
+    let names = ctx.names.borrow();
+    let mut w = Writer::default();
+
+    w.len(program.consts.len());
+    for lit in program.consts.iter() {
+        match lit {
+            Literal::Unit => w.u8(0),
+            Literal::Number(n) => {
+                w.u8(1);
+                w.bytes.extend_from_slice(&n.to_bits().to_le_bytes());
+            }
+            Literal::String(s) => {
+                w.u8(2);
+                w.string(s);
+            }
+        }
+    }
+
+    w.len(program.cons.len());
+    for sig in program.cons.iter() {
+        w.len(sig.len());
+        for sign in sig.iter() {
+            match sign {
+                Sign::Patn(()) => w.u8(0),
+                Sign::Word(id) => {
+                    w.u8(1);
+                    w.string(names.get(*id).unwrap());
+                }
+            }
+        }
+    }
+    w.u32(program.truth.0);
+    w.u32(program.truth.1);
+
+    w.len(program.modules.len());
+    for module in program.modules.iter() {
+        w.string(&module.name);
+        w.len(module.entries.len());
+        for &(id, global) in module.entries.iter() {
+            w.string(names.get(id).unwrap());
+            w.u32(global);
+        }
+    }
+
+    w.len(program.globals.len());
+    for &code in program.globals.iter() {
+        w.u32(code);
+    }
+    w.len(program.members.len());
+    for member in program.members.iter() {
+        w.u32(member.code);
+        w.u32(member.slot);
+    }
+    w.len(program.scopes.len());
+    for &members in program.scopes.iter() {
+        w.u32(members);
+    }
+
+    w.len(program.codes.len());
+    for code in program.codes.iter() {
+        w.u32(code.arity);
+        w.u32(code.slots);
+        w.len(code.ops.len());
+        for &op in code.ops.iter() {
+            let (opcode, operands) = encode(op);
+            w.u8(opcode);
+            for &operand in operands.iter() {
+                w.u32(operand);
+            }
+        }
+        w.len(code.tables.len());
+        for table in code.tables.iter() {
+            w.len(table.cases.len());
+            for &(cons, target) in table.cases.iter() {
+                w.u32(cons);
+                w.u32(target);
+            }
+            w.u32(table.default);
+        }
+    }
+
+    // The strings are only all known once everything else is written.
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(w.strings.len() as u32).to_le_bytes());
+    for s in w.strings.iter() {
+        bytes.extend_from_slice(&(s.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(s.as_bytes());
+    }
+    bytes.extend_from_slice(&w.bytes);
+    bytes
+}
+
+fn encode(op: Op) -> (u8, Vec<u32>) {
+    match op {
+        Op::Const(lit) => (0, vec![lit]),
+        Op::Load(slot) => (1, vec![slot]),
+        Op::Store(slot) => (2, vec![slot]),
+        Op::Capture(ix) => (3, vec![ix]),
+        Op::ThisScope => (4, vec![]),
+        Op::Global(global) => (5, vec![global]),
+        Op::Member(member) => (6, vec![member]),
+        Op::Closure(code, count) => (7, vec![code, count]),
+        Op::Scope(scope, count) => (8, vec![scope, count]),
+        Op::Data(cons, count) => (9, vec![cons, count]),
+        Op::Field(field) => (10, vec![field]),
+        Op::Switch(table) => (11, vec![table]),
+        Op::JumpIf(lit, target) => (12, vec![lit, target]),
+        Op::Jump(target) => (13, vec![target]),
+        Op::Call(count) => (14, vec![count]),
+        Op::TailCall(count) => (15, vec![count]),
+        Op::Return => (16, vec![]),
+        Op::Prim(prim) => {
+            let ix = Prim::ALL.iter().position(|&p| p == prim).unwrap();
+            (17, vec![ix as u32])
+        }
+        Op::Hole => (18, vec![]),
+        Op::NoClause => (19, vec![]),
+        Op::NoMatch(slot) => (20, vec![slot]),
+    }
+}
+
+struct Malformed {
+    message: String,
+    // Whether the file was written for another version of the format.
+    outdated: bool,
+}
+
+impl From<String> for Malformed {
+    fn from(message: String) -> Self {
+        Malformed {
+            message,
+            outdated: false,
+        }
+    }
+}
+
+impl From<&str> for Malformed {
+    fn from(message: &str) -> Self {
+        message.to_owned().into()
+    }
+}
+
+type Result<T> = std::result::Result<T, Malformed>;
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+    pos: usize,
+    strings: Vec<&'b str>,
+}
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, len: usize) -> Result<&'b [u8]> {
+        if self.bytes.len() - self.pos < len {
+            Err("the file ends unexpectedly")?;
+        }
+        let taken = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn f64(&mut self) -> Result<f64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(f64::from_bits(u64::from_le_bytes(bytes)))
+    }
+
+    // Every item takes at least a byte, so no list is longer than what's left.
+    fn len(&mut self) -> Result<usize> {
+        let len = self.u32()? as usize;
+        if len > self.bytes.len() - self.pos {
+            Err("the file ends unexpectedly")?;
+        }
+        Ok(len)
+    }
+
+    fn string(&mut self) -> Result<&'b str> {
+        let ix = self.u32()? as usize;
+        match self.strings.get(ix) {
+            Some(s) => Ok(s),
+            None => Err(format!("string {} is not in the string table", ix))?,
+        }
+    }
+}
+
+fn read(ctx: &Context<'_>, bytes: &[u8]) -> Result<Program> {
+    let mut r = Reader {
+        bytes,
+        pos: 0,
+        strings: Vec::new(),
+    };
+    if r.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        Err("it is not a bytecode file")?;
+    }
+    let version = r.u32()?;
+    if version != VERSION {
+        Err(Malformed {
+            message: format!(
+                "it was written in version {} of the format, not version {}",
+                version, VERSION
+            ),
+            outdated: true,
+        })?;
+    }
+
+    for ix in 0..r.len()? {
+        let len = r.u32()? as usize;
+        match std::str::from_utf8(r.take(len)?) {
+            Ok(s) => r.strings.push(s),
+            Err(_) => Err(format!("string {} is not valid UTF-8", ix))?,
+        }
+    }
+
+    let mut program = Program::default();
+    for _ in 0..r.len()? {
+        program.consts.push(match r.u8()? {
+            0 => Literal::Unit,
+            1 => Literal::Number(r.f64()?),
+            2 => Literal::String(r.string()?.to_owned()),
+            tag => Err(format!("{} is not a kind of constant", tag))?,
+        });
+    }
+
+    for _ in 0..r.len()? {
+        let mut sig = Vec::new();
+        for _ in 0..r.len()? {
+            sig.push(match r.u8()? {
+                0 => Sign::Patn(()),
+                1 => Sign::Word(ident(ctx, r.string()?)),
+                tag => Err(format!("{} is not a part of a signature", tag))?,
+            });
+        }
+        program.cons.push(sig);
+    }
+    program.truth = (r.u32()?, r.u32()?);
+
+    for _ in 0..r.len()? {
+        let name = r.string()?.to_owned();
+        let mut entries = Vec::new();
+        for _ in 0..r.len()? {
+            entries.push((ident(ctx, r.string()?), r.u32()?));
+        }
+        program.modules.push(Module { name, entries });
+    }
+
+    for _ in 0..r.len()? {
+        program.globals.push(r.u32()?);
+    }
+    for _ in 0..r.len()? {
+        program.members.push(Member {
+            code: r.u32()?,
+            slot: r.u32()?,
+        });
+    }
+    for _ in 0..r.len()? {
+        program.scopes.push(r.u32()?);
+    }
+
+    for _ in 0..r.len()? {
+        let mut code = Code {
+            arity: r.u32()?,
+            slots: r.u32()?,
+            ..Code::default()
+        };
+        for _ in 0..r.len()? {
+            code.ops.push(decode(&mut r)?);
+        }
+        for _ in 0..r.len()? {
+            let mut table = Table::default();
+            for _ in 0..r.len()? {
+                table.cases.push((r.u32()?, r.u32()?));
+            }
+            table.default = r.u32()?;
+            code.tables.push(table);
+        }
+        program.codes.push(code);
+    }
+
+    if r.pos != bytes.len() {
+        Err("there is more after the code section")?;
+    }
+    validate(&program, bytes.len())?;
+    Ok(program)
+}
+
+fn ident(ctx: &Context<'_>, name: &str) -> Ident {
+    let mut names = ctx.names.borrow_mut();
+    if let Some(id) = names.get_ident(name) {
+        return id;
+    }
+    let arena_name = ctx.arena.alloc_slice_copy(name.as_bytes());
+    let arena_name = unsafe { std::str::from_utf8_unchecked(arena_name) };
+    names.make_ident(arena_name)
+}
+
+fn decode(r: &mut Reader<'_>) -> Result<Op> {
+    Ok(match r.u8()? {
+        0 => Op::Const(r.u32()?),
+        1 => Op::Load(r.u32()?),
+        2 => Op::Store(r.u32()?),
+        3 => Op::Capture(r.u32()?),
+        4 => Op::ThisScope,
+        5 => Op::Global(r.u32()?),
+        6 => Op::Member(r.u32()?),
+        7 => Op::Closure(r.u32()?, r.u32()?),
+        8 => Op::Scope(r.u32()?, r.u32()?),
+        9 => Op::Data(r.u32()?, r.u32()?),
+        10 => Op::Field(r.u32()?),
+        11 => Op::Switch(r.u32()?),
+        12 => Op::JumpIf(r.u32()?, r.u32()?),
+        13 => Op::Jump(r.u32()?),
+        14 => Op::Call(r.u32()?),
+        15 => Op::TailCall(r.u32()?),
+        16 => Op::Return,
+        17 => match Prim::ALL.get(r.u32()? as usize) {
+            Some(&prim) => Op::Prim(prim),
+            None => Err("an instruction applies an unknown primitive")?,
+        },
+        18 => Op::Hole,
+        19 => Op::NoClause,
+        20 => Op::NoMatch(r.u32()?),
+        opcode => Err(format!("{} is not an instruction", opcode))?,
+    })
+}
+
+/*
+    Beyond every index pointing somewhere, each instruction that can be
+    reached must find on the stack what it takes off, so `stack` follows
+    the code's jumps from its start and works out how deep the stack is
+    before each instruction. Captures are checked against the fewest
+    values the code's closures or scopes are made with, and fields
+    against the constructor with the most of them. A file of n bytes
+    can't need more than n slots, or make scopes with more members than
+    it has, so nothing sized by the file can ask for more than that.
+*/
+fn validate(program: &Program, file_len: usize) -> Result<()> {
+    let check = |ix: u32, len: usize, what: &str| -> Result<()> {
+        match (ix as usize) < len {
+            true => Ok(()),
+            false => Err(format!("there is no {} {}", what, ix))?,
+        }
+    };
+    let codes = program.codes.len();
+    let cons = program.cons.len();
+    let fields = |cons: u32| {
+        program.cons[cons as usize]
+            .iter()
+            .filter(|sign| matches!(sign, Sign::Patn(())))
+            .count()
+    };
+    let most_fields = (0..cons as u32).map(fields).max().unwrap_or(0);
+
+    for &cons_ix in [program.truth.0, program.truth.1].iter() {
+        check(cons_ix, cons, "constructor")?;
+        if fields(cons_ix) > 0 {
+            Err("comparisons would give a constructor with fields")?;
+        }
+    }
+    for module in program.modules.iter() {
+        for &(_, global) in module.entries.iter() {
+            check(global, program.globals.len(), "global")?;
+        }
+    }
+    for &code in program.globals.iter() {
+        check(code, codes, "code")?;
+    }
+
+    // Each scope's members follow the last scope's, in the order of their slots.
+    let mut scope_members = Vec::new();
+    let mut members = program.members.iter();
+    for (scope, &count) in program.scopes.iter().enumerate() {
+        let start = program.members.len() - members.len();
+        for slot in 0..count {
+            match members.next() {
+                Some(member) if member.slot == slot => check(member.code, codes, "code")?,
+                Some(_) => Err(format!("member {} is out of place", start + slot as usize))?,
+                None => Err(format!("scope {} has more members than there are", scope))?,
+            }
+        }
+        scope_members.push(start..start + count as usize);
+    }
+    if members.len() > 0 {
+        Err("some members are not in any scope")?;
+    }
+
+    for (ix, code) in program.codes.iter().enumerate() {
+        let in_code = |err: Malformed| Malformed::from(format!("in code {}, {}", ix, err.message));
+        let ops = code.ops.len();
+        let slots = code.slots as usize;
+        if code.slots < code.arity {
+            Err(in_code("there are fewer slots than arguments".into()))?;
+        }
+        if slots > file_len {
+            Err(in_code(
+                "there are more slots than the file could use".into(),
+            ))?;
+        }
+
+        for &op in code.ops.iter() {
+            match op {
+                Op::Const(lit) => check(lit, program.consts.len(), "constant"),
+                Op::Load(slot) | Op::Store(slot) | Op::NoMatch(slot) => check(slot, slots, "slot"),
+                Op::Global(global) => check(global, program.globals.len(), "global"),
+                Op::Member(member) => check(member, program.members.len(), "member"),
+                Op::Closure(code, _) => check(code, codes, "code"),
+                Op::Scope(scope, _) => check(scope, program.scopes.len(), "scope"),
+                Op::Data(cons_ix, count) => check(cons_ix, cons, "constructor").and_then(|()| {
+                    match fields(cons_ix) == count as usize {
+                        true => Ok(()),
+                        false => Err(format!("constructor {} is given {} fields", cons_ix, count))?,
+                    }
+                }),
+                Op::Field(field) => check(field, most_fields, "field"),
+                Op::Switch(table) => check(table, code.tables.len(), "table"),
+                Op::JumpIf(lit, target) => check(lit, program.consts.len(), "constant")
+                    .and_then(|()| check(target, ops, "instruction")),
+                Op::Jump(target) => check(target, ops, "instruction"),
+                Op::Capture(_)
+                | Op::ThisScope
+                | Op::Call(_)
+                | Op::TailCall(_)
+                | Op::Return
+                | Op::Prim(_)
+                | Op::Hole
+                | Op::NoClause => Ok(()),
+            }
+            .map_err(in_code)?;
+        }
+        for table in code.tables.iter() {
+            for &(cons_ix, target) in table.cases.iter() {
+                check(cons_ix, cons, "constructor").map_err(in_code)?;
+                check(target, ops, "instruction").map_err(in_code)?;
+            }
+            check(table.default, ops, "instruction").map_err(in_code)?;
+        }
+        stack(code).map_err(in_code)?;
+    }
+
+    // Globals are made without captures, and code nothing makes is never run.
+    let mut captures: Vec<Option<u32>> = vec![None; codes];
+    let mut made_with = |code: u32, count: u32| {
+        let seen = &mut captures[code as usize];
+        *seen = Some(seen.map_or(count, |seen| seen.min(count)));
+    };
+    for &code in program.globals.iter() {
+        made_with(code, 0);
+    }
+    for code in program.codes.iter() {
+        for &op in code.ops.iter() {
+            match op {
+                Op::Closure(code, count) => made_with(code, count),
+                Op::Scope(scope, count) => {
+                    for member in program.members[scope_members[scope as usize].clone()].iter() {
+                        made_with(member.code, count);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    for (ix, code) in program.codes.iter().enumerate() {
+        for &op in code.ops.iter() {
+            match (op, captures[ix]) {
+                (Op::Capture(capture), Some(count)) if capture >= count => {
+                    Err(format!("in code {}, there is no capture {}", ix, capture))?
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+// How much each instruction takes off the stack, and puts back.
+fn effect(op: Op) -> (usize, usize) {
+    match op {
+        Op::Const(_) | Op::Load(_) | Op::Capture(_) | Op::ThisScope | Op::Global(_) => (0, 1),
+        Op::Store(_) | Op::Switch(_) | Op::JumpIf(..) | Op::Return => (1, 0),
+        Op::Member(_) | Op::Field(_) => (1, 1),
+        Op::Closure(_, count) | Op::Scope(_, count) | Op::Data(_, count) => (count as usize, 1),
+        Op::Call(count) => (count as usize + 1, 1),
+        Op::TailCall(count) => (count as usize + 1, 0),
+        Op::Prim(_) => (2, 1),
+        Op::Jump(_) | Op::Hole | Op::NoClause | Op::NoMatch(_) => (0, 0),
+    }
+}
+
+fn stack(code: &Code) -> Result<()> {
+    let mut depths = vec![None; code.ops.len()];
+    let mut pending = vec![(0, 0)];
+    while let Some((pc, depth)) = pending.pop() {
+        let op = match code.ops.get(pc) {
+            Some(&op) => op,
+            None => Err("the code can run past its end")?,
+        };
+        match depths[pc] {
+            Some(seen) if seen == depth => continue,
+            Some(_) => Err(format!(
+                "the stack can be two heights at instruction {}",
+                pc
+            ))?,
+            None => depths[pc] = Some(depth),
+        }
+        let (taken, given) = effect(op);
+        if taken > depth {
+            Err(format!(
+                "instruction {} takes more than is on the stack",
+                pc
+            ))?;
+        }
+        let depth = depth - taken + given;
+        match op {
+            Op::Return | Op::TailCall(_) | Op::Hole | Op::NoClause | Op::NoMatch(_) => {}
+            Op::Jump(target) => pending.push((target as usize, depth)),
+            Op::JumpIf(_, target) => {
+                pending.push((target as usize, depth));
+                pending.push((pc + 1, depth));
+            }
+            Op::Switch(table) => {
+                let table = &code.tables[table as usize];
+                for &(_, target) in table.cases.iter() {
+                    pending.push((target as usize, depth));
+                }
+                pending.push((table.default as usize, depth));
+            }
+            _ => pending.push((pc + 1, depth)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::compile;
+    use crate::ir::{infer, lower};
+    use crate::phases;
+    use crate::vm::Machine;
+
+    use bumpalo::Bump;
+
+    const SOURCE: &str = "
+        data List a = con Nil | con Cons a, (List a)
+        def sum (Nil) = 0
+        def sum (Cons x, xs) = x + sum xs
+        def adder (n) = fun x = x + n
+        def shifted (n) = def m = n + 1 in def k = m * 2 in k + n
+        def main = sum (Cons ((adder 2) 3), Cons (shifted 2), Nil)
+    ";
+
+    fn compiled<'ctx>(ctx: &'ctx Context<'ctx>) -> Program {
+        let modl_ref = phases::process_source(ctx, "valid.fri", SOURCE.to_owned()).unwrap();
+        phases::process_aliases(ctx).unwrap();
+        lower::lower_modls(ctx, &[modl_ref]).unwrap();
+        infer::infer_types(ctx);
+        assert_eq!(ctx.error_count(), 0);
+        compile::compile(ctx, &ctx.ir.borrow(), &[modl_ref])
+    }
+
+    fn rejected(ctx: &Context<'_>, bytes: &[u8]) -> String {
+        match read(ctx, bytes) {
+            Ok(_) => panic!("a malformed file was loaded"),
+            Err(err) => err.message,
+        }
+    }
+
+    // Changes the first instruction `change` gives something for.
+    fn changed(ctx: &Context<'_>, program: &Program, change: impl Fn(Op) -> Option<Op>) -> String {
+        let mut program = program.clone();
+        let op = program
+            .codes
+            .iter_mut()
+            .flat_map(|code| code.ops.iter_mut())
+            .find(|op| change(**op).is_some())
+            .unwrap();
+        *op = change(*op).unwrap();
+        rejected(ctx, &write(ctx, &program))
+    }
+
+    #[test]
+    fn written_programs_run() {
+        let arena = Bump::new();
+        let ctx = Context::new(&arena);
+        let program = compiled(&ctx);
+        let loaded = read(&ctx, &write(&ctx, &program)).ok().unwrap();
+        let global = loaded.find_entry(&ctx, "main").unwrap();
+        let value = Machine::new(&ctx, &loaded).run(global).unwrap();
+        assert_eq!(ctx.wrap((&loaded, &value)).to_string(), "13");
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        let arena = Bump::new();
+        let ctx = Context::new(&arena);
+        let bytes = write(&ctx, &compiled(&ctx));
+        for len in 0..bytes.len() {
+            rejected(&ctx, &bytes[..len]);
+        }
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert_eq!(
+            rejected(&ctx, &longer),
+            "there is more after the code section"
+        );
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let arena = Bump::new();
+        let ctx = Context::new(&arena);
+        let mut bytes = write(&ctx, &compiled(&ctx));
+        bytes[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(read(&ctx, &bytes).err().unwrap().outdated);
+        bytes[0] = b'X';
+        assert_eq!(rejected(&ctx, &bytes), "it is not a bytecode file");
+    }
+
+    #[test]
+    fn corrupted_files_are_rejected() {
+        let arena = Bump::new();
+        let ctx = Context::new(&arena);
+        let program = compiled(&ctx);
+
+        let message = changed(&ctx, &program, |op| match op {
+            Op::Field(_) => Some(Op::Field(2)),
+            _ => None,
+        });
+        assert_eq!(message, "in code 1, there is no field 2");
+        let message = changed(&ctx, &program, |op| match op {
+            Op::Capture(ix) => Some(Op::Capture(ix + 1)),
+            _ => None,
+        });
+        assert!(message.ends_with("there is no capture 1"), "{}", message);
+
+        let mut huge = program.clone();
+        huge.codes[0].slots = u32::MAX;
+        let message = rejected(&ctx, &write(&ctx, &huge));
+        assert_eq!(
+            message,
+            "in code 0, there are more slots than the file could use"
+        );
+        let mut huge = program.clone();
+        let last = huge.scopes.len() - 1;
+        huge.scopes[last] = u32::MAX;
+        let message = rejected(&ctx, &write(&ctx, &huge));
+        assert_eq!(
+            message,
+            format!("scope {} has more members than there are", last)
+        );
+
+        // Whatever a flipped bit makes of it, loading it mustn't panic.
+        let bytes = write(&ctx, &program);
+        for ix in 0..bytes.len() {
+            for bit in 0..8 {
+                let mut flipped = bytes.clone();
+                flipped[ix] ^= 1 << bit;
+                let _ = read(&ctx, &flipped);
+            }
+        }
+    }
+
+    #[test]
+    fn stack_underflows_are_rejected() {
+        let arena = Bump::new();
+        let ctx = Context::new(&arena);
+        let program = compiled(&ctx);
+
+        let message = changed(&ctx, &program, |op| match op {
+            Op::Call(count) => Some(Op::Call(count + 1)),
+            _ => None,
+        });
+        assert!(
+            message.contains("takes more than is on the stack"),
+            "{}",
+            message
+        );
+        let message = changed(&ctx, &program, |op| match op {
+            Op::Load(_) => Some(Op::Prim(Prim::Add)),
+            _ => None,
+        });
+        assert!(
+            message.contains("takes more than is on the stack"),
+            "{}",
+            message
+        );
+
+        // Only one way to instruction 3 pushes something first.
+        let mut joined = program.clone();
+        joined.codes.push(Code {
+            ops: vec![
+                Op::Const(0),
+                Op::JumpIf(0, 3),
+                Op::Const(0),
+                Op::Const(0),
+                Op::Return,
+            ],
+            ..Code::default()
+        });
+        let message = rejected(&ctx, &write(&ctx, &joined));
+        assert!(message.ends_with("the stack can be two heights at instruction 3"));
+    }
+}
@@ -1,20 +1,30 @@
 use crate::ast::AstStorage;
+use crate::builtin;
+use crate::error::{Diagnostic, Severity};
 use crate::id::NameTable;
 use crate::ir::{self, IrStorage};
 use crate::refs::*;
+use crate::span::SourceFile;
 use crate::storage::*;
 
 use bumpalo::Bump;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::fmt;
 
 #[derive(Debug, Clone)]
 pub struct Context<'ctx> {
     pub arena: &'ctx Bump,
     pub refs: RefCell<IdCounter>,
     global_modl: ModlRef,
+    poisoned_modl: ModlRef,
     pub names: RefCell<NameTable<'ctx>>,
     pub ast: RefCell<AstStorage<'ctx>>,
     pub ir: RefCell<IrStorage>,
+    pub files: RefCell<VecStorage<SourceFile, FileRef>>,
+    pub diagnostics: RefCell<Vec<Diagnostic>>,
+    // Whether the phases describe what they're doing as they go, on stderr
+    // so it never gets mixed up with what a program prints.
+    pub verbose: Cell<bool>,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -47,14 +57,26 @@ impl<'ctx> Context<'ctx> {
 
         ir.modl.set(global_modl, ir::Modl::new("<global>".into()));
 
-        Context {
+        // Stands in for anything which failed to resolve. It is empty,
+        // and refers to nothing, so no further errors come of using it.
+        let poisoned_modl = refs.modl.make_ref();
+        ir.modl.set(poisoned_modl, ir::Modl::new("<error>".into()));
+
+        let ctx = Context {
             arena,
             global_modl,
+            poisoned_modl,
             refs: RefCell::new(refs),
             names: RefCell::new(NameTable::new()),
             ast: RefCell::new(AstStorage::new()),
             ir: RefCell::new(ir),
-        }
+            files: RefCell::new(VecStorage::new()),
+            diagnostics: RefCell::new(Vec::new()),
+            verbose: Cell::new(false),
+        };
+
+        builtin::declare_builtins(&ctx);
+        ctx
     }
 
     pub fn wrap<T>(&'ctx self, t: T) -> WithContext<'ctx, T> {
@@ -64,4 +86,27 @@ impl<'ctx> Context<'ctx> {
     pub fn global_modl(&self) -> ModlRef {
         self.global_modl
     }
+
+    pub fn poisoned_modl(&self) -> ModlRef {
+        self.poisoned_modl
+    }
+
+    // Record a problem and carry on, so one run can find as many as possible.
+    pub fn report(&self, diag: impl Into<Box<Diagnostic>>) {
+        self.diagnostics.borrow_mut().push(*diag.into());
+    }
+
+    pub fn trace(&self, args: fmt::Arguments) {
+        if self.verbose.get() {
+            eprintln!("{}", args);
+        }
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.diagnostics
+            .borrow()
+            .iter()
+            .filter(|diag| diag.severity == Severity::Error)
+            .count()
+    }
 }
@@ -0,0 +1,353 @@
+use crate::ctx::Context;
+use crate::error::{self, Diagnostic};
+use crate::ir::{self, IrStorage, Modl, ModlRecord, Sign};
+use crate::refs::*;
+use crate::span::Span;
+use crate::storage::*;
+
+use std::fmt::Write;
+use std::path::Path;
+
+// What the `--|` comments above declarations say about them.
+#[derive(Debug, Clone)]
+pub struct DocStorage {
+    pub decl: HashStorage<String, DeclRef>,
+    pub cons: HashStorage<String, ConsRef>,
+    pub data: HashStorage<String, DataRef>,
+    pub modl: HashStorage<String, ModlRef>,
+}
+
+impl DocStorage {
+    pub fn new() -> Self {
+        DocStorage {
+            decl: HashStorage::new(),
+            cons: HashStorage::new(),
+            data: HashStorage::new(),
+            modl: HashStorage::new(),
+        }
+    }
+}
+
+/*
+    Each named module gets a page, listing its child modules, then the
+    constructors of each of its data types, then its definitions, with
+    whatever their doc comments say. Modules that are aliases link to
+    the module they stand for. Scopes and the modules `use` makes up
+    have names nobody can write, so they don't get pages.
+*/
+pub fn generate<'ctx>(ctx: &'ctx Context<'ctx>, dir: &str) -> error::Result<()> {
+    let ir = ctx.ir.borrow();
+    let write_error = |err: std::io::Error| {
+        Diagnostic::new(
+            error::UnreadableFile,
+            format!("Could not write the documentation to {}: {}", dir, err),
+        )
+    };
+    std::fs::create_dir_all(dir).map_err(write_error)?;
+
+    let mut index = Vec::new();
+    for (modl_ref, modl) in &ir.modl {
+        match modl {
+            Modl::Record(record) if is_documented(&record.name) => {
+                let page = Page { ctx, ir: &ir };
+                let html = page.modl(modl_ref, record);
+                let path = Path::new(dir).join(file_name(&record.name));
+                std::fs::write(path, html).map_err(write_error)?;
+                index.push(&record.name);
+            }
+            _ => {}
+        }
+    }
+
+    index.sort();
+    let mut html = header("Modules");
+    writeln!(html, "<h1>Modules</h1>\n<ul>").unwrap();
+    for name in index {
+        let link = format!("<a href=\"{}\">{}</a>", file_name(name), escape(name));
+        writeln!(html, "<li><code>{}</code></li>", link).unwrap();
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    let path = Path::new(dir).join("index.html");
+    std::fs::write(path, html).map_err(write_error)?;
+    Ok(())
+}
+
+fn is_documented(name: &str) -> bool {
+    !name.contains('<')
+}
+
+fn file_name(modl_name: &str) -> String {
+    format!("{}.html", modl_name)
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn header(title: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{}</title>\n</head>\n<body>\n",
+        escape(title)
+    )
+}
+
+// Where a pattern said to have a type was written, like `l : List a`.
+fn annotation(mut patn: &ir::Patn) -> Option<Span> {
+    let mut span = None;
+    loop {
+        match patn {
+            ir::Patn::Spanned(outer, inner) => {
+                span = Some(*outer);
+                patn = inner;
+            }
+            ir::Patn::Annotated(..) => return span,
+            _ => return None,
+        }
+    }
+}
+
+// Blank lines separate paragraphs, like in the comments themselves.
+fn paragraphs(html: &mut String, doc: Option<&String>) {
+    for paragraph in doc.iter().flat_map(|doc| doc.split("\n\n")) {
+        if !paragraph.trim().is_empty() {
+            writeln!(html, "<p>{}</p>", escape(paragraph.trim())).unwrap();
+        }
+    }
+}
+
+struct Page<'a, 'ctx> {
+    ctx: &'ctx Context<'ctx>,
+    ir: &'a IrStorage,
+}
+
+impl<'ctx> Page<'_, 'ctx> {
+    fn modl(&self, modl_ref: ModlRef, record: &ModlRecord) -> String {
+        let (ctx, ir) = (self.ctx, self.ir);
+        let mut html = header(&record.name);
+        writeln!(html, "<h1>mod <code>{}</code></h1>", escape(&record.name)).unwrap();
+        if let Some((parent, _)) = record.name.rsplit_once('.') {
+            let link = format!("<a href=\"{}\">{}</a>", file_name(parent), escape(parent));
+            writeln!(html, "<p>In <code>{}</code></p>", link).unwrap();
+        }
+        paragraphs(&mut html, ir.docs.modl.get(modl_ref));
+
+        let mut children: Vec<_> = record.children.iter().collect();
+        children.sort_by_key(|&(_, &child_ref)| usize::from(child_ref));
+        if !children.is_empty() {
+            html.push_str("<h2>Modules</h2>\n<dl>\n");
+        }
+        for (&id, &child_ref) in children.iter() {
+            let name = escape(ctx.names.borrow().get(id).unwrap());
+            let target = ir
+                .target(child_ref)
+                .map(|target| ir.modl.get(target).unwrap());
+            let line = match (ir.modl.get(child_ref), target) {
+                (Some(Modl::Record(_)), Some(target)) => self.link(target.name(), &name),
+                (Some(Modl::Alias(_)), Some(target)) => {
+                    let link = self.link(target.name(), &escape(target.name()));
+                    format!("{} = {}", name, link)
+                }
+                _ => name,
+            };
+            writeln!(html, "<dt><code>mod {}</code></dt>", line).unwrap();
+            html.push_str("<dd>\n");
+            paragraphs(&mut html, ir.docs.modl.get(child_ref));
+            html.push_str("</dd>\n");
+        }
+        if !children.is_empty() {
+            html.push_str("</dl>\n");
+        }
+
+        if !record.data.is_empty() {
+            html.push_str("<h2>Constructors</h2>\n");
+        }
+        for &data_ref in record.data.iter() {
+            self.data(&mut html, data_ref);
+        }
+
+        let mut sigs: Vec<_> = record.symbols.iter_decl_signs().collect();
+        sigs.sort_by_key(|&(_, decls)| usize::from(decls[0]));
+        let has_decls = !sigs.is_empty();
+        if has_decls {
+            html.push_str("<h2>Definitions</h2>\n<dl>\n");
+        }
+        for (sig, decls) in sigs {
+            let decl = ir.decl.get(decls[0]).unwrap();
+            let (is_let, binds_one) = (decl.is_let(), decl.binds_one(ir));
+            let keyword = if is_let { "let" } else { "def" };
+            let mut line = format!("{} {}", keyword, escape(&ctx.wrap(sig).to_string()));
+            // What was written says more than what was inferred, like
+            // which variables are which.
+            let written = decls.iter().find_map(|&decl_ref| self.written(decl_ref));
+            match (written, ir.types.decl.get(decls[0])) {
+                _ if is_let && !binds_one => {}
+                (Some(head), _) => line = format!("{} {}", keyword, escape(&head)),
+                (None, Some(scheme)) => {
+                    line.push_str(&format!(" : {}", escape(&ctx.wrap(scheme).to_string())));
+                }
+                (None, None) => {}
+            }
+            let id = usize::from(decls[0]);
+            writeln!(html, "<dt id=\"decl-{}\"><code>{}</code></dt>", id, line).unwrap();
+            html.push_str("<dd>\n");
+            let doc = decls
+                .iter()
+                .find_map(|&decl_ref| ir.docs.decl.get(decl_ref));
+            paragraphs(&mut html, doc);
+            html.push_str("</dd>\n");
+        }
+        if has_decls {
+            html.push_str("</dl>\n");
+        }
+
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+
+    fn data(&self, html: &mut String, data_ref: DataRef) {
+        let (ctx, ir) = (self.ctx, self.ir);
+        let data = ir.data.get(data_ref).unwrap();
+        if let Some(name) = data.name {
+            let names = ctx.names.borrow();
+            let mut head = format!("data {}", names.get(name).unwrap());
+            for &param in data.params.iter() {
+                head.push_str(&format!(" {}", names.get(param).unwrap()));
+            }
+            writeln!(html, "<h3><code>{}</code></h3>", escape(&head)).unwrap();
+        }
+        paragraphs(html, ir.docs.data.get(data_ref));
+
+        html.push_str("<dl>\n");
+        for &cons_ref in data.cons.iter() {
+            let sig = &ir.cons.get(cons_ref).unwrap().sig;
+            let mut line = format!("con {}", escape(&ctx.wrap(&sig[..]).to_string()));
+            if let Some(scheme) = ir.types.cons.get(cons_ref) {
+                line.push_str(&format!(" : {}", escape(&ctx.wrap(scheme).to_string())));
+            }
+            let id = usize::from(cons_ref);
+            writeln!(html, "<dt id=\"cons-{}\"><code>{}</code></dt>", id, line).unwrap();
+            html.push_str("<dd>\n");
+            paragraphs(html, ir.docs.cons.get(cons_ref));
+            html.push_str("</dd>\n");
+        }
+        html.push_str("</dl>\n");
+    }
+
+    // The head of a declaration as it was written, if it has annotations.
+    fn written(&self, decl_ref: DeclRef) -> Option<String> {
+        let decl = self.ir.decl.get(decl_ref).unwrap();
+        let files = self.ctx.files.borrow();
+        let text = |span: Span| &files.get(span.file).unwrap().text[span.start..span.end];
+        let names = self.ctx.names.borrow();
+
+        let mut annotated = false;
+        let mut head = Vec::new();
+        for sign in decl.sig.iter() {
+            let patn = match sign {
+                Sign::Word(id) => {
+                    head.push(names.get(*id).unwrap().to_owned());
+                    continue;
+                }
+                Sign::Patn(patn_ref) => self.ir.patn.get(*patn_ref).unwrap(),
+            };
+            let span = annotation(patn);
+            annotated |= span.is_some();
+            match span {
+                // A `let` is only its pattern, so it needs no parentheses.
+                Some(span) if decl.sig.len() == 1 => head.push(text(span).to_owned()),
+                Some(span) => head.push(format!("({})", text(span))),
+                None => head.push("_".to_owned()),
+            }
+        }
+        if let Some((_, span)) = decl.result {
+            head.push(format!(": {}", text(span)));
+            annotated = true;
+        }
+        if annotated {
+            Some(head.join(" "))
+        } else {
+            None
+        }
+    }
+
+    // Only modules with pages can be linked to.
+    fn link(&self, modl_name: &str, text: &str) -> String {
+        if is_documented(modl_name) {
+            format!("<a href=\"{}\">{}</a>", file_name(modl_name), text)
+        } else {
+            text.to_owned()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::phases;
+
+    use bumpalo::Bump;
+
+    const SOURCE: &str = "
+        --| Lists of things.
+        data List a =
+            --| Nothing at all.
+            con Nil
+            --| One thing, and then the rest.
+          | con Cons a, (List a)
+
+        --| How many things there are.
+        --|
+        --| Counted one at a time.
+        def len (l : List a) : Num = match l | Nil = 0 | Cons _, xs = 1 + len xs end
+
+        --| Some shapes.
+        mod Shapes = mod
+            def sides = 4
+        end
+        mod Sh = Shapes
+    ";
+
+    #[test]
+    fn pages_show_doc_comments() {
+        let arena = Bump::new();
+        let ctx = Context::new(&arena);
+        phases::load_source(&ctx, SOURCE).unwrap();
+        assert_eq!(phases::reported(&ctx), []);
+
+        let dir = std::env::temp_dir().join(format!("friday-doc-{}", std::process::id()));
+        generate(&ctx, dir.to_str().unwrap()).unwrap();
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        let (index, page, shapes) = (
+            read("index.html"),
+            read("test.html"),
+            read("test.Shapes.html"),
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(index.contains("<a href=\"test.Shapes.html\">test.Shapes</a>"));
+        let paragraphs = [
+            "<p>Lists of things.</p>",
+            "<p>Nothing at all.</p>",
+            "<p>One thing, and then the rest.</p>",
+            "<p>How many things there are.</p>\n<p>Counted one at a time.</p>",
+            "<p>Some shapes.</p>",
+            "<code>def len (l : List a) : Num</code>",
+            "<code>mod Sh = <a href=\"test.Shapes.html\">test.Shapes</a></code>",
+        ];
+        for paragraph in paragraphs.iter() {
+            assert!(page.contains(paragraph), "{} in\n{}", paragraph, page);
+        }
+        assert!(shapes.contains("<p>In <code><a href=\"test.html\">test</a></code></p>"));
+    }
+}
@@ -1,27 +1,385 @@
+use crate::ctx::WithContext;
+use crate::span::Span;
+use crate::storage::*;
+
 use std::error;
 use std::fmt;
 
-pub type Result<T> = ::std::result::Result<T, Box<dyn std::error::Error + 'static>>;
+pub type Result<T> = ::std::result::Result<T, Box<Diagnostic>>;
 
-#[derive(Debug)]
-pub enum FridayError {
-    InvalidFilename(String),
-    UnresolvableModulePath(String),
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErrorCode {
+    InvalidFilename,
+    UnreadableFile,
+    InvalidSyntax,
+    UnresolvableModulePath,
     UnexpectedModuleAlias,
     UnexpectedModuleRecord,
+    UnresolvedName,
+    NoMatchingSignature,
+    AmbiguousSignature,
+    UngroupableOperators,
+    ConflictingFixity,
+    ConflictingImports,
+    UnknownImport,
+    InvalidArguments,
+    UndefinedEntryPoint,
+    NoMatchingPattern,
+    InvalidOperands,
+    UnfilledHole,
+    DuplicateDefinition,
+    SplitClauses,
+    MismatchedArity,
+    DuplicateBinding,
+    NonExhaustivePatterns,
+    UnreachablePattern,
+    MismatchedTypes,
+    InfiniteType,
+    InvalidType,
+    InvalidBytecode,
+    UnformattedFile,
+    UnstableFormat,
 }
 
-pub use FridayError::*;
+pub use ErrorCode::*;
+
+impl ErrorCode {
+    // Codes are never reused, so new ones only go at the end.
+    pub fn number(self) -> u32 {
+        match self {
+            InvalidFilename => 1,
+            UnreadableFile => 2,
+            InvalidSyntax => 3,
+            UnresolvableModulePath => 4,
+            UnexpectedModuleAlias => 5,
+            UnexpectedModuleRecord => 6,
+            UnresolvedName => 7,
+            NoMatchingSignature => 8,
+            AmbiguousSignature => 9,
+            UngroupableOperators => 10,
+            ConflictingFixity => 11,
+            ConflictingImports => 12,
+            UnknownImport => 13,
+            InvalidArguments => 14,
+            UndefinedEntryPoint => 15,
+            NoMatchingPattern => 16,
+            InvalidOperands => 17,
+            UnfilledHole => 18,
+            DuplicateDefinition => 19,
+            SplitClauses => 20,
+            MismatchedArity => 21,
+            DuplicateBinding => 22,
+            NonExhaustivePatterns => 23,
+            UnreachablePattern => 24,
+            MismatchedTypes => 25,
+            InfiniteType => 26,
+            InvalidType => 27,
+            InvalidBytecode => 28,
+            UnformattedFile => 29,
+            UnstableFormat => 30,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "E{:04}", self.number())
+    }
+}
 
-impl error::Error for FridayError {}
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
 
-impl fmt::Display for FridayError {
+impl fmt::Display for Severity {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            InvalidFilename(s) => write!(f, "Filename was invalid: {}", s),
-            UnresolvableModulePath(s) => write!(f, "Unresolvable path: {}", s),
-            UnexpectedModuleAlias => write!(f, "Expected module record, got alias."),
-            UnexpectedModuleRecord => write!(f, "Expected module alias, got record."),
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub code: ErrorCode,
+    pub severity: Severity,
+    pub message: String,
+    pub primary: Option<Label>,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Diagnostic {
+            code,
+            severity: Severity::Error,
+            message: message.into(),
+            primary: None,
+            labels: Vec::new(),
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn warning(code: ErrorCode, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::new(code, message)
+        }
+    }
+
+    pub fn with_primary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.primary = Some(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn with_suggestions(self, names: &[String]) -> Self {
+        let quoted: Vec<_> = names.iter().map(|name| format!("`{}`", name)).collect();
+        match quoted.as_slice() {
+            [] => self,
+            [only] => self.with_help(format!("Did you mean {}?", only)),
+            _ => self.with_help(format!("Did you mean one of {}?", quoted.join(", "))),
+        }
+    }
+}
+
+const MAX_SUGGESTIONS: usize = 3;
+
+// The candidates close enough to a misspelt name to be worth
+// suggesting instead, closest first.
+pub fn similar_names<S: AsRef<str>>(
+    name: &str,
+    candidates: impl IntoIterator<Item = S>,
+) -> Vec<String> {
+    let limit = (name.chars().count() / 3).max(1);
+    let mut similar: Vec<_> = candidates
+        .into_iter()
+        .map(|candidate| candidate.as_ref().to_owned())
+        .filter(|candidate| candidate != name)
+        .map(|candidate| (edit_distance(name, &candidate), candidate))
+        .filter(|&(distance, _)| distance <= limit)
+        .collect();
+    similar.sort();
+    similar.dedup();
+    similar
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+// Counts swapping two adjacent characters as a single edit,
+// since that's one of the most common kinds of typo.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut dist = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in dist.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in dist[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut best = (dist[i - 1][j] + 1)
+                .min(dist[i][j - 1] + 1)
+                .min(dist[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(dist[i - 2][j - 2] + 1);
+            }
+            dist[i][j] = best;
+        }
+    }
+    dist[a.len()][b.len()]
+}
+
+impl error::Error for Diagnostic {}
+
+// Without the source files at hand, only the headline can be shown.
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)
+    }
+}
+
+impl fmt::Display for WithContext<'_, &Diagnostic> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let diag = self.val;
+        writeln!(f, "{}", diag)?;
+
+        let files = self.files.borrow();
+        let labels = diag.primary.iter().map(|label| (label, '^'));
+        let labels = labels.chain(diag.labels.iter().map(|label| (label, '-')));
+
+        let located: Vec<_> = labels
+            .filter_map(|(label, underline)| {
+                let file = files.get(label.span.file)?;
+                let (line, col) = file.line_col(label.span.start);
+                Some((label, underline, file, line, col))
+            })
+            .collect();
+        let gutter_width = located
+            .iter()
+            .map(|&(_, _, _, line, _)| line.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let gutter = " ".repeat(gutter_width);
+
+        // Each label shows the line it starts on, underlined up to
+        // the end of its span or of that line, whichever is first.
+        for (ix, &(label, underline, file, line, col)) in located.iter().enumerate() {
+            let text = file.text.lines().nth(line - 1).unwrap_or("");
+
+            let arrow = if ix == 0 { "-->" } else { ":::" };
+            writeln!(f, "{} {} {}:{}:{}", gutter, arrow, &file.name, line, col)?;
+            writeln!(f, "{} |", gutter)?;
+            writeln!(f, "{:>width$} | {}", line, text, width = gutter_width)?;
+
+            let width = file.text[label.span.start..label.span.end]
+                .lines()
+                .next()
+                .map_or(0, |first| first.chars().count())
+                .max(1);
+            let padding: String = text
+                .chars()
+                .take(col - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let marks = underline.to_string().repeat(width);
+            writeln!(f, "{} | {}{} {}", gutter, padding, marks, &label.message)?;
+        }
+
+        for note in diag.notes.iter() {
+            writeln!(f, "{} = note: {}", gutter, note)?;
+        }
+        if let Some(help) = &diag.help {
+            writeln!(f, "{} = help: {}", gutter, help)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ctx::Context;
+    use crate::phases;
+
+    use bumpalo::Bump;
+
+    #[test]
+    fn diagnostics_show_their_source() {
+        let arena = Bump::new();
+        let ctx = Context::new(&arena);
+        let source = "def one = 1\ndef two = one one\n";
+        let (file, _) = phases::parse_source(&ctx, "test.fri", source.to_owned()).unwrap();
+        let span = |start, end| Span { file, start, end };
+        let diag = Diagnostic::new(MismatchedTypes, "Cannot apply a number")
+            .with_primary(span(22, 29), "applied here")
+            .with_label(span(10, 11), "a number")
+            .with_note("Numbers aren't functions.")
+            .with_help("Remove the argument.");
+        let expected = "\
+error[E0025]: Cannot apply a number
+  --> test.fri:2:11
+  |
+2 | def two = one one
+  |           ^^^^^^^ applied here
+  ::: test.fri:1:11
+  |
+1 | def one = 1
+  |           - a number
+  = note: Numbers aren't functions.
+  = help: Remove the argument.
+";
+        assert_eq!(ctx.wrap(&diag).to_string(), expected);
+
+        let ctx = Context::new(&arena);
+        assert_eq!(phases::check_source(&ctx, "def = 1"), [InvalidSyntax]);
+        let diagnostics = ctx.diagnostics.borrow();
+        let rendered = ctx.wrap(&diagnostics[0]).to_string();
+        assert!(
+            rendered.contains("1 | def = 1\n  |     ^ unexpected token\n"),
+            "{}",
+            rendered
+        );
+    }
+
+    #[test]
+    fn similar_names_are_suggested() {
+        // Swapping two letters is one edit, and short names allow just the one.
+        assert_eq!(
+            similar_names("lne", vec!["len", "line", "map"]),
+            ["len", "line"]
+        );
+        assert_eq!(similar_names("ab", vec!["ba", "xy"]), ["ba"]);
+        assert_eq!(similar_names("len", vec!["len"]), Vec::<String>::new());
+        let many = vec!["lengths", "lenght", "lengthy", "length", "strength"];
+        assert_eq!(
+            similar_names("length", many),
+            ["lenght", "lengths", "lengthy"]
+        );
+
+        let help = |names: &[&str]| {
+            let names: Vec<_> = names.iter().map(|&name| name.to_owned()).collect();
+            Diagnostic::new(UnresolvedName, "")
+                .with_suggestions(&names)
+                .help
+        };
+        assert_eq!(help(&[]), None);
+        assert_eq!(help(&["len"]).unwrap(), "Did you mean `len`?");
+        assert_eq!(help(&["a", "b"]).unwrap(), "Did you mean one of `a`, `b`?");
+
+        let sources = [
+            ("mod Bee = mod end mod D = Be", "Did you mean `Bee`?"),
+            ("def len (x) = x def y = lem 2", "Did you mean `len`?"),
+            (
+                "mod A = mod end mod B = A.C mod D = C",
+                "Did you mean one of `A`, `B`, `D`?",
+            ),
+        ];
+        for &(source, expected) in sources.iter() {
+            let arena = Bump::new();
+            let ctx = Context::new(&arena);
+            phases::check_source(&ctx, source);
+            let diagnostics = ctx.diagnostics.borrow();
+            let help = diagnostics.iter().find_map(|diag| diag.help.as_deref());
+            assert_eq!(help, Some(expected), "{}", source);
         }
     }
 }
@@ -0,0 +1,657 @@
+use crate::builtin::{self, Operand, Outcome, Prim, View};
+use crate::ctx::{Context, WithContext};
+use crate::error::{self, Diagnostic};
+use crate::id::Ident;
+use crate::ir::decision::{Case, DecisionTree, Node};
+use crate::ir::{Expr, IrStorage, Literal, Modl, Patn, Sign};
+use crate::refs::*;
+use crate::storage::*;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Debug, Clone)]
+pub enum Value<'ir> {
+    Unit,
+    Number(f64),
+    String(Rc<str>),
+    Data(ConsRef, Rc<[Value<'ir>]>),
+    Func(Rc<Closure<'ir>>),
+}
+
+impl Operand for Value<'_> {
+    type Cons = ConsRef;
+    fn view(&self) -> View<'_, Self, ConsRef> {
+        match self {
+            Value::Unit => View::Unit,
+            Value::Number(n) => View::Number(*n),
+            Value::String(s) => View::String(s),
+            Value::Data(cons_ref, fields) => View::Data(*cons_ref, fields),
+            Value::Func(_) => View::Func,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Closure<'ir> {
+    Lambda {
+        patn: &'ir Patn,
+        body: &'ir Expr,
+        env: Env<'ir>,
+    },
+    // A declaration still waiting for some of its arguments.
+    Decl {
+        decl_ref: DeclRef,
+        env: Env<'ir>,
+        args: Vec<Value<'ir>>,
+    },
+}
+
+type Cache<'ir> = Rc<RefCell<HashMap<DeclRef, Value<'ir>>>>;
+
+// What a call comes to: its value, or a body to evaluate in its
+// place, along with the clause it belongs to if there is one.
+enum Tail<'ir> {
+    Value(Value<'ir>),
+    Eval(Option<DeclRef>, &'ir Expr, Env<'ir>),
+}
+
+/*
+    Environments are persistent, so closures can share whatever
+    they capture. Besides local bindings they mark where each
+    scoped module was entered, since the declarations in such a
+    module can refer to the locals which were in scope there.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct Env<'ir>(Option<Rc<Frame<'ir>>>);
+
+#[derive(Debug)]
+struct Frame<'ir> {
+    binding: Binding<'ir>,
+    next: Env<'ir>,
+}
+
+#[derive(Debug)]
+enum Binding<'ir> {
+    Local(Ident, Value<'ir>),
+    Scope(ModlRef, Cache<'ir>),
+}
+
+impl<'ir> Env<'ir> {
+    fn push(&self, binding: Binding<'ir>) -> Self {
+        Env(Some(Rc::new(Frame {
+            binding,
+            next: self.clone(),
+        })))
+    }
+
+    fn bind(&self, id: Ident, value: Value<'ir>) -> Self {
+        self.push(Binding::Local(id, value))
+    }
+
+    fn enter(&self, modl_ref: ModlRef) -> Self {
+        self.push(Binding::Scope(modl_ref, Rc::default()))
+    }
+
+    fn frames(&self) -> impl Iterator<Item = (&Binding<'ir>, &Env<'ir>)> {
+        let mut env = self;
+        std::iter::from_fn(move || {
+            let frame = env.0.as_ref()?;
+            let here = env;
+            env = &frame.next;
+            Some((&frame.binding, here))
+        })
+    }
+
+    fn lookup(&self, id: Ident) -> Option<Value<'ir>> {
+        self.frames().find_map(|(binding, _)| match binding {
+            Binding::Local(local, value) if *local == id => Some(value.clone()),
+            _ => None,
+        })
+    }
+
+    // The environment as it was when a scoped module was entered.
+    fn scope(&self, modl_ref: ModlRef) -> Option<(Env<'ir>, Cache<'ir>)> {
+        self.frames().find_map(|(binding, env)| match binding {
+            Binding::Scope(scope_ref, cache) if *scope_ref == modl_ref => {
+                Some((env.clone(), cache.clone()))
+            }
+            _ => None,
+        })
+    }
+}
+
+pub struct Interpreter<'ctx, 'ir> {
+    ctx: &'ctx Context<'ctx>,
+    ir: &'ir IrStorage,
+    // Which environment a declaration is evaluated in, found by looking up
+    // its scope among the frames, and otherwise the empty one.
+    scopes: HashMap<DeclRef, Option<ModlRef>>,
+    globals: HashMap<DeclRef, Value<'ir>>,
+    truth: (ConsRef, ConsRef),
+    stack: Vec<DeclRef>,
+}
+
+type Result<'ir> = error::Result<Value<'ir>>;
+
+impl<'ctx, 'ir> Interpreter<'ctx, 'ir> {
+    pub fn new(ctx: &'ctx Context<'ctx>, ir: &'ir IrStorage) -> Self {
+        Interpreter {
+            ctx,
+            ir,
+            scopes: ir.scopes(),
+            globals: HashMap::new(),
+            truth: truth(ctx, ir),
+            stack: Vec::new(),
+        }
+    }
+
+    pub fn eval_decl(&mut self, decl_ref: DeclRef) -> Result<'ir> {
+        self.var(decl_ref, &Env::default())
+    }
+
+    fn error(&self, code: error::ErrorCode, message: String) -> Box<Diagnostic> {
+        let diag = Diagnostic::new(code, message);
+        // Builtins have no source, so point at whatever called them.
+        let span = self
+            .stack
+            .iter()
+            .rev()
+            .find_map(|&decl_ref| self.ir.spans.decl.get(decl_ref));
+        Box::new(match span {
+            Some(&span) => diag.with_primary(span, "while evaluating this"),
+            None => diag,
+        })
+    }
+
+    fn eval(&mut self, expr: &'ir Expr, env: &Env<'ir>) -> Result<'ir> {
+        let depth = self.stack.len();
+        let value = self.eval_tail(expr, env.clone(), depth)?;
+        self.stack.truncate(depth);
+        Ok(value)
+    }
+
+    /*
+        Whatever is in tail position replaces the expression being
+        evaluated, instead of being evaluated recursively, so loops
+        through any number of calls, arms and scopes take constant
+        stack. A clause called this way takes the place of the one
+        that called it on the stack of clauses too.
+    */
+    fn eval_tail(&mut self, mut expr: &'ir Expr, mut env: Env<'ir>, depth: usize) -> Result<'ir> {
+        loop {
+            let tail = match expr {
+                Expr::Apply(func, arg) => {
+                    let func = self.eval(func, &env)?;
+                    let arg = self.eval(arg, &env)?;
+                    self.apply(func, arg)?
+                }
+                Expr::Match(scrut, arms, tree) => {
+                    let scrut = self.eval(scrut, &env)?;
+                    if let Some(arm) = self.decide(tree, std::slice::from_ref(&scrut), &mut env) {
+                        expr = &arms[arm].1;
+                        continue;
+                    }
+                    let scrut = self.ctx.wrap(&scrut).to_string();
+                    return Err(self.error(
+                        error::NoMatchingPattern,
+                        format!("No pattern matches {}", scrut),
+                    ));
+                }
+                Expr::Scoped(modl_ref, body) => {
+                    env = env.enter(*modl_ref);
+                    expr = body;
+                    continue;
+                }
+                Expr::Spanned(_, inner) => {
+                    expr = inner;
+                    continue;
+                }
+                _ => return self.eval_leaf(expr, &env),
+            };
+
+            match tail {
+                Tail::Value(value) => return Ok(value),
+                Tail::Eval(clause_ref, body, body_env) => {
+                    if let Some(clause_ref) = clause_ref {
+                        match self.stack.len() > depth {
+                            true => *self.stack.last_mut().unwrap() = clause_ref,
+                            false => self.stack.push(clause_ref),
+                        }
+                    }
+                    expr = body;
+                    env = body_env;
+                }
+            }
+        }
+    }
+
+    // Expressions with nothing in tail position.
+    fn eval_leaf(&mut self, expr: &'ir Expr, env: &Env<'ir>) -> Result<'ir> {
+        match expr {
+            Expr::Hole => Err(self.error(error::UnfilledHole, "Evaluated a hole".into())),
+            Expr::Literal(lit) => Ok(match lit {
+                Literal::Unit => Value::Unit,
+                Literal::Number(n) => Value::Number(*n),
+                Literal::String(s) => Value::String(s.as_str().into()),
+            }),
+            Expr::Local(id) => Ok(env.lookup(*id).expect("Locals are bound when lowered.")),
+            Expr::Var(decl_ref) => self.var(*decl_ref, env),
+            Expr::Data(cons_ref, args) => {
+                let mut values = Vec::new();
+                for arg in args.iter() {
+                    values.push(self.eval(arg, env)?);
+                }
+                Ok(Value::Data(*cons_ref, values.into()))
+            }
+            Expr::Func(patn, body) => Ok(Value::Func(Rc::new(Closure::Lambda {
+                patn,
+                body,
+                env: env.clone(),
+            }))),
+            Expr::Prim(prim, args) => {
+                let lhs = self.eval(&args[0], env)?;
+                let rhs = self.eval(&args[1], env)?;
+                self.prim(*prim, lhs, rhs)
+            }
+            Expr::Apply(..) | Expr::Match(..) | Expr::Scoped(..) | Expr::Spanned(..) => {
+                unreachable!("Tail positions are evaluated in a loop.")
+            }
+        }
+    }
+
+    fn var(&mut self, decl_ref: DeclRef, env: &Env<'ir>) -> Result<'ir> {
+        let decl = self.ir.decl.get(decl_ref).unwrap();
+        let (env, cache) = match self.scopes.get(&decl_ref).copied().flatten() {
+            Some(scope_ref) => {
+                let (env, cache) = env.scope(scope_ref).expect("Scopes are entered first.");
+                (env, Some(cache))
+            }
+            None => (Env::default(), None),
+        };
+
+        let arity = decl
+            .sig
+            .iter()
+            .filter(|sign| matches!(sign, Sign::Patn(_)))
+            .count();
+        // A `let` has a pattern too, but only for its value.
+        if arity > 0 && !decl.is_let() {
+            return Ok(Value::Func(Rc::new(Closure::Decl {
+                decl_ref,
+                env,
+                args: Vec::new(),
+            })));
+        }
+
+        let cached = match &cache {
+            Some(cache) => cache.borrow().get(&decl_ref).cloned(),
+            None => self.globals.get(&decl_ref).cloned(),
+        };
+        if let Some(value) = cached {
+            return Ok(value);
+        }
+
+        let (clause_ref, body, env) = self.call(decl_ref, &env, &[])?;
+        self.stack.push(clause_ref);
+        let value = self.eval(body, &env)?;
+        self.stack.pop();
+        match cache {
+            Some(cache) => cache.borrow_mut().insert(decl_ref, value.clone()),
+            None => self.globals.insert(decl_ref, value.clone()),
+        };
+        Ok(value)
+    }
+
+    // Takes the first clause whose patterns all match the arguments,
+    // giving its body to evaluate and the bindings to evaluate it with.
+    fn call(
+        &mut self,
+        decl_ref: DeclRef,
+        env: &Env<'ir>,
+        args: &[Value<'ir>],
+    ) -> error::Result<(DeclRef, &'ir Expr, Env<'ir>)> {
+        let mut env = env.clone();
+        let arm = match self.ir.decisions.get(decl_ref) {
+            Some(tree) => self.decide(tree, args, &mut env),
+            // A `let` is matched where it's used, so it has no tree.
+            None => Some(0),
+        };
+        if let Some(arm) = arm {
+            let clause_ref = self.ir.clauses(decl_ref)[arm];
+            let clause = self.ir.decl.get(clause_ref).unwrap();
+            let body = self.ir.expr.get(clause.body).unwrap();
+            return Ok((clause_ref, body, env));
+        }
+
+        // The error is the called function's, not its caller's.
+        self.stack.push(decl_ref);
+
+        let args: Vec<_> = args
+            .iter()
+            .map(|arg| self.ctx.wrap(arg).to_string())
+            .collect();
+        Err(self.error(
+            error::NoMatchingPattern,
+            format!("No clause matches {}", args.join(", ")),
+        ))
+    }
+
+    fn apply(&mut self, func: Value<'ir>, arg: Value<'ir>) -> error::Result<Tail<'ir>> {
+        let closure = match func {
+            Value::Func(closure) => closure,
+            other => {
+                let other = self.ctx.wrap(&other).to_string();
+                return Err(self.error(
+                    error::InvalidOperands,
+                    format!("Applied {}, which is not a function", other),
+                ));
+            }
+        };
+
+        match &*closure {
+            Closure::Lambda { patn, body, env } => {
+                let mut env = env.clone();
+                if !self.bind(patn, &arg, &mut env) {
+                    let arg = self.ctx.wrap(&arg).to_string();
+                    return Err(self.error(
+                        error::NoMatchingPattern,
+                        format!("No pattern matches {}", arg),
+                    ));
+                }
+                Ok(Tail::Eval(None, body, env))
+            }
+            Closure::Decl {
+                decl_ref,
+                env,
+                args,
+            } => {
+                let mut args = args.clone();
+                args.push(arg);
+                let decl = self.ir.decl.get(*decl_ref).unwrap();
+                let arity = decl
+                    .sig
+                    .iter()
+                    .filter(|sign| matches!(sign, Sign::Patn(_)))
+                    .count();
+                if args.len() < arity {
+                    return Ok(Tail::Value(Value::Func(Rc::new(Closure::Decl {
+                        decl_ref: *decl_ref,
+                        env: env.clone(),
+                        args,
+                    }))));
+                }
+                let (clause_ref, body, env) = self.call(*decl_ref, env, &args)?;
+                Ok(Tail::Eval(Some(clause_ref), body, env))
+            }
+        }
+    }
+
+    // Walks the tree down to the arm the values select, if any,
+    // binding whatever names that arm's patterns introduce.
+    fn decide(
+        &self,
+        tree: &DecisionTree,
+        values: &[Value<'ir>],
+        env: &mut Env<'ir>,
+    ) -> Option<usize> {
+        let at = |path: &[usize]| {
+            let mut value = &values[path[0]];
+            for &field in path[1..].iter() {
+                value = match value {
+                    Value::Data(_, fields) => &fields[field],
+                    _ => unreachable!("Only data has fields to test."),
+                };
+            }
+            value
+        };
+
+        let mut node = tree.root;
+        loop {
+            match &tree.nodes[node] {
+                Node::Fail => return None,
+                Node::Leaf { arm, bindings } => {
+                    for (id, path) in bindings.iter() {
+                        *env = env.bind(*id, at(path).clone());
+                    }
+                    return Some(*arm);
+                }
+                Node::Switch {
+                    path,
+                    cases,
+                    default,
+                } => {
+                    let value = at(path);
+                    let taken = cases.iter().find(|(case, _)| match (case, value) {
+                        (Case::Cons(cons_ref, arity), Value::Data(data_ref, fields)) => {
+                            cons_ref == data_ref && *arity == fields.len()
+                        }
+                        (Case::Literal(Literal::Unit), Value::Unit) => true,
+                        (Case::Literal(Literal::Number(n)), Value::Number(m)) => n == m,
+                        (Case::Literal(Literal::String(s)), Value::String(t)) => s.as_str() == &**t,
+                        _ => false,
+                    });
+                    node = taken.map_or(*default, |&(_, next)| next);
+                }
+            }
+        }
+    }
+
+    fn bind(&self, patn: &Patn, value: &Value<'ir>, env: &mut Env<'ir>) -> bool {
+        match (patn, value) {
+            (Patn::Spanned(_, patn), _) | (Patn::Annotated(patn, ..), _) => {
+                self.bind(patn, value, env)
+            }
+            (Patn::Empty, _) => true,
+            (Patn::Binding(ids), _) => {
+                for &id in ids.iter() {
+                    *env = env.bind(id, value.clone());
+                }
+                true
+            }
+            (Patn::Literal(Literal::Unit), Value::Unit) => true,
+            (Patn::Literal(Literal::Number(n)), Value::Number(m)) => n == m,
+            (Patn::Literal(Literal::String(s)), Value::String(t)) => s.as_str() == &**t,
+            (Patn::Data(cons_ref, patns), Value::Data(data_ref, values)) => {
+                cons_ref == data_ref
+                    && patns.len() == values.len()
+                    && patns
+                        .iter()
+                        .zip(values.iter())
+                        .all(|(patn, value)| self.bind(patn, value, env))
+            }
+            _ => false,
+        }
+    }
+
+    fn prim(&self, prim: Prim, lhs: Value<'ir>, rhs: Value<'ir>) -> Result<'ir> {
+        Ok(match prim.apply(&lhs, &rhs) {
+            Some(Outcome::Number(n)) => Value::Number(n),
+            Some(Outcome::Truth(b)) => {
+                let cons_ref = if b { self.truth.0 } else { self.truth.1 };
+                Value::Data(cons_ref, Rc::new([]))
+            }
+            None => Err(self.invalid_operands(prim, &lhs, &rhs))?,
+        })
+    }
+
+    fn invalid_operands(&self, prim: Prim, lhs: &Value<'ir>, rhs: &Value<'ir>) -> Box<Diagnostic> {
+        self.error(
+            error::InvalidOperands,
+            prim.invalid_operands(self.ctx.wrap(lhs), self.ctx.wrap(rhs)),
+        )
+    }
+}
+
+// Finds the built-in `True` and `False`, for comparisons to give.
+pub fn truth(ctx: &Context<'_>, ir: &IrStorage) -> (ConsRef, ConsRef) {
+    let names = ctx.names.borrow();
+    let global = match ir.modl.get(ctx.global_modl()) {
+        Some(Modl::Record(record)) => record,
+        _ => unreachable!("The global module is a record."),
+    };
+    let mut cons = builtin::CONSTRUCTORS.iter().map(|&name| {
+        let sig = [Sign::Word(names.get_ident(name).unwrap())];
+        global.symbols.lookup_cons(&sig)[0]
+    });
+    (cons.next().unwrap(), cons.next().unwrap())
+}
+
+// Finds a closed definition with the given name in one of the files.
+pub fn find_entry(ctx: &Context<'_>, modls: &[ModlRef], name: &str) -> error::Result<DeclRef> {
+    let ir = ctx.ir.borrow();
+    let names = ctx.names.borrow();
+    let mut defined = Vec::new();
+    for &modl_ref in modls.iter() {
+        let record = ir.modl.get(modl_ref).unwrap().as_record()?;
+        if let Some(id) = names.get_ident(name) {
+            if let Some(&decl_ref) = record.symbols.lookup_decl(&[Sign::Word(id)]).first() {
+                return Ok(decl_ref);
+            }
+        }
+        defined.extend(record.first_words());
+    }
+
+    let similar = error::similar_names(name, defined.iter().map(|&id| names.get(id).unwrap()));
+    Err(Diagnostic::new(
+        error::UndefinedEntryPoint,
+        format!("No definition named {} to evaluate", name),
+    )
+    .with_suggestions(&similar))?
+}
+
+impl fmt::Display for WithContext<'_, &Value<'_>> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.val {
+            Value::Unit => write!(f, "()"),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::Func(_) => write!(f, "<function>"),
+            Value::Data(cons_ref, args) => {
+                let ir = self.ir.borrow();
+                let names = self.names.borrow();
+                let cons = ir.cons.get(*cons_ref).unwrap();
+                let mut args = args.iter();
+                for (ix, sign) in cons.sig.iter().enumerate() {
+                    if ix > 0 {
+                        write!(f, " ")?;
+                    }
+                    match sign {
+                        Sign::Word(id) => write!(f, "{}", names.get(*id).unwrap())?,
+                        Sign::Patn(()) => {
+                            let arg = args.next().unwrap();
+                            match arg {
+                                Value::Data(_, nested) if !nested.is_empty() => {
+                                    write!(f, "({})", self.wrap(arg))?
+                                }
+                                _ => write!(f, "{}", self.wrap(arg))?,
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode;
+    use crate::ir::{infer, lower};
+    use crate::phases;
+    use crate::vm;
+
+    use bumpalo::Bump;
+
+    fn load<'ctx>(ctx: &'ctx Context<'ctx>, source: &str) -> ModlRef {
+        let modl_ref = phases::process_source(ctx, "tail_calls.fri", source.to_owned()).unwrap();
+        phases::process_aliases(ctx).unwrap();
+        lower::lower_modls(ctx, &[modl_ref]).unwrap();
+        infer::infer_types(ctx);
+        assert_eq!(ctx.error_count(), 0);
+        modl_ref
+    }
+
+    fn interpret<'ctx>(ctx: &'ctx Context<'ctx>, modl_ref: ModlRef, name: &str) -> String {
+        let ir = ctx.ir.borrow();
+        let decl_ref = find_entry(ctx, &[modl_ref], name).unwrap();
+        let value = Interpreter::new(ctx, &ir).eval_decl(decl_ref).unwrap();
+        ctx.wrap(&value).to_string()
+    }
+
+    const SOURCE: &str = "
+        def count (n) from (acc) =
+          match n == 0 | True = acc | False = count (n - 1) from (acc + 1) end
+        def even (n) = match n | 0 = True | _ = odd (n - 1) end
+        def odd (n) = match n | 0 = False | _ = even (n - 1) end
+        def down (0) = 0
+        def down (n) = def next = n - 1 in down next
+
+        def counted = count 1000000 from 0
+        def parity = even 10001
+        def scoped = down 10000
+        def applied = (fun n = count n from 0) 10000
+    ";
+
+    // A million calls deep would overflow the native stack without them.
+    #[test]
+    fn tail_calls_take_constant_stack() {
+        let arena = Bump::new();
+        let ctx = Context::new(&arena);
+        let modl_ref = load(&ctx, SOURCE);
+        assert_eq!(interpret(&ctx, modl_ref, "counted"), "1000000");
+    }
+
+    // The machine gives the same values, without growing its frames either.
+    #[test]
+    fn tail_calls_agree_with_the_machine() {
+        let arena = Bump::new();
+        let ctx = Context::new(&arena);
+        let modl_ref = load(&ctx, SOURCE);
+        let ir = ctx.ir.borrow();
+        let program = bytecode::compile::compile(&ctx, &ir, &[modl_ref]);
+        drop(ir);
+
+        let expected = [("parity", "False"), ("scoped", "0"), ("applied", "10000")];
+        for &(name, expected) in expected.iter() {
+            let value = interpret(&ctx, modl_ref, name);
+            assert_eq!(value, expected, "interpreting {}", name);
+
+            let global = program.find_entry(&ctx, name).unwrap();
+            let value = vm::Machine::new(&ctx, &program).run(global).unwrap();
+            let shown = ctx.wrap((&program, &value)).to_string();
+            assert_eq!(shown, expected, "running {} on the machine", name);
+        }
+    }
+
+    #[test]
+    fn values() {
+        let fib = "def fib (n) = match n | 0 = 0 | 1 = 1 | n = fib (n - 1) + fib (n - 2) end";
+        let list = "con Nil con Cons _, _";
+        let examples = [
+            (format!("{} def main = fib 10", fib), "55"),
+            ("def main = \"friday\"".to_owned(), "\"friday\""),
+            ("def main = ()".to_owned(), "()"),
+            ("def main = 1 == 1".to_owned(), "True"),
+            (
+                "def add (x) = fun y = x + y def main = (add 2) 3".to_owned(),
+                "5",
+            ),
+            (format!("{} def main = Cons 1, Nil", list), "Cons 1 , Nil"),
+            (
+                format!(
+                    "{} def main = match Cons 1, (Cons 2, Nil) | Cons x, (Cons y, _) = x - y | _ = 0 end",
+                    list
+                ),
+                "-1",
+            ),
+        ];
+        for (source, expected) in examples.iter() {
+            assert_eq!(phases::evaluate(source), *expected, "{}", source);
+        }
+    }
+}
@@ -45,6 +45,6 @@ impl<'r> Storage<'r, Ident> for NameTable<'_> {
     type Stored = str;
     type StoredRef = &'r str;
     fn get(&'r self, id: Ident) -> Option<Self::StoredRef> {
-        self.id_to_name.get(&id).map(|s| *s)
+        self.id_to_name.get(&id).copied()
     }
 }
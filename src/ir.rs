@@ -1,15 +1,27 @@
+pub mod coverage;
+pub mod decision;
+pub mod infer;
+pub mod lower;
+pub mod mixfix;
 pub mod symbol;
+pub mod types;
 
 use crate::storage::*;
 
 use crate::ast;
+use crate::builtin::Prim;
 use crate::ctx::{Context, WithContext};
-use crate::error;
+use crate::decision::DecisionTree;
+use crate::doc::DocStorage;
+use crate::error::{self, Diagnostic};
 use crate::id::Ident;
 use crate::refs::*;
+use crate::span::{Span, SpanStorage};
 use crate::symbol::SymbolTable;
+use crate::types::{Type, TypeStorage};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
@@ -21,16 +33,33 @@ pub enum Literal {
 // TODO: this might not be necessary.
 impl Eq for Literal {}
 
+// Numbers are hashed by their bits, so only `NaN` can be equal
+// to a value it doesn't share a hash with, and it's never equal.
+impl std::hash::Hash for Literal {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Literal::Unit => (),
+            Literal::Number(n) => n.to_bits().hash(state),
+            Literal::String(s) => s.hash(state),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Expr {
     Hole,
     Literal(Literal),
+    Local(Ident),
     Var(DeclRef),
     Data(ConsRef, Vec<Expr>),
     Apply(Box<Expr>, Box<Expr>),
     Func(Box<Patn>, Box<Expr>),
-    Match(Box<Expr>, Vec<(Patn, Expr)>),
+    Match(Box<Expr>, Vec<(Patn, Expr)>, Rc<DecisionTree>),
     Scoped(ModlRef, Box<Expr>),
+    Prim(Prim, Vec<Expr>),
+    // Where in the source an expression came from, for diagnostics.
+    Spanned(Span, Box<Expr>),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -39,19 +68,58 @@ pub enum Patn {
     Literal(Literal),
     Binding(Vec<Ident>),
     Data(ConsRef, Vec<Patn>),
+    Spanned(Span, Box<Patn>),
+    // A pattern said to match values of a type, written at the span.
+    Annotated(Box<Patn>, Type, Span),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Decl {
+    // A `let` has no words in its signature, only its pattern.
     pub sig: Vec<Sign<PatnRef>>,
+    pub body: ExprRef,
+    pub result: Option<(Type, Span)>,
+    // What each variable in the declaration's annotations is called.
+    pub type_vars: Vec<Ident>,
+}
+
+impl Decl {
+    pub fn is_let(&self) -> bool {
+        !self.sig.iter().any(|sign| matches!(sign, Sign::Word(_)))
+    }
+
+    // A `let` only has the type of its names when it binds just one.
+    pub fn binds_one(&self, ir: &IrStorage) -> bool {
+        self.sig.iter().any(|sign| match sign {
+            Sign::Patn(patn_ref) => matches!(
+                ir.patn.get(*patn_ref).map(decision::unspanned),
+                Some(Patn::Binding(_))
+            ),
+            Sign::Word(_) => false,
+        })
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Cons {
     pub sig: Vec<Sign>,
+    pub data: DataRef,
+    // The declared type of each field, in terms of the parameters
+    // of its data type, or nothing if they're left to inference.
+    pub fields: Option<Vec<Type>>,
+}
+
+// A closed set of constructors. The constructors declared on their
+// own in a module make up a type too, though it has no name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Data {
+    pub name: Option<Ident>,
+    pub params: Vec<Ident>,
+    pub cons: Vec<ConsRef>,
 }
 
 #[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum Modl {
     Record(ModlRecord),
     Alias(ModlAlias),
@@ -61,12 +129,95 @@ pub enum Modl {
 pub struct ModlRecord {
     pub name: String,
     pub scope: Vec<ModlRef>,
+    pub uses: Vec<ModlUse>,
     pub decls: Vec<DeclRef>,
     pub cons: Vec<ConsRef>,
+    pub data: Vec<DataRef>,
     pub symbols: SymbolTable,
     pub children: HashMap<Ident, ModlRef>,
 }
 
+#[derive(Debug, Clone)]
+pub struct ModlUse {
+    pub modl: ModlRef,
+    pub filter: ImportFilter,
+}
+
+#[derive(Debug, Clone)]
+pub enum ImportFilter {
+    All,
+    Only(Vec<ImportItem>),
+    Hiding(Vec<ImportItem>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportItem {
+    pub leading: usize,
+    pub word: Ident,
+    pub trailing: usize,
+}
+
+impl ImportItem {
+    // A bare word names every signature it begins, or a module.
+    pub fn matches_sig(&self, sig: &[Sign]) -> bool {
+        let leading = sig
+            .iter()
+            .take_while(|&sign| *sign == Sign::Patn(()))
+            .count();
+        if sig.get(leading) != Some(&Sign::Word(self.word)) {
+            return false;
+        }
+        let holes = sig.iter().filter(|&sign| *sign == Sign::Patn(())).count();
+        self.is_bare() || (leading == self.leading && holes == self.leading + self.trailing)
+    }
+
+    pub fn matches_child(&self, id: Ident) -> bool {
+        self.is_bare() && self.word == id
+    }
+
+    fn is_bare(&self) -> bool {
+        self.leading == 0 && self.trailing == 0
+    }
+}
+
+impl ImportFilter {
+    pub fn items(&self) -> &[ImportItem] {
+        match self {
+            ImportFilter::All => &[],
+            ImportFilter::Only(items) | ImportFilter::Hiding(items) => items,
+        }
+    }
+
+    pub fn admits_sig(&self, sig: &[Sign]) -> bool {
+        match self {
+            ImportFilter::All => true,
+            ImportFilter::Only(items) => items.iter().any(|item| item.matches_sig(sig)),
+            ImportFilter::Hiding(items) => !items.iter().any(|item| item.matches_sig(sig)),
+        }
+    }
+
+    pub fn admits_child(&self, id: Ident) -> bool {
+        match self {
+            ImportFilter::All => true,
+            ImportFilter::Only(items) => items.iter().any(|item| item.matches_child(id)),
+            ImportFilter::Hiding(items) => !items.iter().any(|item| item.matches_child(id)),
+        }
+    }
+}
+
+pub static IMPORT_ALL: ImportFilter = ImportFilter::All;
+
+impl ModlRecord {
+    // The word each of the module's operators begins with.
+    pub fn first_words(&self) -> impl Iterator<Item = Ident> + '_ {
+        let decls = self.symbols.iter_decl_signs().map(|(sig, _)| sig);
+        let cons = self.symbols.iter_cons_signs().map(|(sig, _)| sig);
+        decls
+            .chain(cons)
+            .filter_map(|sig| mixfix::words(sig).next())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ModlAlias {
     pub name: String,
@@ -80,8 +231,10 @@ impl Modl {
         Modl::Record(ModlRecord {
             name,
             scope: Vec::new(),
+            uses: Vec::new(),
             decls: Vec::new(),
             cons: Vec::new(),
+            data: Vec::new(),
             symbols: SymbolTable::new(),
             children: HashMap::new(),
         })
@@ -90,32 +243,44 @@ impl Modl {
     pub fn as_record(&self) -> error::Result<&ModlRecord> {
         match self {
             Modl::Record(ref record) => Ok(record),
-            Modl::Alias(_) => Err(error::UnexpectedModuleAlias)?,
+            Modl::Alias(_) => Err(Diagnostic::new(
+                error::UnexpectedModuleAlias,
+                "Expected module record, got alias.",
+            ))?,
         }
     }
 
     pub fn as_record_mut(&mut self) -> error::Result<&mut ModlRecord> {
         match self {
             Modl::Record(ref mut record) => Ok(record),
-            Modl::Alias(_) => Err(error::UnexpectedModuleAlias)?,
+            Modl::Alias(_) => Err(Diagnostic::new(
+                error::UnexpectedModuleAlias,
+                "Expected module record, got alias.",
+            ))?,
         }
     }
 
     pub fn as_alias(&self) -> error::Result<&ModlAlias> {
         match self {
             Modl::Alias(ref alias) => Ok(alias),
-            Modl::Record(_) => Err(error::UnexpectedModuleRecord)?,
+            Modl::Record(_) => Err(Diagnostic::new(
+                error::UnexpectedModuleRecord,
+                "Expected module alias, got record.",
+            ))?,
         }
     }
 
     pub fn as_alias_mut(&mut self) -> error::Result<&mut ModlAlias> {
         match self {
             Modl::Alias(ref mut alias) => Ok(alias),
-            Modl::Record(_) => Err(error::UnexpectedModuleRecord)?,
+            Modl::Record(_) => Err(Diagnostic::new(
+                error::UnexpectedModuleRecord,
+                "Expected module alias, got record.",
+            ))?,
         }
     }
 
-    pub fn new_alias<'ctx>(name: String, scope: ModlRef) -> Self {
+    pub fn new_alias(name: String, scope: ModlRef) -> Self {
         Modl::Alias(ModlAlias {
             name,
             scope,
@@ -148,7 +313,8 @@ impl<T> Sign<T> {
 }
 
 impl<'ctx> ast::Sign<'ctx> {
-    pub fn from_ast(self, ctx: &Context<'ctx>) -> Sign<ast::Patn<'ctx>> {
+    #[allow(clippy::wrong_self_convention)]
+    pub fn from_ast(self, ctx: &Context<'ctx>) -> Sign<ast::Spanned<ast::Patn<'ctx>>> {
         let mut names = ctx.names.borrow_mut();
         match self {
             ast::Sign::Word(ast::Ident(id)) => Sign::Word(names.make_ident(id)),
@@ -163,7 +329,15 @@ pub struct IrStorage {
     pub patn: VecStorage<self::Patn, PatnRef>,
     pub decl: VecStorage<self::Decl, DeclRef>,
     pub cons: VecStorage<self::Cons, ConsRef>,
+    pub data: VecStorage<self::Data, DataRef>,
     pub modl: VecStorage<self::Modl, ModlRef>,
+    // Functions with several clauses, keyed by the first of them.
+    pub clauses: HashStorage<Vec<DeclRef>, DeclRef>,
+    // How each function picks a clause given its arguments.
+    pub decisions: HashStorage<DecisionTree, DeclRef>,
+    pub spans: SpanStorage,
+    pub docs: DocStorage,
+    pub types: TypeStorage,
 }
 
 impl IrStorage {
@@ -173,13 +347,160 @@ impl IrStorage {
             patn: VecStorage::new(),
             decl: VecStorage::new(),
             cons: VecStorage::new(),
+            data: VecStorage::new(),
             modl: VecStorage::new(),
+            clauses: HashStorage::new(),
+            decisions: HashStorage::new(),
+            spans: SpanStorage::new(),
+            docs: DocStorage::new(),
+            types: TypeStorage::new(),
         }
     }
+
+    // Every clause of the function a declaration begins, in source order.
+    pub fn clauses(&self, decl_ref: DeclRef) -> Vec<DeclRef> {
+        match self.clauses.get(decl_ref) {
+            Some(clauses) => clauses.clone(),
+            None => vec![decl_ref],
+        }
+    }
+
+    // The innermost scoped module around each declaration, if any.
+    pub fn scopes(&self) -> HashMap<DeclRef, Option<ModlRef>> {
+        let record = |modl_ref| match self.modl.get(modl_ref) {
+            Some(Modl::Record(record)) => Some(record),
+            _ => None,
+        };
+
+        // Scoped modules are the only records nobody has as a child.
+        let mut children = HashSet::new();
+        for (_, modl) in &self.modl {
+            if let Modl::Record(record) = modl {
+                children.extend(record.children.values().copied());
+            }
+        }
+        let is_scoped = |modl_ref: ModlRef| {
+            let has_parent = record(modl_ref).is_some_and(|r| !r.scope.is_empty());
+            has_parent && !children.contains(&modl_ref)
+        };
+
+        let mut scopes = HashMap::new();
+        for (modl_ref, modl) in &self.modl {
+            if let Modl::Record(owner) = modl {
+                let mut scope = Some(modl_ref);
+                while let Some(scope_ref) = scope {
+                    if is_scoped(scope_ref) {
+                        break;
+                    }
+                    scope = record(scope_ref).and_then(|r| r.scope.first().copied());
+                }
+                for &decl_ref in owner.decls.iter() {
+                    scopes.insert(decl_ref, scope);
+                }
+            }
+        }
+        scopes
+    }
+
+    // The record a module ultimately stands for, once aliases are resolved.
+    pub fn target(&self, modl_ref: ModlRef) -> Option<ModlRef> {
+        match self.modl.get(modl_ref)? {
+            Modl::Record(_) => Some(modl_ref),
+            Modl::Alias(alias) => match self.modl.get(alias.aliased?)? {
+                Modl::Record(_) => alias.aliased,
+                Modl::Alias(_) => None,
+            },
+        }
+    }
+
+    // The records opened by a module's `use` declarations, in order.
+    pub fn opened(&self, modl_ref: ModlRef) -> Vec<(ModlRef, &ModlUse)> {
+        let mut opened = Vec::new();
+        if let Some(Modl::Record(record)) = self.modl.get(modl_ref) {
+            for modl_use in record.uses.iter() {
+                if let Some(target) = self.target(modl_use.modl) {
+                    opened.push((target, modl_use));
+                }
+            }
+        }
+        opened
+    }
+
+    // The records whose names are visible from within a module, grouped
+    // so that each group shadows every group after it: the module itself,
+    // then the modules it opens, then the same again for each enclosing scope.
+    pub fn scope_levels(&self, modl_ref: ModlRef) -> Vec<Vec<(ModlRef, &ImportFilter)>> {
+        let mut levels = Vec::new();
+        let mut seen = Vec::new();
+        let mut current = Some(modl_ref);
+        while let Some(scope_ref) = current {
+            if seen.contains(&scope_ref) {
+                break;
+            }
+            seen.push(scope_ref);
+            levels.push(vec![(scope_ref, &IMPORT_ALL)]);
+            levels.push(
+                self.opened(scope_ref)
+                    .into_iter()
+                    .map(|(target, modl_use)| (target, &modl_use.filter))
+                    .collect(),
+            );
+            current = match self.modl.get(scope_ref) {
+                Some(Modl::Record(record)) => record.scope.first().copied(),
+                _ => None,
+            };
+        }
+        levels
+    }
+
+    // The names of every module which a path could start with.
+    pub fn visible_children(&self, modl_ref: ModlRef) -> Vec<Ident> {
+        let mut children = Vec::new();
+        for &(scope_ref, filter) in self.scope_levels(modl_ref).iter().flatten() {
+            if let Some(Modl::Record(record)) = self.modl.get(scope_ref) {
+                let admitted = record
+                    .children
+                    .keys()
+                    .filter(|&&id| filter.admits_child(id));
+                children.extend(admitted);
+            }
+        }
+        children
+    }
 }
 
 use std::fmt;
 
+impl fmt::Display for WithContext<'_, &ImportItem> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = self.names.borrow();
+        for _ in 0..self.val.leading {
+            write!(f, "_ ")?;
+        }
+        write!(f, "{}", names.get(self.val.word).unwrap())?;
+        for _ in 0..self.val.trailing {
+            write!(f, " _")?;
+        }
+        Ok(())
+    }
+}
+
+impl<T> fmt::Display for WithContext<'_, &[Sign<T>]> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = self.names.borrow();
+        for (ix, sign) in self.val.iter().enumerate() {
+            if ix > 0 {
+                write!(f, " ")?;
+            }
+            match sign {
+                Sign::Word(id) => write!(f, "{}", names.get(*id).unwrap())?,
+                Sign::Patn(_) => write!(f, "_")?,
+            }
+        }
+        Ok(())
+    }
+}
+
 impl fmt::Display for WithContext<'_, &ModlAlias> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let names = self.names.borrow();
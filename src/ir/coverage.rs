@@ -0,0 +1,280 @@
+use crate::ctx::WithContext;
+use crate::decision::{Case, DecisionTree, Node, NodeId, Path};
+use crate::ir::{IrStorage, Literal, Sign};
+use crate::refs::ConsRef;
+use crate::storage::*;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/*
+    A decision tree already knows which arm each value reaches, so
+    coverage is read straight off it. Every case of a switch can be
+    reached, since no part is tested twice on the way down, but its
+    default can't once the cases name every constructor of the type
+    being tested.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum Example {
+    Any,
+    Literal(Literal),
+    Data(ConsRef, Vec<Example>),
+}
+
+#[derive(Debug)]
+pub struct Coverage {
+    // Some values that no arm matches, one for each matched value.
+    pub missing: Option<Vec<Example>>,
+    pub unreachable: Vec<usize>,
+}
+
+// Every constructor of the same type as this one.
+fn siblings(ir: &IrStorage, cons_ref: ConsRef) -> &[ConsRef] {
+    let cons = ir.cons.get(cons_ref).unwrap();
+    ir.data.get(cons.data).map_or(&[], |data| &data.cons[..])
+}
+
+fn arity(ir: &IrStorage, cons_ref: ConsRef) -> usize {
+    let cons = ir.cons.get(cons_ref).unwrap();
+    cons.sig
+        .iter()
+        .filter(|sign| matches!(sign, Sign::Patn(())))
+        .count()
+}
+
+// A constructor the cases leave out, if they can leave any out at all.
+fn uncovered(ir: &IrStorage, cases: &[(Case, NodeId)]) -> Option<Example> {
+    let named: Vec<_> = cases
+        .iter()
+        .filter_map(|(case, _)| match case {
+            Case::Cons(cons_ref, _) => Some(*cons_ref),
+            Case::Literal(_) => None,
+        })
+        .collect();
+    let is_unit = |(case, _): &(Case, NodeId)| *case == Case::Literal(Literal::Unit);
+
+    match named.first() {
+        Some(&cons_ref) => {
+            let missing = siblings(ir, cons_ref)
+                .iter()
+                .find(|sibling| !named.contains(sibling))?;
+            Some(Example::Data(
+                *missing,
+                vec![Example::Any; arity(ir, *missing)],
+            ))
+        }
+        None if cases.iter().any(is_unit) => None,
+        None => Some(Example::Any),
+    }
+}
+
+struct Checker<'a> {
+    ir: &'a IrStorage,
+    tree: &'a DecisionTree,
+    // Nodes known not to lead to a failure from anywhere.
+    exhaustive: HashSet<NodeId>,
+    known: HashMap<Path, Example>,
+}
+
+impl Checker<'_> {
+    fn successors(&self, node: NodeId) -> Vec<NodeId> {
+        match &self.tree.nodes[node] {
+            Node::Fail | Node::Leaf { .. } => Vec::new(),
+            Node::Switch { cases, default, .. } => {
+                let mut next: Vec<_> = cases.iter().map(|&(_, next)| next).collect();
+                if uncovered(self.ir, cases).is_some() {
+                    next.push(*default);
+                }
+                next
+            }
+        }
+    }
+
+    // Looks for a way down to a failure, noting what
+    // each part tested along the way must have been.
+    fn find_failure(&mut self, node: NodeId) -> bool {
+        if self.exhaustive.contains(&node) {
+            return false;
+        }
+
+        let found = match &self.tree.nodes[node] {
+            Node::Fail => true,
+            Node::Leaf { .. } => false,
+            Node::Switch {
+                path,
+                cases,
+                default,
+            } => {
+                let mut found = false;
+                for (case, next) in cases.iter() {
+                    let example = match case {
+                        Case::Cons(cons_ref, _) => Example::Data(*cons_ref, Vec::new()),
+                        Case::Literal(lit) => Example::Literal(lit.clone()),
+                    };
+                    self.known.insert(path.clone(), example);
+                    if self.find_failure(*next) {
+                        found = true;
+                        break;
+                    }
+                }
+                if !found {
+                    if let Some(example) = uncovered(self.ir, cases) {
+                        self.known.insert(path.clone(), example);
+                        found = self.find_failure(*default);
+                    }
+                }
+                if !found {
+                    self.known.remove(path);
+                }
+                found
+            }
+        };
+
+        if !found {
+            self.exhaustive.insert(node);
+        }
+        found
+    }
+
+    fn example_at(&self, path: &mut Path) -> Example {
+        match self.known.get(path) {
+            Some(Example::Data(cons_ref, fields)) if fields.is_empty() => {
+                let fields = (0..arity(self.ir, *cons_ref))
+                    .map(|field| {
+                        path.push(field);
+                        let example = self.example_at(path);
+                        path.pop();
+                        example
+                    })
+                    .collect();
+                Example::Data(*cons_ref, fields)
+            }
+            Some(example) => example.clone(),
+            None => Example::Any,
+        }
+    }
+}
+
+// Checks the arms of a tree over `width` values at once.
+pub fn check(ir: &IrStorage, tree: &DecisionTree, arms: usize, width: usize) -> Coverage {
+    let mut checker = Checker {
+        ir,
+        tree,
+        exhaustive: HashSet::new(),
+        known: HashMap::new(),
+    };
+
+    let mut reached = HashSet::new();
+    let mut stack = vec![tree.root];
+    while let Some(node) = stack.pop() {
+        if reached.insert(node) {
+            stack.extend(checker.successors(node));
+        }
+    }
+    let mut unreachable: Vec<_> = (0..arms).collect();
+    for &node in reached.iter() {
+        if let Node::Leaf { arm, .. } = tree.nodes[node] {
+            unreachable.retain(|&other| other != arm);
+        }
+    }
+
+    let missing = if checker.find_failure(tree.root) {
+        Some(
+            (0..width)
+                .map(|ix| checker.example_at(&mut vec![ix]))
+                .collect(),
+        )
+    } else {
+        None
+    };
+
+    Coverage {
+        missing,
+        unreachable,
+    }
+}
+
+// Shows the examples in place of the placeholders of a signature.
+impl<T> fmt::Display for WithContext<'_, (&[Sign<T>], &[Example])> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (sig, examples) = self.val;
+        let mut examples = examples.iter();
+        for (ix, sign) in sig.iter().enumerate() {
+            if ix > 0 {
+                write!(f, " ")?;
+            }
+            match sign {
+                Sign::Word(id) => write!(f, "{}", self.names.borrow().get(*id).unwrap())?,
+                Sign::Patn(_) => match examples.next().unwrap() {
+                    example @ Example::Data(_, fields) if !fields.is_empty() => {
+                        write!(f, "({})", self.wrap(example))?
+                    }
+                    example => write!(f, "{}", self.wrap(example))?,
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for WithContext<'_, &Example> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.val {
+            Example::Any => write!(f, "_"),
+            Example::Literal(Literal::Unit) => write!(f, "()"),
+            Example::Literal(Literal::Number(n)) => write!(f, "{}", n),
+            Example::Literal(Literal::String(s)) => write!(f, "{:?}", s),
+            Example::Data(cons_ref, fields) => {
+                let sig = self.ir.borrow().cons.get(*cons_ref).unwrap().sig.clone();
+                write!(f, "{}", self.wrap((&sig[..], &fields[..])))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ctx::Context;
+    use crate::error::{NonExhaustivePatterns, Severity, UnreachablePattern};
+    use crate::phases;
+
+    use bumpalo::Bump;
+
+    // What's reported about the matches in the source, as text.
+    fn messages(source: &str) -> Vec<(Severity, String)> {
+        let arena = Bump::new();
+        let ctx = Context::new(&arena);
+        let source = format!("con Nil con Cons _, _ {}", source);
+        let codes = phases::check_source(&ctx, &source);
+        let diagnostics = ctx.diagnostics.borrow();
+        let expected = |code| matches!(code, NonExhaustivePatterns | UnreachablePattern);
+        assert!(codes.into_iter().all(expected), "{}", source);
+        let shown = diagnostics
+            .iter()
+            .map(|diag| (diag.severity, diag.message.clone()));
+        shown.collect()
+    }
+
+    #[test]
+    fn missing_and_unreachable_patterns() {
+        let missing = |message: &str| vec![(Severity::Error, message.to_owned())];
+        assert_eq!(
+            messages("def f (n) = match n | 0 = 0 | 1 = 1 end"),
+            missing("This match doesn't cover `_`")
+        );
+        assert_eq!(
+            messages("def first (Cons x, _) = x"),
+            missing("The clauses of first _ don't cover `first Nil`")
+        );
+        assert_eq!(
+            messages("def zip (Nil) with (Nil) = 0 def zip (Cons _, _) with (_) = 1"),
+            missing("The clauses of zip _ with _ don't cover `zip Nil with (Cons _ , _)`")
+        );
+        assert_eq!(
+            messages("def g (n) = match n | _ = 0 | 1 = 1 end"),
+            [(Severity::Warning, "This arm can never be used".to_owned())]
+        );
+        assert_eq!(messages("def len (Nil) = 0 def len (Cons _, xs) = 1"), []);
+        assert_eq!(messages("def f (n) = match n | 0 = 0 | n = n end"), []);
+    }
+}
@@ -0,0 +1,323 @@
+use crate::id::Ident;
+use crate::ir::{IrStorage, Literal, Patn, Sign};
+use crate::refs::{ConsRef, DeclRef};
+use crate::storage::*;
+
+use std::collections::HashMap;
+
+/*
+    A decision tree tests the parts of the values being matched
+    one at a time, so that no part is ever examined twice. The
+    same subtree is often reached along several branches, so
+    identical nodes are only stored once and shared by index.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecisionTree {
+    pub nodes: Vec<Node>,
+    pub root: NodeId,
+}
+
+pub type NodeId = usize;
+
+// Where a part lives: which of the values being matched, and then
+// which field to take at each constructor on the way down to it.
+pub type Path = Vec<usize>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Node {
+    Fail,
+    // The arm chosen, and where to find each of the names it binds.
+    Leaf {
+        arm: usize,
+        bindings: Vec<(Ident, Path)>,
+    },
+    Switch {
+        path: Path,
+        cases: Vec<(Case, NodeId)>,
+        default: NodeId,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Case {
+    Cons(ConsRef, usize),
+    Literal(Literal),
+}
+
+// Where a pattern was written, or what type it was said to have,
+// makes no difference to what it matches.
+pub fn unspanned(mut patn: &Patn) -> &Patn {
+    while let Patn::Spanned(_, inner) | Patn::Annotated(inner, ..) = patn {
+        patn = inner;
+    }
+    patn
+}
+
+#[derive(Debug, Clone)]
+struct Row<'p> {
+    tests: Vec<(Path, &'p Patn)>,
+    bindings: Vec<(Ident, Path)>,
+    arm: usize,
+}
+
+impl<'p> Row<'p> {
+    // Wildcards and bindings can't fail, so they never need testing.
+    fn simplify(&mut self) {
+        let bindings = &mut self.bindings;
+        self.tests.retain(|(path, patn)| match patn {
+            Patn::Empty => false,
+            Patn::Binding(ids) => {
+                bindings.extend(ids.iter().map(|&id| (id, path.clone())));
+                false
+            }
+            Patn::Literal(_) | Patn::Data(..) => true,
+            Patn::Spanned(..) | Patn::Annotated(..) => {
+                unreachable!("Spans and annotations are removed before testing.")
+            }
+        });
+    }
+
+    fn case_at(&self, path: &[usize]) -> Option<Case> {
+        let (_, patn) = self.tests.iter().find(|(at, _)| at[..] == *path)?;
+        match patn {
+            Patn::Data(cons_ref, args) => Some(Case::Cons(*cons_ref, args.len())),
+            Patn::Literal(lit) => Some(Case::Literal(lit.clone())),
+            Patn::Empty | Patn::Binding(_) | Patn::Spanned(..) | Patn::Annotated(..) => None,
+        }
+    }
+
+    // The row as it stands once the part at `path` is known to
+    // match `case`, or nothing if the row can no longer match.
+    fn specialize(&self, path: &[usize], case: &Case) -> Option<Self> {
+        let ix = match self.tests.iter().position(|(at, _)| at[..] == *path) {
+            Some(ix) => ix,
+            None => return Some(self.clone()),
+        };
+        if self.case_at(path).as_ref() != Some(case) {
+            return None;
+        }
+
+        let mut row = self.clone();
+        let (_, patn) = row.tests.remove(ix);
+        if let Patn::Data(_, args) = patn {
+            let fields = args.iter().enumerate().map(|(field, arg)| {
+                let mut field_path = path.to_vec();
+                field_path.push(field);
+                (field_path, unspanned(arg))
+            });
+            row.tests.splice(ix..ix, fields);
+        }
+        Some(row)
+    }
+}
+
+struct Builder {
+    nodes: Vec<Node>,
+    shared: HashMap<Node, NodeId>,
+}
+
+impl Builder {
+    fn node(&mut self, node: Node) -> NodeId {
+        if let Some(&id) = self.shared.get(&node) {
+            return id;
+        }
+        let id = self.nodes.len();
+        self.nodes.push(node.clone());
+        self.shared.insert(node, id);
+        id
+    }
+
+    fn build(&mut self, mut rows: Vec<Row<'_>>) -> NodeId {
+        for row in rows.iter_mut() {
+            row.simplify();
+        }
+
+        // Rows are tried in order, and the first can only be
+        // passed over once one of its own tests has failed.
+        let first = match rows.first() {
+            Some(first) => first,
+            None => return self.node(Node::Fail),
+        };
+        let path = match first.tests.first() {
+            Some((path, _)) => path.clone(),
+            None => {
+                return self.node(Node::Leaf {
+                    arm: first.arm,
+                    bindings: first.bindings.clone(),
+                })
+            }
+        };
+
+        let mut cases = Vec::new();
+        for case in rows.iter().filter_map(|row| row.case_at(&path)) {
+            if !cases.contains(&case) {
+                cases.push(case);
+            }
+        }
+
+        let cases = cases
+            .into_iter()
+            .map(|case| {
+                let rows = rows
+                    .iter()
+                    .filter_map(|row| row.specialize(&path, &case))
+                    .collect();
+                (case, self.build(rows))
+            })
+            .collect();
+        let rest = rows
+            .iter()
+            .filter(|row| row.case_at(&path).is_none())
+            .cloned()
+            .collect();
+        let default = self.build(rest);
+
+        self.node(Node::Switch {
+            path,
+            cases,
+            default,
+        })
+    }
+}
+
+// Each arm gives one pattern for each of the values being matched.
+pub fn compile<'p>(arms: impl IntoIterator<Item = Vec<&'p Patn>>) -> DecisionTree {
+    let rows = arms
+        .into_iter()
+        .enumerate()
+        .map(|(arm, patns)| Row {
+            tests: patns
+                .into_iter()
+                .enumerate()
+                .map(|(ix, patn)| (vec![ix], unspanned(patn)))
+                .collect(),
+            bindings: Vec::new(),
+            arm,
+        })
+        .collect();
+
+    let mut builder = Builder {
+        nodes: Vec::new(),
+        shared: HashMap::new(),
+    };
+    let root = builder.build(rows);
+    DecisionTree {
+        nodes: builder.nodes,
+        root,
+    }
+}
+
+// A function's clauses each give one pattern per argument.
+pub fn compile_clauses(ir: &IrStorage, decl_ref: DeclRef) -> DecisionTree {
+    let clauses = ir.clauses(decl_ref);
+    let arms = clauses.iter().map(|&clause_ref| {
+        let clause = ir.decl.get(clause_ref).unwrap();
+        clause
+            .sig
+            .iter()
+            .filter_map(|sign| match sign {
+                Sign::Patn(patn_ref) => ir.patn.get(*patn_ref),
+                Sign::Word(_) => None,
+            })
+            .collect()
+    });
+    compile(arms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Follows the tree down to an arm, given which case each part matches.
+    fn walk(tree: &DecisionTree, parts: &[(Path, Case)]) -> Option<usize> {
+        let mut id = tree.root;
+        let mut tested = Vec::new();
+        loop {
+            match &tree.nodes[id] {
+                Node::Fail => return None,
+                Node::Leaf { arm, .. } => return Some(*arm),
+                Node::Switch {
+                    path,
+                    cases,
+                    default,
+                } => {
+                    assert!(!tested.contains(path), "{:?} is tested twice", path);
+                    tested.push(path.clone());
+                    let case = parts.iter().find(|(at, _)| at == path).map(|(_, c)| c);
+                    let next = cases.iter().find(|(c, _)| Some(c) == case);
+                    id = next.map_or(*default, |&(_, next)| next);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parts_are_tested_once() {
+        let (nil, cons) = (ConsRef::from(0), ConsRef::from(1));
+        let bind = |id: usize| Patn::Binding(vec![Ident::from(id)]);
+        let cons_patn = |head: usize, tail: usize| Patn::Data(cons, vec![bind(head), bind(tail)]);
+        let nil_patn = Patn::Data(nil, Vec::new());
+        let (first, second) = (cons_patn(0, 1), cons_patn(2, 3));
+        let arms = vec![
+            vec![&nil_patn, &Patn::Empty],
+            vec![&Patn::Empty, &nil_patn],
+            vec![&first, &second],
+        ];
+        let tree = compile(arms);
+
+        let (is_nil, is_cons) = (Case::Cons(nil, 0), Case::Cons(cons, 2));
+        let arm = |first: &Case, second: &Case| {
+            walk(
+                &tree,
+                &[(vec![0], first.clone()), (vec![1], second.clone())],
+            )
+        };
+        assert_eq!(arm(&is_nil, &is_cons), Some(0));
+        assert_eq!(arm(&is_nil, &is_nil), Some(0));
+        assert_eq!(arm(&is_cons, &is_nil), Some(1));
+        assert_eq!(arm(&is_cons, &is_cons), Some(2));
+
+        // The second arm is reached after the first list is found to be
+        // a `Cons`, and by default, through the one node.
+        for (ix, node) in tree.nodes.iter().enumerate() {
+            assert!(
+                !tree.nodes[..ix].contains(node),
+                "{:?} is stored twice",
+                node
+            );
+        }
+        let leaf = |arm| {
+            let found = tree.nodes.iter().position(|node| match node {
+                Node::Leaf { arm: found, .. } => *found == arm,
+                _ => false,
+            });
+            found.unwrap()
+        };
+        let reaching_second = tree.nodes.iter().filter(|node| match node {
+            Node::Switch { cases, .. } => cases.iter().any(|&(_, next)| next == leaf(1)),
+            _ => false,
+        });
+        assert_eq!(reaching_second.count(), 2);
+
+        let bindings = match &tree.nodes[leaf(2)] {
+            Node::Leaf { bindings, .. } => bindings.clone(),
+            _ => unreachable!(),
+        };
+        let paths = [vec![0, 0], vec![0, 1], vec![1, 0], vec![1, 1]];
+        let expected: Vec<_> = paths
+            .iter()
+            .enumerate()
+            .map(|(id, path)| (Ident::from(id), path.clone()))
+            .collect();
+        assert_eq!(bindings, expected);
+
+        let literal = |n| Patn::Literal(Literal::Number(n));
+        let (zero, one) = (literal(0.0), literal(1.0));
+        let tree = compile(vec![vec![&zero], vec![&one], vec![&Patn::Empty]]);
+        let number = |n| walk(&tree, &[(vec![0], Case::Literal(Literal::Number(n)))]);
+        assert_eq!(
+            (number(0.0), number(1.0), number(2.0)),
+            (Some(0), Some(1), Some(2))
+        );
+    }
+}
@@ -0,0 +1,925 @@
+use crate::builtin::Prim;
+use crate::ctx::Context;
+use crate::error::{self, Diagnostic};
+use crate::id::Ident;
+use crate::ir::types::{Scheme, Type, TypeCon, TypeNames, TypeVar};
+use crate::ir::{Expr, IrStorage, Literal, Modl, Patn, Sign};
+use crate::refs::*;
+use crate::span::Span;
+use crate::storage::*;
+
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone)]
+enum VarState {
+    Bound(Type),
+    // Unbound, at the level of the declarations it was made for.
+    Free(usize),
+}
+
+enum Failure {
+    Mismatch,
+    Infinite(TypeVar, Type),
+}
+
+/*
+    Declarations are inferred a strongly connected component at a
+    time, so that each is generalized before anything else uses it,
+    while those which depend on one another are inferred together.
+    Variables made for a component are at a deeper level than any
+    made outside it, and only those still that deep afterwards are
+    free to generalize.
+
+    Constructors declared on their own, rather than as part of a data
+    type, make up a type with the others in their module, and the types
+    of their fields are inferred from how they're used throughout the
+    program instead.
+*/
+struct Infer<'ctx, 'ir> {
+    ctx: &'ctx Context<'ctx>,
+    ir: &'ir IrStorage,
+    vars: Vec<VarState>,
+    level: usize,
+    // Declarations within each scoped module, or outside of any.
+    scopes: HashMap<Option<ModlRef>, Vec<DeclRef>>,
+    // Every clause of a function is inferred as part of its first.
+    firsts: HashMap<DeclRef, DeclRef>,
+    // Types of the declarations being inferred, which can't be
+    // generalized until all of their component is done.
+    mono: HashMap<DeclRef, Type>,
+    schemes: HashMap<DeclRef, Scheme>,
+    // The fields of constructors declared on their own.
+    fields: HashMap<ConsRef, Vec<Type>>,
+    truth: Type,
+    // The variables named in the annotations of each function, and
+    // what those of the clause being inferred stand for.
+    annotated: HashMap<DeclRef, Vec<(Ident, Type)>>,
+    clause_vars: Vec<Type>,
+    locals: Vec<(Ident, Type)>,
+    span: Option<Span>,
+}
+
+pub fn infer_types<'ctx>(ctx: &'ctx Context<'ctx>) {
+    let ir = ctx.ir.borrow();
+    let mut infer = Infer::new(ctx, &ir);
+    let globals = infer.scopes.get(&None).cloned().unwrap_or_default();
+    infer.infer_decls(&globals);
+
+    let mut decl_types = Vec::new();
+    for (&first, scheme) in infer.schemes.iter() {
+        let scheme = Scheme {
+            vars: scheme.vars.clone(),
+            ty: infer.zonk(&scheme.ty),
+        };
+        for clause_ref in ir.clauses(first) {
+            decl_types.push((clause_ref, scheme.clone()));
+        }
+    }
+    let mut cons_types = Vec::new();
+    for (cons_ref, cons) in &ir.cons {
+        let params = ir.data.get(cons.data).map_or(0, |data| data.params.len());
+        let result = Type::Con(
+            TypeCon::Data(cons.data),
+            (0..params).map(Type::Var).collect(),
+        );
+        let scheme = match &cons.fields {
+            Some(fields) => Scheme {
+                vars: (0..params).collect(),
+                ty: Type::func(fields.iter().cloned(), result),
+            },
+            None => {
+                let fields = infer.fields[&cons_ref]
+                    .iter()
+                    .map(|field| infer.zonk(field));
+                Scheme {
+                    vars: Vec::new(),
+                    ty: Type::func(fields, result),
+                }
+            }
+        };
+        cons_types.push((cons_ref, scheme));
+    }
+    drop(ir);
+
+    let mut ir = ctx.ir.borrow_mut();
+    for (decl_ref, scheme) in decl_types {
+        ir.types.decl.set(decl_ref, scheme);
+    }
+    for (cons_ref, scheme) in cons_types {
+        ir.types.cons.set(cons_ref, scheme);
+    }
+}
+
+fn literal_type(lit: &Literal) -> Type {
+    Type::base(match lit {
+        Literal::Unit => TypeCon::Unit,
+        Literal::Number(_) => TypeCon::Number,
+        Literal::String(_) => TypeCon::String,
+    })
+}
+
+fn patns<'ir>(ir: &'ir IrStorage, sig: &[Sign<PatnRef>]) -> Vec<&'ir Patn> {
+    sig.iter()
+        .filter_map(|sign| match sign {
+            Sign::Patn(patn_ref) => ir.patn.get(*patn_ref),
+            Sign::Word(_) => None,
+        })
+        .collect()
+}
+
+impl<'ctx, 'ir> Infer<'ctx, 'ir> {
+    fn new(ctx: &'ctx Context<'ctx>, ir: &'ir IrStorage) -> Self {
+        let mut scopes: HashMap<_, Vec<_>> = HashMap::new();
+        let mut in_scope: Vec<_> = ir.scopes().into_iter().collect();
+        in_scope.sort_by_key(|&(decl_ref, _)| usize::from(decl_ref));
+        for (decl_ref, scope) in in_scope {
+            if ir.decl.get(decl_ref).is_some() {
+                scopes.entry(scope).or_default().push(decl_ref);
+            }
+        }
+
+        let mut firsts = HashMap::new();
+        for (first, clauses) in &ir.clauses {
+            for &clause_ref in clauses.iter() {
+                firsts.insert(clause_ref, first);
+            }
+        }
+
+        let bool_id = ctx.names.borrow_mut().make_ident("Bool");
+        let global = ir.modl.get(ctx.global_modl()).unwrap();
+        let truth = match global {
+            Modl::Record(record) => record.symbols.lookup_type(bool_id),
+            Modl::Alias(_) => None,
+        };
+
+        let mut infer = Infer {
+            ctx,
+            ir,
+            vars: Vec::new(),
+            level: 0,
+            scopes,
+            firsts,
+            mono: HashMap::new(),
+            schemes: HashMap::new(),
+            fields: HashMap::new(),
+            truth: Type::base(truth.expect("`Bool` is a builtin type.")),
+            annotated: HashMap::new(),
+            clause_vars: Vec::new(),
+            locals: Vec::new(),
+            span: None,
+        };
+
+        // Fields are made at the outermost level, so they're never generalized.
+        for (cons_ref, cons) in &ir.cons {
+            if cons.fields.is_none() {
+                let arity = cons
+                    .sig
+                    .iter()
+                    .filter(|sign| **sign == Sign::Patn(()))
+                    .count();
+                let fields = (0..arity).map(|_| infer.fresh()).collect();
+                infer.fields.insert(cons_ref, fields);
+            }
+        }
+        infer
+    }
+
+    fn first(&self, decl_ref: DeclRef) -> DeclRef {
+        self.firsts.get(&decl_ref).copied().unwrap_or(decl_ref)
+    }
+
+    // The types of a constructor's fields and of the value
+    // it makes, with fresh arguments for its type's parameters.
+    fn instantiate_cons(&mut self, cons_ref: ConsRef) -> (Vec<Type>, Type) {
+        let cons = self.ir.cons.get(cons_ref).unwrap();
+        let params = self
+            .ir
+            .data
+            .get(cons.data)
+            .map_or(0, |data| data.params.len());
+        let args: Vec<_> = (0..params).map(|_| self.fresh()).collect();
+        let fields = match &cons.fields {
+            Some(fields) => fields
+                .iter()
+                .map(|field| self.declared(field, &args))
+                .collect(),
+            None => self.fields[&cons_ref].clone(),
+        };
+        (fields, Type::Con(TypeCon::Data(cons.data), args))
+    }
+
+    // Declared types refer to parameters by position. Any other
+    // variable stands in for a type which failed to lower.
+    fn declared(&mut self, ty: &Type, args: &[Type]) -> Type {
+        match ty {
+            Type::Var(param) => match args.get(*param) {
+                Some(arg) => arg.clone(),
+                None => self.fresh(),
+            },
+            Type::Con(con, con_args) => Type::Con(
+                *con,
+                con_args
+                    .iter()
+                    .map(|arg| self.declared(arg, args))
+                    .collect(),
+            ),
+            Type::Func(param, result) => Type::Func(
+                Box::new(self.declared(param, args)),
+                Box::new(self.declared(result, args)),
+            ),
+        }
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.vars.push(VarState::Free(self.level));
+        Type::Var(self.vars.len() - 1)
+    }
+
+    // Follows bound variables until reaching some other type.
+    fn resolve(&self, ty: &Type) -> Type {
+        match ty {
+            Type::Var(var) => match &self.vars[*var] {
+                VarState::Bound(bound) => self.resolve(bound),
+                VarState::Free(_) => ty.clone(),
+            },
+            _ => ty.clone(),
+        }
+    }
+
+    // Replaces every bound variable throughout a type.
+    fn zonk(&self, ty: &Type) -> Type {
+        match self.resolve(ty) {
+            Type::Var(var) => Type::Var(var),
+            Type::Con(con, args) => Type::Con(con, args.iter().map(|arg| self.zonk(arg)).collect()),
+            Type::Func(param, result) => {
+                Type::Func(Box::new(self.zonk(&param)), Box::new(self.zonk(&result)))
+            }
+        }
+    }
+
+    // Whether a variable occurs within a type. Any variables deeper
+    // than it are brought up to its level along the way, since
+    // they'll no longer be free to generalize independently of it.
+    fn occurs(&mut self, var: TypeVar, level: usize, ty: &Type) -> bool {
+        match self.resolve(ty) {
+            Type::Var(other) => {
+                if let VarState::Free(other_level) = &mut self.vars[other] {
+                    *other_level = (*other_level).min(level);
+                }
+                other == var
+            }
+            Type::Con(_, args) => args.iter().any(|arg| self.occurs(var, level, arg)),
+            Type::Func(param, result) => {
+                self.occurs(var, level, &param) | self.occurs(var, level, &result)
+            }
+        }
+    }
+
+    fn bind(&mut self, var: TypeVar, ty: &Type) -> Result<(), Failure> {
+        let level = match self.vars[var] {
+            VarState::Free(level) => level,
+            VarState::Bound(_) => unreachable!("Only free variables are bound."),
+        };
+        if self.occurs(var, level, ty) {
+            return Err(Failure::Infinite(var, ty.clone()));
+        }
+        self.vars[var] = VarState::Bound(ty.clone());
+        Ok(())
+    }
+
+    fn unify_types(&mut self, a: &Type, b: &Type) -> Result<(), Failure> {
+        match (self.resolve(a), self.resolve(b)) {
+            (Type::Var(a), Type::Var(b)) if a == b => Ok(()),
+            (Type::Var(var), ty) | (ty, Type::Var(var)) => self.bind(var, &ty),
+            (Type::Con(a, a_args), Type::Con(b, b_args))
+                if a == b && a_args.len() == b_args.len() =>
+            {
+                for (a_arg, b_arg) in a_args.iter().zip(b_args.iter()) {
+                    self.unify_types(a_arg, b_arg)?;
+                }
+                Ok(())
+            }
+            (Type::Func(a_param, a_result), Type::Func(b_param, b_result)) => {
+                self.unify_types(&a_param, &b_param)?;
+                self.unify_types(&a_result, &b_result)
+            }
+            _ => Err(Failure::Mismatch),
+        }
+    }
+
+    // Reports a failure at `at`, pointing out where the expected type
+    // came from too, when that's known and somewhere else.
+    fn unify(&mut self, expected: &Type, actual: &Type, at: Option<Span>, origin: Option<Span>) {
+        let failure = match self.unify_types(expected, actual) {
+            Ok(()) => return,
+            Err(failure) => failure,
+        };
+
+        let mut names = TypeNames::default();
+        let mut diag = match failure {
+            Failure::Mismatch => {
+                let expected = names.show(self.ctx, &self.zonk(expected));
+                let actual = names.show(self.ctx, &self.zonk(actual));
+                let diag = Diagnostic::new(
+                    error::MismatchedTypes,
+                    format!("Expected `{}`, but found `{}`", expected, actual),
+                );
+                let diag = match at {
+                    Some(at) => diag.with_primary(at, format!("this is `{}`", actual)),
+                    None => diag,
+                };
+                match origin {
+                    Some(origin) if Some(origin) != at => diag.with_label(
+                        origin,
+                        format!("`{}` is expected because of this", expected),
+                    ),
+                    _ => diag,
+                }
+            }
+            Failure::Infinite(var, ty) => {
+                let var = names.show(self.ctx, &Type::Var(var));
+                let ty = names.show(self.ctx, &self.zonk(&ty));
+                let diag = Diagnostic::new(
+                    error::InfiniteType,
+                    format!("`{}` would have to be the infinite type `{}`", var, ty),
+                )
+                .with_note("A value can't contain itself, or be applied to itself.");
+                let diag = match at {
+                    Some(at) => diag.with_primary(at, format!("`{}` is needed here", ty)),
+                    None => diag,
+                };
+                match origin {
+                    Some(origin) if Some(origin) != at => {
+                        diag.with_label(origin, format!("`{}` comes from here", var))
+                    }
+                    _ => diag,
+                }
+            }
+        };
+        if diag.primary.is_none() {
+            if let Some(span) = self.span {
+                diag = diag.with_primary(span, "in this declaration");
+            }
+        }
+        self.ctx.report(diag);
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        let fresh: HashMap<_, _> = scheme.vars.iter().map(|&var| (var, self.fresh())).collect();
+        self.substitute(&scheme.ty, &fresh)
+    }
+
+    fn substitute(&self, ty: &Type, fresh: &HashMap<TypeVar, Type>) -> Type {
+        match self.resolve(ty) {
+            Type::Var(var) => fresh.get(&var).cloned().unwrap_or(Type::Var(var)),
+            Type::Con(con, args) => Type::Con(
+                con,
+                args.iter().map(|arg| self.substitute(arg, fresh)).collect(),
+            ),
+            Type::Func(param, result) => Type::Func(
+                Box::new(self.substitute(&param, fresh)),
+                Box::new(self.substitute(&result, fresh)),
+            ),
+        }
+    }
+
+    fn generalize(&self, ty: &Type) -> Scheme {
+        fn collect(infer: &Infer<'_, '_>, ty: &Type, vars: &mut Vec<TypeVar>) {
+            match ty {
+                Type::Var(var) => {
+                    let deeper =
+                        matches!(infer.vars[*var], VarState::Free(level) if level > infer.level);
+                    if deeper && !vars.contains(var) {
+                        vars.push(*var);
+                    }
+                }
+                Type::Con(_, args) => args.iter().for_each(|arg| collect(infer, arg, vars)),
+                Type::Func(param, result) => {
+                    collect(infer, param, vars);
+                    collect(infer, result, vars);
+                }
+            }
+        }
+
+        let ty = self.zonk(ty);
+        let mut vars = Vec::new();
+        collect(self, &ty, &mut vars);
+        Scheme { vars, ty }
+    }
+
+    fn infer_decls(&mut self, decls: &[DeclRef]) {
+        let firsts: Vec<_> = decls
+            .iter()
+            .copied()
+            .filter(|&decl_ref| self.first(decl_ref) == decl_ref)
+            .collect();
+
+        let mut deps = HashMap::new();
+        for &first in firsts.iter() {
+            let mut refs = Vec::new();
+            for clause_ref in self.ir.clauses(first) {
+                let decl = self.ir.decl.get(clause_ref).unwrap();
+                self.references(self.ir.expr.get(decl.body).unwrap(), &mut refs);
+            }
+            refs.retain(|dep| firsts.contains(dep));
+            deps.insert(first, refs);
+        }
+
+        for component in components(&firsts, &deps) {
+            self.level += 1;
+            for &first in component.iter() {
+                let ty = self.fresh();
+                self.mono.insert(first, ty);
+            }
+            for &first in component.iter() {
+                let ty = self.infer_decl(first);
+                let mono = self.mono[&first].clone();
+                let span = self.ir.spans.decl.get(first).copied();
+                self.unify(&mono, &ty, span, None);
+            }
+            self.level -= 1;
+
+            for first in component {
+                let mono = self.mono.remove(&first).unwrap();
+                let scheme = self.generalize(&mono);
+                self.schemes.insert(first, scheme);
+                self.check_annotations(first);
+            }
+        }
+    }
+
+    // The variables a clause's annotations name, which are
+    // shared with every other clause of the same function.
+    fn annotation_vars(&mut self, first: DeclRef, clause_ref: DeclRef) -> Vec<Type> {
+        let clause = self.ir.decl.get(clause_ref).unwrap();
+        let mut vars = Vec::new();
+        for &id in clause.type_vars.iter() {
+            let named = self.annotated.get(&first).into_iter().flatten();
+            let var = match named.clone().find(|(other, _)| *other == id) {
+                Some((_, var)) => var.clone(),
+                None => {
+                    let var = self.fresh();
+                    let named = self.annotated.entry(first).or_default();
+                    named.push((id, var.clone()));
+                    var
+                }
+            };
+            vars.push(var);
+        }
+        vars
+    }
+
+    fn annotation(&mut self, ty: &Type) -> Type {
+        let vars = self.clause_vars.clone();
+        self.declared(ty, &vars)
+    }
+
+    // A variable in an annotation promises that any type will do, so
+    // it must still be free to generalize, and distinct from the rest.
+    fn check_annotations(&mut self, first: DeclRef) {
+        let named = self.annotated.remove(&first).unwrap_or_default();
+        let mut general: Vec<(TypeVar, Ident)> = Vec::new();
+        for (id, ty) in named {
+            let names = self.ctx.names.borrow();
+            let problem = match self.resolve(&ty) {
+                Type::Var(var) => match general.iter().find(|&&(other, _)| other == var) {
+                    Some(&(_, other)) => {
+                        format!("it must be the same as `{}`", names.get(other).unwrap())
+                    }
+                    None if matches!(self.vars[var], VarState::Free(level) if level > self.level) =>
+                    {
+                        general.push((var, id));
+                        continue;
+                    }
+                    None => "it depends on the types around the declaration".to_owned(),
+                },
+                ty => {
+                    let ty = TypeNames::default().show(self.ctx, &self.zonk(&ty));
+                    format!("it must be `{}`", ty)
+                }
+            };
+            let diag = Diagnostic::new(
+                error::MismatchedTypes,
+                format!(
+                    "The annotations say `{}` can be any type, but {}",
+                    names.get(id).unwrap(),
+                    problem
+                ),
+            );
+            drop(names);
+            self.ctx.report(match self.ir.spans.decl.get(first) {
+                Some(&span) => diag.with_primary(span, "in this declaration"),
+                None => diag,
+            });
+        }
+    }
+
+    // The declarations an expression refers to, including
+    // through the declarations of any scoped modules within it.
+    fn references(&self, expr: &Expr, refs: &mut Vec<DeclRef>) {
+        match expr {
+            Expr::Hole | Expr::Literal(_) | Expr::Local(_) => {}
+            Expr::Var(decl_ref) => refs.push(self.first(*decl_ref)),
+            Expr::Data(_, args) | Expr::Prim(_, args) => {
+                args.iter().for_each(|arg| self.references(arg, refs))
+            }
+            Expr::Apply(func, arg) => {
+                self.references(func, refs);
+                self.references(arg, refs);
+            }
+            Expr::Func(_, body) | Expr::Spanned(_, body) => self.references(body, refs),
+            Expr::Match(scrut, arms, _) => {
+                self.references(scrut, refs);
+                arms.iter()
+                    .for_each(|(_, body)| self.references(body, refs));
+            }
+            Expr::Scoped(modl_ref, body) => {
+                for &decl_ref in self.scopes.get(&Some(*modl_ref)).into_iter().flatten() {
+                    let decl = self.ir.decl.get(decl_ref).unwrap();
+                    self.references(self.ir.expr.get(decl.body).unwrap(), refs);
+                }
+                self.references(body, refs);
+            }
+        }
+    }
+
+    fn infer_decl(&mut self, first: DeclRef) -> Type {
+        let ir = self.ir;
+        let mark = self.locals.len();
+        let outer = self.span;
+        let outer_vars = std::mem::take(&mut self.clause_vars);
+
+        let decl = ir.decl.get(first).unwrap();
+        if decl.is_let() {
+            self.span = ir.spans.decl.get(first).copied().or(outer);
+            self.clause_vars = self.annotation_vars(first, first);
+            let body = ir.expr.get(decl.body).unwrap();
+            let ty = self.infer_expr(body);
+            let origin = self.span_of(body);
+            for patn in patns(ir, &decl.sig) {
+                self.infer_patn(patn, &ty, origin);
+            }
+            self.locals.truncate(mark);
+            self.span = outer;
+            self.clause_vars = outer_vars;
+            return ty;
+        }
+
+        let arity = patns(ir, &decl.sig).len();
+        let params: Vec<_> = (0..arity).map(|_| self.fresh()).collect();
+        let result = self.fresh();
+        let mut result_origin = None;
+        for clause_ref in ir.clauses(first) {
+            self.span = ir.spans.decl.get(clause_ref).copied().or(outer);
+            self.clause_vars = self.annotation_vars(first, clause_ref);
+            let clause = ir.decl.get(clause_ref).unwrap();
+            for (patn, param) in patns(ir, &clause.sig).into_iter().zip(params.iter()) {
+                self.infer_patn(patn, param, None);
+            }
+            if let Some((annotation, span)) = &clause.result {
+                let annotation = self.annotation(annotation);
+                self.unify(&annotation, &result, Some(*span), None);
+                result_origin = result_origin.or(Some(*span));
+            }
+            let body = ir.expr.get(clause.body).unwrap();
+            let ty = self.infer_expr(body);
+            let at = self.span_of(body);
+            self.unify(&result, &ty, at, result_origin);
+            self.locals.truncate(mark);
+        }
+        self.span = outer;
+        self.clause_vars = outer_vars;
+        Type::func(params, result)
+    }
+
+    fn span_of(&self, expr: &Expr) -> Option<Span> {
+        match expr {
+            Expr::Spanned(span, _) => Some(*span),
+            _ => self.span,
+        }
+    }
+
+    fn infer_expr(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Hole => self.fresh(),
+            Expr::Literal(lit) => literal_type(lit),
+            Expr::Local(id) => {
+                let (_, ty) = self
+                    .locals
+                    .iter()
+                    .rev()
+                    .find(|(local, _)| local == id)
+                    .unwrap();
+                ty.clone()
+            }
+            Expr::Var(decl_ref) => {
+                let first = self.first(*decl_ref);
+                if let Some(mono) = self.mono.get(&first) {
+                    return mono.clone();
+                }
+                match self.schemes.get(&first).cloned() {
+                    Some(scheme) => self.instantiate(&scheme),
+                    None => self.fresh(),
+                }
+            }
+            Expr::Data(cons_ref, args) => {
+                let (fields, ty) = self.instantiate_cons(*cons_ref);
+                let origin = self.ir.spans.cons.get(*cons_ref).copied();
+                for (arg, field) in args.iter().zip(fields.iter()) {
+                    let arg_ty = self.infer_expr(arg);
+                    self.unify(field, &arg_ty, self.span_of(arg), origin);
+                }
+                ty
+            }
+            Expr::Apply(func, arg) => {
+                let func_ty = self.infer_expr(func);
+                let arg_ty = self.infer_expr(arg);
+                match self.resolve(&func_ty) {
+                    Type::Func(param, result) => {
+                        self.unify(&param, &arg_ty, self.span_of(arg), self.span_of(func));
+                        *result
+                    }
+                    _ => {
+                        let result = self.fresh();
+                        let expected = Type::Func(Box::new(arg_ty), Box::new(result.clone()));
+                        self.unify(&expected, &func_ty, self.span_of(func), self.span_of(arg));
+                        result
+                    }
+                }
+            }
+            Expr::Func(patn, body) => {
+                let mark = self.locals.len();
+                let param = self.fresh();
+                self.infer_patn(patn, &param, None);
+                let result = self.infer_expr(body);
+                self.locals.truncate(mark);
+                Type::Func(Box::new(param), Box::new(result))
+            }
+            Expr::Match(scrut, arms, _) => {
+                let scrut_ty = self.infer_expr(scrut);
+                let origin = self.span_of(scrut);
+                let result = self.fresh();
+                let mut first_arm = None;
+                for (patn, body) in arms.iter() {
+                    let mark = self.locals.len();
+                    self.infer_patn(patn, &scrut_ty, origin);
+                    let ty = self.infer_expr(body);
+                    let at = self.span_of(body);
+                    self.unify(&result, &ty, at, first_arm);
+                    first_arm = first_arm.or(at);
+                    self.locals.truncate(mark);
+                }
+                result
+            }
+            Expr::Scoped(modl_ref, body) => {
+                let decls = self
+                    .scopes
+                    .get(&Some(*modl_ref))
+                    .cloned()
+                    .unwrap_or_default();
+                self.infer_decls(&decls);
+                self.infer_expr(body)
+            }
+            Expr::Prim(prim, args) => {
+                let number = Type::base(TypeCon::Number);
+                let (lhs, rhs) = (&args[0], &args[1]);
+                let lhs_ty = self.infer_expr(lhs);
+                let rhs_ty = self.infer_expr(rhs);
+                match prim {
+                    Prim::Add | Prim::Sub | Prim::Mul | Prim::Div | Prim::Rem => {
+                        self.unify(&number, &lhs_ty, self.span_of(lhs), None);
+                        self.unify(&number, &rhs_ty, self.span_of(rhs), None);
+                        number
+                    }
+                    _ => {
+                        self.unify(&lhs_ty, &rhs_ty, self.span_of(rhs), self.span_of(lhs));
+                        self.truth.clone()
+                    }
+                }
+            }
+            Expr::Spanned(span, expr) => {
+                let outer = self.span.replace(*span);
+                let ty = self.infer_expr(expr);
+                self.span = outer;
+                ty
+            }
+        }
+    }
+
+    // Binds the names in a pattern matched against a value of type `ty`,
+    // which is known to have that type because of whatever's at `origin`.
+    fn infer_patn(&mut self, patn: &Patn, ty: &Type, origin: Option<Span>) {
+        match patn {
+            Patn::Empty => {}
+            Patn::Binding(ids) => {
+                for &id in ids.iter() {
+                    self.locals.push((id, ty.clone()));
+                }
+            }
+            Patn::Literal(lit) => self.unify(ty, &literal_type(lit), self.span, origin),
+            Patn::Data(cons_ref, args) => {
+                let (fields, cons_ty) = self.instantiate_cons(*cons_ref);
+                self.unify(ty, &cons_ty, self.span, origin);
+                let cons_span = self.ir.spans.cons.get(*cons_ref).copied();
+                for (arg, field) in args.iter().zip(fields.iter()) {
+                    self.infer_patn(arg, field, cons_span);
+                }
+            }
+            Patn::Spanned(span, patn) => {
+                let outer = self.span.replace(*span);
+                self.infer_patn(patn, ty, origin);
+                self.span = outer;
+            }
+            Patn::Annotated(patn, annotation, span) => {
+                let annotation = self.annotation(annotation);
+                self.unify(&annotation, ty, origin.or(self.span), Some(*span));
+                self.infer_patn(patn, &annotation, Some(*span));
+            }
+        }
+    }
+}
+
+// Tarjan's algorithm, which finishes each component only after
+// every component it depends upon.
+fn components(nodes: &[DeclRef], deps: &HashMap<DeclRef, Vec<DeclRef>>) -> Vec<Vec<DeclRef>> {
+    struct State<'a> {
+        deps: &'a HashMap<DeclRef, Vec<DeclRef>>,
+        index: HashMap<DeclRef, usize>,
+        lowlink: HashMap<DeclRef, usize>,
+        stack: Vec<DeclRef>,
+        on_stack: HashSet<DeclRef>,
+        components: Vec<Vec<DeclRef>>,
+    }
+
+    fn visit(state: &mut State<'_>, node: DeclRef) {
+        let index = state.index.len();
+        state.index.insert(node, index);
+        state.lowlink.insert(node, index);
+        state.stack.push(node);
+        state.on_stack.insert(node);
+
+        for &dep in state.deps[&node].iter() {
+            if !state.index.contains_key(&dep) {
+                visit(state, dep);
+                let low = state.lowlink[&node].min(state.lowlink[&dep]);
+                state.lowlink.insert(node, low);
+            } else if state.on_stack.contains(&dep) {
+                let low = state.lowlink[&node].min(state.index[&dep]);
+                state.lowlink.insert(node, low);
+            }
+        }
+
+        if state.lowlink[&node] == state.index[&node] {
+            let mut component = Vec::new();
+            loop {
+                let member = state.stack.pop().unwrap();
+                state.on_stack.remove(&member);
+                component.push(member);
+                if member == node {
+                    break;
+                }
+            }
+            component.reverse();
+            state.components.push(component);
+        }
+    }
+
+    let mut state = State {
+        deps,
+        index: HashMap::new(),
+        lowlink: HashMap::new(),
+        stack: Vec::new(),
+        on_stack: HashSet::new(),
+        components: Vec::new(),
+    };
+    for &node in nodes.iter() {
+        if !state.index.contains_key(&node) {
+            visit(&mut state, node);
+        }
+    }
+    state.components
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::phases;
+
+    use bumpalo::Bump;
+
+    fn type_of<'ctx>(ctx: &'ctx Context<'ctx>, modl_ref: ModlRef, sig: &str) -> String {
+        let decl_ref = phases::find_decl(ctx, modl_ref, sig);
+        let ir = ctx.ir.borrow();
+        ctx.wrap(ir.types.decl.get(decl_ref).unwrap()).to_string()
+    }
+
+    #[test]
+    fn annotations() {
+        let arena = Bump::new();
+        let ctx = Context::new(&arena);
+        let source = "
+            data List a = con Nil | con Cons a, (List a)
+            def len (l : List a) : Num = match l | Nil = 0 | Cons _, xs = 1 + len xs end
+            let x : Num = 10
+        ";
+        let modl_ref = phases::load_source(&ctx, source).unwrap();
+        assert_eq!(phases::reported(&ctx), []);
+        assert_eq!(type_of(&ctx, modl_ref, "len _"), "List a -> Num");
+        assert_eq!(type_of(&ctx, modl_ref, "x"), "Num");
+
+        for &source in ["def len (l : Num) : Str = l", "let x : Num = \"ten\""].iter() {
+            let ctx = Context::new(&arena);
+            phases::load_source(&ctx, source).unwrap();
+            assert_eq!(
+                phases::reported(&ctx),
+                [error::MismatchedTypes],
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn inference() {
+        let arena = Bump::new();
+        let ctx = Context::new(&arena);
+        let source = "
+            con Nil
+            con Cons _, _
+            def len (Nil) = 0
+            def len (Cons _, xs) = 1 + len xs
+            def id (x) = x
+            let n = id 1
+            let s = id \"one\"
+            def main = len (Cons s, Nil) + n
+        ";
+        let modl_ref = phases::load_source(&ctx, source).unwrap();
+        assert_eq!(phases::reported(&ctx), []);
+        assert_eq!(type_of(&ctx, modl_ref, "id _"), "a -> a");
+        assert_eq!(type_of(&ctx, modl_ref, "n"), "Num");
+        assert_eq!(type_of(&ctx, modl_ref, "s"), "Str");
+        assert_eq!(type_of(&ctx, modl_ref, "main"), "Num");
+
+        // Both sides of a mismatch are shown where they came from.
+        let ctx = Context::new(&arena);
+        let source = "con Nil def len (Nil) = 0 def main = len 5";
+        phases::load_source(&ctx, source).unwrap();
+        assert_eq!(phases::reported(&ctx), [error::MismatchedTypes]);
+        let diagnostics = ctx.diagnostics.borrow();
+        let diag = &diagnostics[0];
+        let at = |label: &error::Label| &source[label.span.start..label.span.end];
+        assert_eq!(at(diag.primary.as_ref().unwrap()), "5");
+        assert_eq!(diag.labels.len(), 1);
+        assert_eq!(at(&diag.labels[0]), "len 5");
+        assert_eq!(diag.message, "Expected `{Nil}`, but found `Num`");
+        drop(diagnostics);
+
+        let ctx = Context::new(&arena);
+        phases::load_source(&ctx, "def f (x) = x x").unwrap();
+        assert_eq!(phases::reported(&ctx), [error::InfiniteType]);
+    }
+
+    #[test]
+    fn data_declarations() {
+        let arena = Bump::new();
+        let ctx = Context::new(&arena);
+        let source = "
+            data List a = con Nil | con Cons a, (List a)
+            data Pair a b = con (a) & (b)
+            def swap (x & y) = y & x
+            def main = swap ((Cons 1, Nil) & \"one\")
+        ";
+        let modl_ref = phases::load_source(&ctx, source).unwrap();
+        assert_eq!(phases::reported(&ctx), []);
+        let ir = ctx.ir.borrow();
+        let record = ir.modl.get(modl_ref).unwrap().as_record().unwrap();
+        let types: Vec<_> = record
+            .cons
+            .iter()
+            .map(|&cons_ref| ctx.wrap(ir.types.cons.get(cons_ref).unwrap()).to_string())
+            .collect();
+        assert_eq!(
+            types,
+            ["List a", "a -> List a -> List a", "a -> b -> Pair a b"]
+        );
+        drop(ir);
+        assert_eq!(type_of(&ctx, modl_ref, "swap _"), "Pair a b -> Pair b a");
+        assert_eq!(type_of(&ctx, modl_ref, "main"), "Pair Str (List Num)");
+
+        // Only the constructors of a value's own type need covering.
+        let ctx = Context::new(&arena);
+        let source = "
+            data List a = con Nil | con Cons a, (List a)
+            data Maybe a = con None | con Some a
+            def first (Cons x, _) = Some x
+        ";
+        phases::load_source(&ctx, source).unwrap();
+        assert_eq!(phases::reported(&ctx), [error::NonExhaustivePatterns]);
+        let message = ctx.diagnostics.borrow()[0].message.clone();
+        assert_eq!(message, "The clauses of first _ don't cover `first Nil`");
+
+        let ctx = Context::new(&arena);
+        phases::load_source(&ctx, "data T = con A (Missing)").unwrap();
+        assert_eq!(phases::reported(&ctx), [error::UnresolvedName]);
+    }
+}
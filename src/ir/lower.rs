@@ -0,0 +1,1340 @@
+use crate::ast::{self, Fixity, Span, Spanned};
+use crate::ctx::Context;
+use crate::error::{self, Diagnostic};
+use crate::id::Ident;
+use crate::ir::coverage;
+use crate::ir::decision::{self, DecisionTree};
+use crate::ir::mixfix::{self, Token, Tree};
+use crate::ir::types::{Type, TypeCon};
+use crate::ir::{self, Expr, Literal, Patn, Sign};
+use crate::phases;
+use crate::refs::*;
+use crate::storage::*;
+
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::rc::Rc;
+
+// Modules can open one another, so every `let` binder must be
+// known before lowering any of the bodies which might refer to it.
+pub fn lower_modls<'ctx>(ctx: &'ctx Context<'ctx>, modls: &[ModlRef]) -> error::Result<()> {
+    let mut lowerer = Lowerer::new(ctx, ctx.global_modl());
+    for &modl_ref in modls.iter() {
+        lowerer.in_modl(modl_ref, Lowerer::declare_lets)?;
+    }
+    for &modl_ref in modls.iter() {
+        phases::check_imports(ctx, modl_ref)?;
+    }
+    for &modl_ref in modls.iter() {
+        lowerer.in_modl(modl_ref, Lowerer::lower_record)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Target {
+    Decl(DeclRef),
+    Let(DeclRef, Ident),
+    Cons(ConsRef),
+}
+
+#[derive(Debug, Clone)]
+struct Operators {
+    sigs: Vec<Vec<Sign>>,
+    qualifiers: Vec<Option<ModlRef>>,
+    targets: Vec<Target>,
+    fixities: Vec<Fixity>,
+}
+
+impl Operators {
+    fn has_word(&self, id: Ident) -> bool {
+        self.sigs.iter().any(|sig| sig.contains(&Sign::Word(id)))
+    }
+
+    fn push(&mut self, sig: Vec<Sign>, qualifier: Option<ModlRef>, target: Target, fixity: Fixity) {
+        self.sigs.push(sig);
+        self.qualifiers.push(qualifier);
+        self.targets.push(target);
+        self.fixities.push(fixity);
+    }
+}
+
+struct Lowerer<'ctx> {
+    ctx: &'ctx Context<'ctx>,
+    modl: ModlRef,
+    locals: Vec<Ident>,
+    let_patns: HashMap<DeclRef, PatnRef>,
+    let_vars: HashMap<DeclRef, Vec<Ident>>,
+    // Variables in the annotations of the declaration being lowered.
+    type_vars: Vec<Ident>,
+    // Declarations which failed to lower, and only have placeholders.
+    failed: HashSet<DeclRef>,
+    // Set when an error is only a consequence of an earlier one.
+    cascading: Cell<bool>,
+}
+
+// What a run of atoms was resolved to, and the signatures of the
+// operators its tree refers to by index.
+struct Resolved {
+    tree: Rc<Tree>,
+    targets: Vec<Target>,
+    sigs: Vec<Vec<Sign>>,
+}
+
+impl Resolved {
+    // The atoms a tree was parsed from begin at `start`.
+    fn span<T>(&self, tree: &Tree, start: usize, atoms: &[Spanned<T>]) -> Span {
+        let end = start + mixfix::width(tree, &self.sigs) - 1;
+        atoms[start].span.to(atoms[end].span)
+    }
+
+    // Where each argument of an operator begins, skipping over its words.
+    fn args<'t>(&self, op_ix: usize, args: &'t [Rc<Tree>], start: usize) -> Vec<(&'t Tree, usize)> {
+        let mut pos = start;
+        let mut args = args.iter();
+        let mut starts = Vec::new();
+        for sign in self.sigs[op_ix].iter() {
+            match sign {
+                Sign::Word(_) => pos += 1,
+                Sign::Patn(()) => {
+                    let arg = args.next().unwrap();
+                    starts.push((&**arg, pos));
+                    pos += mixfix::width(arg, &self.sigs);
+                }
+            }
+        }
+        starts
+    }
+}
+
+// Parentheses only group, so a nested expression keeps its own span.
+fn spanned_expr(span: Span, expr: Expr) -> Expr {
+    match expr {
+        Expr::Spanned(..) => expr,
+        _ => Expr::Spanned(span, Box::new(expr)),
+    }
+}
+
+type TypeAtom<'ctx> = Spanned<ast::Atom<'ctx, Spanned<ast::Type<'ctx>>>>;
+
+fn lower_literal<T>(atom: &ast::Atom<'_, T>) -> Option<Literal> {
+    match *atom {
+        ast::Atom::Unit => Some(Literal::Unit),
+        ast::Atom::Number(n) => Some(Literal::Number(n)),
+        // A backslash only keeps what comes after it in the string.
+        ast::Atom::String(s) => {
+            let mut chars = s.chars();
+            let mut text = String::new();
+            while let Some(c) = chars.next() {
+                text.extend(if c == '\\' { chars.next() } else { Some(c) });
+            }
+            Some(Literal::String(text))
+        }
+        _ => None,
+    }
+}
+
+impl<'ctx> Lowerer<'ctx> {
+    fn new(ctx: &'ctx Context<'ctx>, modl: ModlRef) -> Self {
+        Lowerer {
+            ctx,
+            modl,
+            locals: Vec::new(),
+            let_patns: HashMap::new(),
+            let_vars: HashMap::new(),
+            type_vars: Vec::new(),
+            failed: HashSet::new(),
+            cascading: Cell::new(false),
+        }
+    }
+
+    // Errors within a declaration are reported so that lowering can
+    // carry on with the next one, unless they were caused by a name
+    // missing from a module which has already failed to resolve.
+    fn report(&self, err: Box<Diagnostic>) {
+        if !self.cascading.replace(false) {
+            self.ctx.report(err);
+        }
+    }
+
+    fn sees_poison(&self) -> bool {
+        let poisoned = self.ctx.poisoned_modl();
+        self.ctx
+            .ir
+            .borrow()
+            .scope_levels(self.modl)
+            .iter()
+            .flatten()
+            .any(|&(scope_ref, _)| scope_ref == poisoned)
+    }
+
+    // Nested modules see the same local bindings as their enclosing scope.
+    fn in_modl<T>(&mut self, modl: ModlRef, f: impl FnOnce(&mut Self) -> T) -> T {
+        let outer = std::mem::replace(&mut self.modl, modl);
+        let result = f(self);
+        self.modl = outer;
+        result
+    }
+
+    fn with_locals<T>(&mut self, binders: Vec<Ident>, f: impl FnOnce(&mut Self) -> T) -> T {
+        let mark = self.locals.len();
+        self.locals.extend(binders);
+        let result = f(self);
+        self.locals.truncate(mark);
+        result
+    }
+
+    // Annotations within a declaration share their type variables,
+    // apart from those of any declarations nested inside it.
+    fn with_type_vars<T>(
+        &mut self,
+        vars: Vec<Ident>,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> (T, Vec<Ident>) {
+        let outer = std::mem::replace(&mut self.type_vars, vars);
+        let result = f(self);
+        (result, std::mem::replace(&mut self.type_vars, outer))
+    }
+
+    fn ast_decl(&self, decl_ref: DeclRef) -> ast::Decl<'ctx> {
+        *self.ctx.ast.borrow().decl.get(decl_ref).unwrap()
+    }
+
+    fn make_ident(&self, id: ast::Ident<'ctx>) -> Ident {
+        self.ctx.names.borrow_mut().make_ident(id.0)
+    }
+
+    fn store_expr(&self, expr: Expr) -> ExprRef {
+        let expr_ref = self.ctx.refs.borrow_mut().expr.make_ref();
+        self.ctx.ir.borrow_mut().expr.set(expr_ref, expr);
+        expr_ref
+    }
+
+    fn store_patn(&self, patn: Patn) -> PatnRef {
+        let patn_ref = self.ctx.refs.borrow_mut().patn.make_ref();
+        self.ctx.ir.borrow_mut().patn.set(patn_ref, patn);
+        patn_ref
+    }
+
+    fn record_contents(&self) -> error::Result<(Vec<DeclRef>, Vec<ModlRef>)> {
+        let ir = self.ctx.ir.borrow();
+        let record = ir.modl.get(self.modl).unwrap().as_record()?;
+        let mut children: Vec<_> = record
+            .children
+            .values()
+            .copied()
+            .filter(|&child_ref| matches!(ir.modl.get(child_ref), Some(ir::Modl::Record(_))))
+            .collect();
+        children.sort_by_key(|&child_ref| usize::from(child_ref));
+        Ok((record.decls.clone(), children))
+    }
+
+    // The names bound by a `let` are visible throughout the
+    // whole module, so register them before lowering any bodies.
+    fn declare_lets(&mut self) -> error::Result<()> {
+        let (decls, children) = self.record_contents()?;
+
+        for &decl_ref in decls.iter() {
+            if let ast::Decl::Let(patn, _) = self.ast_decl(decl_ref) {
+                let mut binders = Vec::new();
+                let (patn, vars) =
+                    self.with_type_vars(Vec::new(), |this| this.lower_patn(patn, &mut binders));
+                let patn = patn.unwrap_or_else(|err| {
+                    self.report(err);
+                    binders.clear();
+                    Patn::Empty
+                });
+                let patn_ref = self.store_patn(patn);
+                self.let_patns.insert(decl_ref, patn_ref);
+                self.let_vars.insert(decl_ref, vars);
+
+                let mut ir = self.ctx.ir.borrow_mut();
+                let ir = &mut *ir;
+                let record = ir.modl.get_mut(self.modl).unwrap().as_record_mut()?;
+                for id in binders {
+                    let sig = vec![Sign::Word(id)];
+                    if let Some(&previous) = record.symbols.lookup_decl(&sig).first() {
+                        let spans = &ir.spans.decl;
+                        let span = |decl_ref| *spans.get(decl_ref).unwrap();
+                        self.ctx.report(
+                            Diagnostic::new(
+                                error::DuplicateDefinition,
+                                format!("{} is defined more than once", self.ctx.wrap(&sig[..])),
+                            )
+                            .with_primary(span(decl_ref), "defined again here")
+                            .with_label(span(previous), "first defined here"),
+                        );
+                    }
+                    record.symbols.new_decl(decl_ref, sig);
+                }
+            }
+        }
+
+        for child_ref in children {
+            self.in_modl(child_ref, Self::declare_lets)?;
+        }
+
+        Ok(())
+    }
+
+    fn lower_record(&mut self) -> error::Result<()> {
+        let (decls, children) = self.record_contents()?;
+        self.lower_data()?;
+
+        for &decl_ref in decls.iter() {
+            let decl = match self.ast_decl(decl_ref) {
+                ast::Decl::Def(sig, result, body) => self.lower_def(sig, result, body),
+                ast::Decl::Let(_, body) => {
+                    let vars = self.let_vars[&decl_ref].clone();
+                    let (body, type_vars) = self.with_type_vars(vars, |this| this.lower_body(body));
+                    body.map(|body| ir::Decl {
+                        sig: vec![Sign::Patn(self.let_patns[&decl_ref])],
+                        body,
+                        result: None,
+                        type_vars,
+                    })
+                }
+                _ => unreachable!("Only `def` and `let` create declarations."),
+            };
+            let decl = match decl {
+                Ok(decl) => decl,
+                Err(err) => {
+                    self.report(err);
+                    self.failed.insert(decl_ref);
+                    self.placeholder_decl(decl_ref)
+                }
+            };
+            self.ctx.ir.borrow_mut().decl.set(decl_ref, decl);
+        }
+        self.compile_decisions()?;
+
+        for child_ref in children {
+            self.in_modl(child_ref, Self::lower_record)?;
+        }
+
+        Ok(())
+    }
+
+    // Fields may have types from anywhere in scope, so they're only
+    // lowered once every module's names are known.
+    fn lower_data(&mut self) -> error::Result<()> {
+        let data_refs = {
+            let ir = self.ctx.ir.borrow();
+            ir.modl.get(self.modl).unwrap().as_record()?.data.clone()
+        };
+
+        for data_ref in data_refs {
+            let (params, ast_cons) = match self.ctx.ast.borrow().data.get(data_ref) {
+                Some(&ast::Decl::Data(_, params, cons)) => (params, cons),
+                _ => continue,
+            };
+            let span = *self.ctx.ir.borrow().spans.data.get(data_ref).unwrap();
+            for (ix, param) in params.iter().enumerate() {
+                if params[..ix].contains(param) {
+                    self.report(
+                        Diagnostic::new(
+                            error::InvalidType,
+                            format!("The parameter {} is named more than once", param),
+                        )
+                        .with_primary(span, "in this type")
+                        .into(),
+                    );
+                }
+            }
+            let params: Vec<_> = params.iter().map(|&param| self.make_ident(param)).collect();
+
+            let cons_refs = self
+                .ctx
+                .ir
+                .borrow()
+                .data
+                .get(data_ref)
+                .unwrap()
+                .cons
+                .clone();
+            for (cons_ref, cons) in cons_refs.into_iter().zip(ast_cons.iter()) {
+                let mut fields = Vec::new();
+                for field in cons.fields.iter() {
+                    let field = self.lower_type(field.node, field.span, Some(&params));
+                    fields.push(field.unwrap_or_else(|err| {
+                        self.report(err);
+                        Type::Var(params.len())
+                    }));
+                }
+                self.ctx
+                    .ir
+                    .borrow_mut()
+                    .cons
+                    .get_mut(cons_ref)
+                    .unwrap()
+                    .fields = Some(fields);
+            }
+        }
+        Ok(())
+    }
+
+    // Arrows group to the right, and everything else binds tighter.
+    // Data types give the parameters their fields may use, while
+    // annotations may use any variable they like.
+    fn lower_type(
+        &mut self,
+        ty: ast::Type<'ctx>,
+        span: Span,
+        params: Option<&[Ident]>,
+    ) -> error::Result<Type> {
+        let ast::Type::Flat(atoms) = ty;
+        let is_arrow =
+            |atom: &TypeAtom<'ctx>| matches!(atom.node, ast::Atom::Ident(ast::Ident("->")));
+        let arrow = match atoms.iter().position(is_arrow) {
+            Some(arrow) => arrow,
+            None => return self.lower_applied_type(atoms, params),
+        };
+
+        let (param, result) = (&atoms[..arrow], &atoms[arrow + 1..]);
+        if param.is_empty() || result.is_empty() {
+            Err(
+                Diagnostic::new(error::InvalidType, "Expected a type on both sides of `->`")
+                    .with_primary(atoms[arrow].span, "missing a type beside this"),
+            )?
+        }
+        let result_span = result[0].span.to(span);
+        Ok(Type::Func(
+            Box::new(self.lower_applied_type(param, params)?),
+            Box::new(self.lower_type(ast::Type::Flat(result), result_span, params)?),
+        ))
+    }
+
+    // A type applied to its arguments, like `List a`.
+    fn lower_applied_type(
+        &mut self,
+        atoms: &[TypeAtom<'ctx>],
+        params: Option<&[Ident]>,
+    ) -> error::Result<Type> {
+        let (head, args) = atoms.split_first().unwrap();
+        if let ast::Atom::Ident(id) = head.node {
+            if let Some(var) = self.type_var(id, params) {
+                if let Some(arg) = args.first() {
+                    Err(Diagnostic::new(
+                        error::InvalidType,
+                        format!("The type variable {} can't be given type arguments", id),
+                    )
+                    .with_primary(arg.span.to(atoms[atoms.len() - 1].span), "given here"))?
+                }
+                return Ok(Type::Var(var));
+            }
+        }
+
+        let con = match head.node {
+            ast::Atom::Unit => TypeCon::Unit,
+            ast::Atom::Nested(inner) if args.is_empty() => {
+                return self.lower_type(inner.node, inner.span, params)
+            }
+            ast::Atom::Ident(id) => self.resolve_type(head, &[], id)?,
+            ast::Atom::Qualified(path, id) => self.resolve_type(head, path, id)?,
+            _ => Err(Diagnostic::new(
+                error::InvalidType,
+                format!("Expected a type, but found {}", head.node),
+            )
+            .with_primary(head.span, "not a type"))?,
+        };
+
+        let arity = match con {
+            TypeCon::Data(data_ref) => self
+                .ctx
+                .ir
+                .borrow()
+                .data
+                .get(data_ref)
+                .unwrap()
+                .params
+                .len(),
+            TypeCon::Unit | TypeCon::Number | TypeCon::String => 0,
+        };
+        if args.len() != arity {
+            let plural = if arity == 1 { "" } else { "s" };
+            Err(Diagnostic::new(
+                error::InvalidType,
+                format!(
+                    "{} takes {} type argument{}, but was given {}",
+                    head.node,
+                    arity,
+                    plural,
+                    args.len()
+                ),
+            )
+            .with_primary(head.span.to(atoms[atoms.len() - 1].span), "in this type"))?
+        }
+
+        let args = args
+            .iter()
+            .map(|arg| self.lower_applied_type(std::slice::from_ref(arg), params))
+            .collect::<error::Result<_>>()?;
+        Ok(Type::Con(con, args))
+    }
+
+    // Names starting with a lowercase letter are variables in annotations.
+    fn type_var(&mut self, id: ast::Ident<'ctx>, params: Option<&[Ident]>) -> Option<usize> {
+        let id_ref = self.make_ident(id);
+        match params {
+            Some(params) => params.iter().position(|&param| param == id_ref),
+            None if id.0.starts_with(char::is_lowercase) => {
+                match self.type_vars.iter().position(|&var| var == id_ref) {
+                    Some(ix) => Some(ix),
+                    None => {
+                        self.type_vars.push(id_ref);
+                        Some(self.type_vars.len() - 1)
+                    }
+                }
+            }
+            None => None,
+        }
+    }
+
+    // Type names are scoped like a signature of one word.
+    fn resolve_type(
+        &self,
+        atom: &TypeAtom<'ctx>,
+        path: &[ast::Ident<'ctx>],
+        id: ast::Ident<'ctx>,
+    ) -> error::Result<TypeCon> {
+        let id = self.make_ident(id);
+        let sig = [Sign::Word(id)];
+        let qualifier = match path {
+            [] => None,
+            _ => Some(self.resolve_modl_path(path, atom.span)?),
+        };
+
+        let ir = self.ctx.ir.borrow();
+        let scopes = match qualifier {
+            None => ir.scope_levels(self.modl).concat(),
+            Some(modl_ref) => vec![(modl_ref, &ir::IMPORT_ALL)],
+        };
+        let symbols = |scope_ref| match ir.modl.get(scope_ref) {
+            Some(ir::Modl::Record(record)) => Some(&record.symbols),
+            _ => None,
+        };
+        let found = scopes
+            .iter()
+            .filter(|(_, filter)| filter.admits_sig(&sig))
+            .find_map(|&(scope_ref, _)| symbols(scope_ref)?.lookup_type(id));
+        if let Some(con) = found {
+            return Ok(con);
+        }
+
+        self.cascading.set(self.sees_poison());
+        let names = self.ctx.names.borrow();
+        let visible = scopes
+            .iter()
+            .filter_map(|&(scope_ref, _)| symbols(scope_ref))
+            .flat_map(|symbols| symbols.iter_types().map(|(id, _)| id))
+            .map(|id| names.get(id).unwrap());
+        let similar = error::similar_names(names.get(id).unwrap(), visible);
+        Err(Diagnostic::new(
+            error::UnresolvedName,
+            format!("Unresolved type: {}", atom.node),
+        )
+        .with_primary(atom.span, "not found in this scope")
+        .with_suggestions(&similar))?
+    }
+
+    // A function's clauses can only be compiled together
+    // once every one of them has been lowered.
+    fn compile_decisions(&self) -> error::Result<()> {
+        let mut ir = self.ctx.ir.borrow_mut();
+        let record = ir.modl.get(self.modl).unwrap().as_record()?;
+        let firsts: Vec<_> = record
+            .symbols
+            .iter_decl_signs()
+            .map(|(_, decls)| decls[0])
+            .filter(|decl_ref| !self.let_patns.contains_key(decl_ref))
+            .collect();
+        for &decl_ref in firsts.iter() {
+            let tree = decision::compile_clauses(&ir, decl_ref);
+            ir.decisions.set(decl_ref, tree);
+        }
+        drop(ir);
+
+        for decl_ref in firsts {
+            self.check_clauses(decl_ref);
+        }
+        Ok(())
+    }
+
+    fn check_clauses(&self, decl_ref: DeclRef) {
+        let ir = self.ctx.ir.borrow();
+        let clauses = ir.clauses(decl_ref);
+        // A placeholder's patterns match anything, which says nothing
+        // about whether the clauses as written would have.
+        if clauses
+            .iter()
+            .any(|clause_ref| self.failed.contains(clause_ref))
+        {
+            return;
+        }
+        let sig = ir.decl.get(decl_ref).unwrap().sig.clone();
+        let arity = sig
+            .iter()
+            .filter(|sign| matches!(sign, Sign::Patn(_)))
+            .count();
+        if arity == 0 {
+            return;
+        }
+        let span = |clause_ref| *ir.spans.decl.get(clause_ref).unwrap();
+        let spans: Vec<_> = clauses.iter().map(|&clause_ref| span(clause_ref)).collect();
+        let tree = ir.decisions.get(decl_ref).unwrap();
+        let coverage = coverage::check(&ir, tree, clauses.len(), arity);
+        drop(ir);
+
+        if let Some(missing) = coverage.missing {
+            let missing = self.ctx.wrap((&sig[..], &missing[..])).to_string();
+            self.ctx.report(
+                Diagnostic::new(
+                    error::NonExhaustivePatterns,
+                    format!(
+                        "The clauses of {} don't cover `{}`",
+                        self.ctx.wrap(&sig[..]),
+                        missing
+                    ),
+                )
+                .with_primary(spans[0], format!("`{}` is not matched", missing))
+                .with_help("Add a clause for it, or one taking any argument."),
+            );
+        }
+        for clause in coverage.unreachable {
+            self.ctx.report(
+                Diagnostic::warning(error::UnreachablePattern, "This clause can never be used")
+                    .with_primary(
+                        spans[clause],
+                        "the clauses before it match everything it does",
+                    ),
+            );
+        }
+    }
+
+    fn check_match(&self, tree: &DecisionTree, scrut_span: Span, arm_spans: &[Span]) {
+        let coverage = coverage::check(&self.ctx.ir.borrow(), tree, arm_spans.len(), 1);
+        if let Some(missing) = coverage.missing {
+            let missing = self.ctx.wrap(&missing[0]).to_string();
+            self.ctx.report(
+                Diagnostic::new(
+                    error::NonExhaustivePatterns,
+                    format!("This match doesn't cover `{}`", missing),
+                )
+                .with_primary(scrut_span, format!("`{}` is not matched", missing))
+                .with_help("Add an arm for it, or one matching anything with `_`."),
+            );
+        }
+        for arm in coverage.unreachable {
+            self.ctx.report(
+                Diagnostic::warning(error::UnreachablePattern, "This arm can never be used")
+                    .with_primary(
+                        arm_spans[arm],
+                        "the arms before it match everything it does",
+                    ),
+            );
+        }
+    }
+
+    // Keeps the shape of a declaration which failed to lower.
+    fn placeholder_decl(&self, decl_ref: DeclRef) -> ir::Decl {
+        let sig = match self.ast_decl(decl_ref) {
+            ast::Decl::Def(sig, ..) => sig
+                .iter()
+                .map(|sign| match sign.from_ast(self.ctx) {
+                    Sign::Word(id) => Sign::Word(id),
+                    Sign::Patn(_) => Sign::Patn(self.store_patn(Patn::Empty)),
+                })
+                .collect(),
+            _ => vec![Sign::Patn(self.let_patns[&decl_ref])],
+        };
+        ir::Decl {
+            sig,
+            body: self.store_expr(Expr::Hole),
+            result: None,
+            type_vars: Vec::new(),
+        }
+    }
+
+    fn lower_def(
+        &mut self,
+        sig: &'ctx [Spanned<ast::Sign<'ctx>>],
+        result: Option<&'ctx Spanned<ast::Type<'ctx>>>,
+        body: &'ctx Spanned<ast::Expr<'ctx>>,
+    ) -> error::Result<ir::Decl> {
+        let (decl, type_vars) = self.with_type_vars(Vec::new(), |this| -> error::Result<_> {
+            let mut binders = Vec::new();
+            let mut ir_sig = Vec::new();
+            for sign in sig.iter() {
+                match sign.from_ast(this.ctx) {
+                    Sign::Word(id) => ir_sig.push(Sign::Word(id)),
+                    Sign::Patn(patn) => {
+                        let patn = this.lower_patn(&patn, &mut binders)?;
+                        ir_sig.push(Sign::Patn(this.store_patn(patn)));
+                    }
+                }
+            }
+            let result = match result {
+                Some(ty) => Some((this.lower_type(ty.node, ty.span, None)?, ty.span)),
+                None => None,
+            };
+
+            let body = this.with_locals(binders, |this| this.lower_body(body))?;
+            Ok((ir_sig, result, body))
+        });
+        let (sig, result, body) = decl?;
+        Ok(ir::Decl {
+            sig,
+            body,
+            result,
+            type_vars,
+        })
+    }
+
+    fn lower_body(&mut self, body: &Spanned<ast::Expr<'ctx>>) -> error::Result<ExprRef> {
+        let expr = self.lower_expr(body)?;
+        Ok(self.store_expr(expr))
+    }
+
+    fn lower_expr(&mut self, expr: &Spanned<ast::Expr<'ctx>>) -> error::Result<Expr> {
+        let ir_expr = match expr.node {
+            ast::Expr::Flat(atoms) => self.lower_flat(atoms)?,
+
+            ast::Expr::Func(patn, body) => {
+                let mut binders = Vec::new();
+                let patn = self.lower_patn(patn, &mut binders)?;
+                let body = self.with_locals(binders, |this| this.lower_expr(body))?;
+                Expr::Func(Box::new(patn), Box::new(body))
+            }
+
+            ast::Expr::Match(scrut, arms) => {
+                let scrut_span = scrut.span;
+                let scrut = self.lower_expr(scrut)?;
+                let mut ir_arms = Vec::new();
+                for (patn, body) in arms.iter() {
+                    let mut binders = Vec::new();
+                    let patn = self.lower_patn(patn, &mut binders)?;
+                    let body = self.with_locals(binders, |this| this.lower_expr(body))?;
+                    ir_arms.push((patn, body));
+                }
+                let tree = decision::compile(ir_arms.iter().map(|(patn, _)| vec![patn]));
+                let arm_spans: Vec<_> = arms.iter().map(|(patn, _)| patn.span).collect();
+                self.check_match(&tree, scrut_span, &arm_spans);
+                Expr::Match(Box::new(scrut), ir_arms, Rc::new(tree))
+            }
+
+            ast::Expr::Scoped(decls, body) => {
+                let modl_ref = phases::process_scoped_modl(self.ctx, self.modl, decls, expr.span)?;
+                let body = self.in_modl(modl_ref, |this| {
+                    this.declare_lets()?;
+                    phases::check_imports(this.ctx, this.modl)?;
+                    this.lower_record()?;
+                    this.lower_expr(body)
+                })?;
+                Expr::Scoped(modl_ref, Box::new(body))
+            }
+        };
+        Ok(spanned_expr(expr.span, ir_expr))
+    }
+
+    fn decl_target(&self, sig: &[Sign], decl_ref: DeclRef) -> Target {
+        match sig {
+            [Sign::Word(id)] if self.let_patns.contains_key(&decl_ref) => {
+                Target::Let(decl_ref, *id)
+            }
+            _ => Target::Decl(decl_ref),
+        }
+    }
+
+    fn visible_operators(&self, with_decls: bool) -> error::Result<Operators> {
+        let ir = self.ctx.ir.borrow();
+        let mut ops = Operators {
+            sigs: Vec::new(),
+            qualifiers: Vec::new(),
+            targets: Vec::new(),
+            fixities: Vec::new(),
+        };
+
+        // Nearer scopes shadow any signature defined further out.
+        let levels = ir.scope_levels(self.modl);
+        let mut shadowed = HashSet::new();
+        for level in levels.iter() {
+            let mut defined = HashSet::new();
+            for &(scope_ref, filter) in level.iter() {
+                let symbols = &ir.modl.get(scope_ref).unwrap().as_record()?.symbols;
+                let visible = |sig: &[Sign]| {
+                    !shadowed.contains(sig) && !defined.contains(sig) && filter.admits_sig(sig)
+                };
+
+                let first_ix = ops.sigs.len();
+                let mut found = Vec::new();
+                if with_decls {
+                    for (sig, decls) in symbols.iter_decl_signs() {
+                        if visible(sig) {
+                            found.push((sig.to_vec(), self.decl_target(sig, decls[0])));
+                        }
+                    }
+                }
+                for (sig, cons) in symbols.iter_cons_signs() {
+                    if visible(sig) {
+                        found.push((sig.to_vec(), Target::Cons(cons[0])));
+                    }
+                }
+
+                // The same module may be opened more than once.
+                for (sig, target) in found {
+                    ops.sigs.push(sig);
+                    ops.qualifiers.push(None);
+                    ops.targets.push(target);
+                }
+                defined.extend(ops.sigs[first_ix..].iter().cloned());
+            }
+            shadowed.extend(defined);
+        }
+
+        // Fixities are scoped independently of the operators they describe.
+        for sig in ops.sigs.iter() {
+            let fixity = levels
+                .iter()
+                .flatten()
+                .filter(|(_, filter)| filter.admits_sig(sig))
+                .filter_map(|&(scope_ref, _)| match ir.modl.get(scope_ref) {
+                    Some(ir::Modl::Record(record)) => record.symbols.lookup_fixity(sig),
+                    _ => None,
+                })
+                .next();
+            ops.fixities.push(fixity.unwrap_or(mixfix::DEFAULT_FIXITY));
+        }
+
+        Ok(ops)
+    }
+
+    // Follow a module path through children and aliases, starting
+    // from whichever visible module has the first name as a child.
+    fn resolve_modl_path(&self, path: &[ast::Ident<'ctx>], span: Span) -> error::Result<ModlRef> {
+        let not_found = |len: usize, siblings: Vec<Ident>| {
+            let names = self.ctx.names.borrow();
+            let similar = error::similar_names(
+                path[len - 1].0,
+                siblings.into_iter().map(|id| names.get(id).unwrap()),
+            );
+            let path = path[..len]
+                .iter()
+                .map(|id| id.0)
+                .collect::<Vec<_>>()
+                .join(".");
+            Diagnostic::new(
+                error::UnresolvableModulePath,
+                format!("No such module: {}", path),
+            )
+            .with_primary(span, "not found in this scope")
+            .with_suggestions(&similar)
+        };
+
+        let ir = self.ctx.ir.borrow();
+        let child = |modl_ref: ModlRef, id: Ident| match ir.modl.get(modl_ref) {
+            Some(ir::Modl::Record(record)) => record.children.get(&id).copied(),
+            _ => None,
+        };
+        let children_of = |modl_ref: Option<ModlRef>| match modl_ref.and_then(|m| ir.modl.get(m)) {
+            Some(ir::Modl::Record(record)) => record.children.keys().copied().collect(),
+            _ => Vec::new(),
+        };
+
+        let head = self.make_ident(path[0]);
+        let mut modl_ref = ir
+            .scope_levels(self.modl)
+            .iter()
+            .flatten()
+            .filter(|(_, filter)| filter.admits_child(head))
+            .find_map(|&(scope_ref, _)| child(scope_ref, head))
+            .ok_or_else(|| {
+                self.cascading.set(self.sees_poison());
+                not_found(1, ir.visible_children(self.modl))
+            })?;
+
+        for (ix, &id) in path.iter().enumerate().skip(1) {
+            if ir.target(modl_ref) == Some(self.ctx.poisoned_modl()) {
+                break;
+            }
+            let id = self.make_ident(id);
+            let target = ir.target(modl_ref);
+            modl_ref = target
+                .and_then(|target| child(target, id))
+                .ok_or_else(|| not_found(ix + 1, children_of(target)))?;
+        }
+
+        ir.target(modl_ref)
+            .ok_or_else(|| not_found(path.len(), Vec::new()).into())
+    }
+
+    // Qualified names only see what a module defines itself, and
+    // make its operators starting with that word available.
+    fn qualified_operators<T: Display>(
+        &self,
+        ops: &mut Operators,
+        atom: &Spanned<ast::Atom<'ctx, T>>,
+        path: &[ast::Ident<'ctx>],
+        id: ast::Ident<'ctx>,
+        with_decls: bool,
+    ) -> error::Result<Token> {
+        let modl_ref = self.resolve_modl_path(path, atom.span)?;
+        let id = self.make_ident(id);
+        let token = Token::Qualified(modl_ref, id);
+        let starts_with = |sig: &[Sign]| mixfix::words(sig).next() == Some(id);
+
+        // The same name may be used more than once in an expression.
+        let seen = ops
+            .sigs
+            .iter()
+            .zip(ops.qualifiers.iter())
+            .any(|(sig, &qualifier)| qualifier == Some(modl_ref) && starts_with(sig));
+        if seen {
+            return Ok(token);
+        }
+
+        let ir = self.ctx.ir.borrow();
+        let record = ir.modl.get(modl_ref).unwrap().as_record()?;
+        let (symbols, record_name) = (&record.symbols, &record.name);
+        let fixity = |sig: &[Sign]| symbols.lookup_fixity(sig).unwrap_or(mixfix::DEFAULT_FIXITY);
+        let mut found = false;
+        if with_decls {
+            for (sig, decls) in symbols.iter_decl_signs() {
+                if starts_with(sig) {
+                    ops.push(
+                        sig.to_vec(),
+                        Some(modl_ref),
+                        self.decl_target(sig, decls[0]),
+                        fixity(sig),
+                    );
+                    found = true;
+                }
+            }
+        }
+        for (sig, cons) in symbols.iter_cons_signs() {
+            if starts_with(sig) {
+                ops.push(
+                    sig.to_vec(),
+                    Some(modl_ref),
+                    Target::Cons(cons[0]),
+                    fixity(sig),
+                );
+                found = true;
+            }
+        }
+
+        if !found {
+            self.cascading.set(modl_ref == self.ctx.poisoned_modl());
+            let names = self.ctx.names.borrow();
+            let similar = error::similar_names(
+                names.get(id).unwrap(),
+                record.first_words().map(|id| names.get(id).unwrap()),
+            );
+            Err(Diagnostic::new(
+                error::UnresolvedName,
+                format!("Unresolved name: {}", atom.node),
+            )
+            .with_primary(
+                atom.span,
+                format!("{} defines no operator starting with this", &record_name),
+            )
+            .with_suggestions(&similar))?
+        }
+        Ok(token)
+    }
+
+    fn resolve_flat<T: Display>(
+        &self,
+        atoms: &[Spanned<ast::Atom<'ctx, T>>],
+        tokens: &[Token],
+        ops: Operators,
+        allow_apply: bool,
+    ) -> error::Result<Resolved> {
+        let words: HashSet<_> = tokens.iter().filter(|&&tok| tok != Token::Atom).collect();
+
+        let mut sigs = Vec::new();
+        let mut qualifiers = Vec::new();
+        let mut targets = Vec::new();
+        let mut fixities = Vec::new();
+        let mut candidates = Vec::new();
+        for (ix, sig) in ops.sigs.into_iter().enumerate() {
+            let (qualifier, fixity) = (ops.qualifiers[ix], ops.fixities[ix]);
+            let op_words = mixfix::word_tokens(&sig, qualifier);
+            if op_words.iter().all(|tok| words.contains(tok)) {
+                sigs.push(sig.clone());
+                qualifiers.push(qualifier);
+                targets.push(ops.targets[ix]);
+                fixities.push(fixity);
+            }
+            if op_words.iter().any(|tok| words.contains(tok)) {
+                candidates.push((sig, fixity));
+            }
+        }
+
+        let resolver =
+            mixfix::Resolver::new(&sigs, &qualifiers, Some(&fixities), tokens, allow_apply);
+        let err = match resolver.resolve() {
+            Ok(tree) => {
+                return Ok(Resolved {
+                    tree,
+                    targets,
+                    sigs,
+                })
+            }
+            Err(err) => err,
+        };
+
+        let span = atoms[0].span.to(atoms[atoms.len() - 1].span);
+        let candidates = candidates
+            .iter()
+            .map(|(sig, fixity)| format!("{} ({})", self.ctx.wrap(&sig[..]), fixity))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let with_candidates = |diag: Diagnostic| {
+            if candidates.is_empty() {
+                diag
+            } else {
+                diag.with_note(format!("candidates are: {}", candidates))
+            }
+        };
+
+        // Tell apart input that no fixities could ever make sense
+        // of from input which merely needs some parentheses.
+        if let mixfix::Error::NoMatch = err {
+            let ungrouped = mixfix::Resolver::new(&sigs, &qualifiers, None, tokens, allow_apply);
+            if let Ok(_) | Err(mixfix::Error::Ambiguous(..)) = ungrouped.resolve() {
+                Err(with_candidates(
+                    Diagnostic::new(
+                        error::UngroupableOperators,
+                        "Operators cannot be grouped by their fixities",
+                    )
+                    .with_primary(span, "these operators need parentheses")
+                    .with_help("Add parentheses to make the grouping explicit."),
+                ))?
+            }
+        }
+
+        match err {
+            mixfix::Error::NoMatch => Err(with_candidates(
+                Diagnostic::new(error::NoMatchingSignature, "No operator matches this input")
+                    .with_primary(span, "no matching signature"),
+            ))?,
+            mixfix::Error::Ambiguous(first, second) => {
+                let word = |id| self.ctx.names.borrow().get(id).unwrap().to_owned();
+                let atom = |ix: usize| atoms[ix].to_string();
+                let first = mixfix::render(&first, &sigs, &word, &atom);
+                let second = mixfix::render(&second, &sigs, &word, &atom);
+                Err(with_candidates(
+                    Diagnostic::new(error::AmbiguousSignature, "Ambiguous use of operators")
+                        .with_primary(span, "this can be read more than one way")
+                        .with_note(format!("could be: {}", first))
+                        .with_note(format!("or: {}", second)),
+                ))?
+            }
+        }
+    }
+
+    fn lower_flat(
+        &mut self,
+        atoms: &[Spanned<ast::Atom<'ctx, Spanned<ast::Expr<'ctx>>>>],
+    ) -> error::Result<Expr> {
+        let mut ops = self.visible_operators(true)?;
+
+        let mut tokens = Vec::new();
+        for atom in atoms.iter() {
+            let token = match atom.node {
+                ast::Atom::Ident(id) => {
+                    let id_ref = self.make_ident(id);
+                    if self.locals.contains(&id_ref) {
+                        Token::Atom
+                    } else if ops.has_word(id_ref) {
+                        Token::Word(id_ref)
+                    } else {
+                        self.cascading.set(self.sees_poison());
+                        let names = self.ctx.names.borrow();
+                        let words = ops.sigs.iter().flat_map(|sig| mixfix::words(sig));
+                        let visible = self.locals.iter().copied().chain(words);
+                        let similar =
+                            error::similar_names(id.0, visible.map(|id| names.get(id).unwrap()));
+                        Err(Diagnostic::new(
+                            error::UnresolvedName,
+                            format!("Unresolved name: {}", id.0),
+                        )
+                        .with_primary(atom.span, "not found in this scope")
+                        .with_suggestions(&similar))?
+                    }
+                }
+                ast::Atom::Qualified(path, id) => {
+                    self.qualified_operators(&mut ops, atom, path, id, true)?
+                }
+                _ => Token::Atom,
+            };
+            tokens.push(token);
+        }
+
+        let resolved = self.resolve_flat(atoms, &tokens, ops, true)?;
+        self.build_expr(&resolved.tree, 0, &resolved, atoms)
+    }
+
+    fn build_expr(
+        &mut self,
+        tree: &Tree,
+        start: usize,
+        resolved: &Resolved,
+        atoms: &[Spanned<ast::Atom<'ctx, Spanned<ast::Expr<'ctx>>>>],
+    ) -> error::Result<Expr> {
+        let span = resolved.span(tree, start, atoms);
+        let expr = match tree {
+            Tree::Atom(ix) => self.lower_expr_atom(&atoms[*ix])?,
+            Tree::Apply(func, arg) => {
+                let arg_start = start + mixfix::width(func, &resolved.sigs);
+                let func = self.build_expr(func, start, resolved, atoms)?;
+                let arg = self.build_expr(arg, arg_start, resolved, atoms)?;
+                Expr::Apply(Box::new(func), Box::new(arg))
+            }
+            Tree::Op(op_ix, args) => {
+                let mut ir_args = Vec::new();
+                for (arg, arg_start) in resolved.args(*op_ix, args, start) {
+                    ir_args.push(self.build_expr(arg, arg_start, resolved, atoms)?);
+                }
+                match resolved.targets[*op_ix] {
+                    Target::Decl(decl_ref) => {
+                        ir_args.into_iter().fold(Expr::Var(decl_ref), |func, arg| {
+                            Expr::Apply(Box::new(func), Box::new(arg))
+                        })
+                    }
+                    // Picks the name's part out of the whole `let` value.
+                    Target::Let(decl_ref, id) => {
+                        let ir = self.ctx.ir.borrow();
+                        let patn = ir.patn.get(self.let_patns[&decl_ref]).unwrap().clone();
+                        let tree = decision::compile(vec![vec![&patn]]);
+                        Expr::Match(
+                            Box::new(Expr::Var(decl_ref)),
+                            vec![(patn, Expr::Local(id))],
+                            Rc::new(tree),
+                        )
+                    }
+                    Target::Cons(cons_ref) => Expr::Data(cons_ref, ir_args),
+                }
+            }
+        };
+        Ok(spanned_expr(span, expr))
+    }
+
+    fn lower_expr_atom(
+        &mut self,
+        atom: &Spanned<ast::Atom<'ctx, Spanned<ast::Expr<'ctx>>>>,
+    ) -> error::Result<Expr> {
+        if let Some(lit) = lower_literal(atom) {
+            return Ok(Expr::Literal(lit));
+        }
+
+        match atom.node {
+            ast::Atom::Hole => Ok(Expr::Hole),
+            ast::Atom::Nested(expr) => self.lower_expr(expr),
+            ast::Atom::Ident(id) => Ok(Expr::Local(self.make_ident(id))),
+            _ => unreachable!(),
+        }
+    }
+
+    fn lower_patn(
+        &mut self,
+        patn: &Spanned<ast::Patn<'ctx>>,
+        binders: &mut Vec<Ident>,
+    ) -> error::Result<Patn> {
+        match patn.node {
+            ast::Patn::Flat(atoms) => {
+                // Only constructors can appear in patterns; any
+                // other name is a new binding.
+                let mut ops = self.visible_operators(false)?;
+                let mut tokens = Vec::new();
+                for atom in atoms.iter() {
+                    let token = match atom.node {
+                        ast::Atom::Ident(id) => {
+                            let id_ref = self.make_ident(id);
+                            if ops.has_word(id_ref) {
+                                Token::Word(id_ref)
+                            } else {
+                                Token::Atom
+                            }
+                        }
+                        ast::Atom::Qualified(path, id) => {
+                            self.qualified_operators(&mut ops, atom, path, id, false)?
+                        }
+                        _ => Token::Atom,
+                    };
+                    tokens.push(token);
+                }
+
+                let resolved = self.resolve_flat(atoms, &tokens, ops, false)?;
+                self.build_patn(&resolved.tree, 0, &resolved, atoms, binders)
+            }
+            ast::Patn::Annotated(inner, ty) => {
+                let inner = self.lower_patn(inner, binders)?;
+                let annotation = self.lower_type(ty.node, ty.span, None)?;
+                let annotated = Patn::Annotated(Box::new(inner), annotation, ty.span);
+                Ok(Patn::Spanned(patn.span, Box::new(annotated)))
+            }
+            ast::Patn::Scoped(decls, inner) => {
+                let modl_ref = phases::process_scoped_modl(self.ctx, self.modl, decls, patn.span)?;
+                self.in_modl(modl_ref, |this| {
+                    this.declare_lets()?;
+                    phases::check_imports(this.ctx, this.modl)?;
+                    this.lower_record()?;
+                    this.lower_patn(inner, binders)
+                })
+            }
+        }
+    }
+
+    fn build_patn(
+        &mut self,
+        tree: &Tree,
+        start: usize,
+        resolved: &Resolved,
+        atoms: &[Spanned<ast::Atom<'ctx, Spanned<ast::Patn<'ctx>>>>],
+        binders: &mut Vec<Ident>,
+    ) -> error::Result<Patn> {
+        let span = resolved.span(tree, start, atoms);
+        let patn = match tree {
+            Tree::Atom(ix) => self.lower_patn_atom(&atoms[*ix], binders)?,
+            Tree::Op(op_ix, args) => {
+                let mut ir_args = Vec::new();
+                for (arg, arg_start) in resolved.args(*op_ix, args, start) {
+                    ir_args.push(self.build_patn(arg, arg_start, resolved, atoms, binders)?);
+                }
+                match resolved.targets[*op_ix] {
+                    Target::Cons(cons_ref) => Patn::Data(cons_ref, ir_args),
+                    Target::Decl(_) | Target::Let(..) => {
+                        unreachable!("Patterns only use constructors.")
+                    }
+                }
+            }
+            Tree::Apply(..) => unreachable!("Patterns have no application."),
+        };
+        Ok(match patn {
+            Patn::Spanned(..) => patn,
+            _ => Patn::Spanned(span, Box::new(patn)),
+        })
+    }
+
+    fn lower_patn_atom(
+        &mut self,
+        atom: &Spanned<ast::Atom<'ctx, Spanned<ast::Patn<'ctx>>>>,
+        binders: &mut Vec<Ident>,
+    ) -> error::Result<Patn> {
+        if let Some(lit) = lower_literal(atom) {
+            return Ok(Patn::Literal(lit));
+        }
+
+        match atom.node {
+            ast::Atom::Hole => Ok(Patn::Empty),
+            ast::Atom::Nested(patn) => self.lower_patn(patn, binders),
+            ast::Atom::Ident(id) => {
+                let id_ref = self.make_ident(id);
+                if binders.contains(&id_ref) {
+                    Err(Diagnostic::new(
+                        error::DuplicateBinding,
+                        format!("{} is bound more than once", id.0),
+                    )
+                    .with_primary(atom.span, "already bound earlier in the pattern")
+                    .with_help("Use `_` for parts which don't need a name."))?
+                }
+                binders.push(id_ref);
+                Ok(Patn::Binding(vec![id_ref]))
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::phases;
+
+    use bumpalo::Bump;
+
+    const LIST: &str = "
+        mod List = mod
+            con Nil
+            con Cons _, _
+            def len (Nil) = 0
+            def len (Cons _, xs) = 1 + len xs
+            mod Ops = mod def (a) ++ (b) = len a + len b end
+        end
+    ";
+
+    #[test]
+    fn qualified_names() {
+        let source = |decls: &str| format!("{} {}", LIST, decls);
+        let main = source("def main = List.len (List.Cons 1, List.Nil)");
+        assert_eq!(phases::evaluate(&main), "1");
+        let main = source("def main = List.Nil List.Ops.++ (List.Cons 1, List.Nil)");
+        assert_eq!(phases::evaluate(&main), "1");
+        let main = source(
+            "def first (List.Cons x, _) = x
+             def first (List.Nil) = 0
+             def main = first (List.Cons 7, List.Nil)",
+        );
+        assert_eq!(phases::evaluate(&main), "7");
+
+        let wrong = [
+            (
+                "List.Opz.++ 1",
+                error::UnresolvableModulePath,
+                "Did you mean `Ops`?",
+            ),
+            ("List.lenn 1", error::UnresolvedName, "Did you mean `len`?"),
+        ];
+        for &(expr, code, help) in wrong.iter() {
+            let arena = Bump::new();
+            let ctx = Context::new(&arena);
+            let main = source(&format!("def main = {}", expr));
+            assert_eq!(phases::check_source(&ctx, &main), [code], "{}", expr);
+            let diagnostics = ctx.diagnostics.borrow();
+            assert_eq!(diagnostics[0].help.as_deref(), Some(help));
+        }
+    }
+
+    #[test]
+    fn patterns() {
+        let arena = Bump::new();
+        let ctx = Context::new(&arena);
+        let source = "con Nil con Cons _, _ def first (Cons x, xs) = x def first (Nil) = 0";
+        let modl_ref = phases::load_source(&ctx, source).unwrap();
+        assert_eq!(phases::reported(&ctx), []);
+        let decl_ref = phases::find_decl(&ctx, modl_ref, "first _");
+
+        let ir = ctx.ir.borrow();
+        let record = ir.modl.get(modl_ref).unwrap().as_record().unwrap();
+        let patn = match ir.decl.get(decl_ref).unwrap().sig[1] {
+            Sign::Patn(patn_ref) => ir.patn.get(patn_ref).unwrap(),
+            Sign::Word(_) => unreachable!(),
+        };
+        let (cons_ref, fields) = match decision::unspanned(patn) {
+            Patn::Data(cons_ref, fields) => (*cons_ref, fields),
+            other => panic!("Not a constructor: {:?}", other),
+        };
+        assert_eq!(cons_ref, record.cons[1]);
+        let names = ctx.names.borrow();
+        let expected = ["x", "xs"]
+            .iter()
+            .map(|name| names.get_ident(name).unwrap());
+        let bound = fields.iter().map(|field| match decision::unspanned(field) {
+            Patn::Binding(idents) => idents[0],
+            other => panic!("Not a binding: {:?}", other),
+        });
+        assert!(bound.eq(expected));
+        drop(names);
+        drop(ir);
+
+        let arena = Bump::new();
+        let ctx = Context::new(&arena);
+        let source = "con Nil con Cons _, _ def first (Cons x, x) = x";
+        assert_eq!(
+            phases::check_source(&ctx, source),
+            [error::DuplicateBinding]
+        );
+    }
+}
@@ -0,0 +1,484 @@
+use crate::ast::{Assoc, Fixity};
+use crate::id::Ident;
+use crate::ir::Sign;
+use crate::refs::ModlRef;
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Token {
+    Word(Ident),
+    Qualified(ModlRef, Ident),
+    Atom,
+}
+
+#[derive(Debug, Clone)]
+pub enum Tree {
+    Atom(usize),
+    Apply(Rc<Tree>, Rc<Tree>),
+    Op(usize, Vec<Rc<Tree>>),
+}
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    NoMatch,
+    Ambiguous(Rc<Tree>, Rc<Tree>),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Shape {
+    Closed,
+    Apply,
+    Op(usize),
+}
+
+#[derive(Debug, Clone)]
+struct Parse {
+    tree: Rc<Tree>,
+    shape: Shape,
+}
+
+// Parses of the same shape are interchangeable wherever they are used,
+// so two of each is enough to tell whether the whole input is ambiguous.
+const MAX_PARSES_PER_SHAPE: usize = 2;
+
+// Operators without a declaration bind tighter than any of the
+// builtin arithmetic, and refuse to group with themselves.
+pub const DEFAULT_FIXITY: Fixity = Fixity {
+    assoc: Assoc::Non,
+    level: 10,
+};
+
+pub fn words(sig: &[Sign]) -> impl Iterator<Item = Ident> + '_ {
+    sig.iter().filter_map(|sign| match sign {
+        Sign::Word(id) => Some(*id),
+        Sign::Patn(()) => None,
+    })
+}
+
+// The tokens an operator's words must match. Naming an operator through
+// a module path qualifies its first word, like `List.Cons x , xs`.
+pub fn word_tokens(sig: &[Sign], qualifier: Option<ModlRef>) -> Vec<Token> {
+    words(sig)
+        .enumerate()
+        .map(|(ix, id)| match qualifier {
+            Some(modl_ref) if ix == 0 => Token::Qualified(modl_ref, id),
+            _ => Token::Word(id),
+        })
+        .collect()
+}
+
+pub fn is_closed(sig: &[Sign]) -> bool {
+    matches!(
+        (sig.first(), sig.last()),
+        (Some(Sign::Word(_)), Some(Sign::Word(_)))
+    )
+}
+
+pub struct Resolver<'a> {
+    ops: &'a [Vec<Sign>],
+    op_words: Vec<Vec<Token>>,
+    fixities: Option<&'a [Fixity]>,
+    tokens: &'a [Token],
+    allow_apply: bool,
+    chart: HashMap<(usize, usize), Rc<Vec<Parse>>>,
+}
+
+impl<'a> Resolver<'a> {
+    // Without any fixities, operators may be grouped in any way at all.
+    pub fn new(
+        ops: &'a [Vec<Sign>],
+        qualifiers: &[Option<ModlRef>],
+        fixities: Option<&'a [Fixity]>,
+        tokens: &'a [Token],
+        allow_apply: bool,
+    ) -> Self {
+        let op_words = ops
+            .iter()
+            .zip(qualifiers)
+            .map(|(sig, &qualifier)| word_tokens(sig, qualifier))
+            .collect();
+        Resolver {
+            ops,
+            op_words,
+            fixities,
+            tokens,
+            allow_apply,
+            chart: HashMap::new(),
+        }
+    }
+
+    pub fn resolve(mut self) -> Result<Rc<Tree>, Error> {
+        let parses = self.parse(0, self.tokens.len());
+        match parses.as_slice() {
+            [] => Err(Error::NoMatch),
+            [parse] => Ok(parse.tree.clone()),
+            [first, second, ..] => Err(Error::Ambiguous(first.tree.clone(), second.tree.clone())),
+        }
+    }
+
+    fn add(parses: &mut Vec<Parse>, parse: Parse) {
+        let same_shape = parses.iter().filter(|p| p.shape == parse.shape).count();
+        if same_shape < MAX_PARSES_PER_SHAPE {
+            parses.push(parse);
+        }
+    }
+
+    fn parse(&mut self, start: usize, end: usize) -> Rc<Vec<Parse>> {
+        if let Some(parses) = self.chart.get(&(start, end)) {
+            return parses.clone();
+        }
+
+        let mut parses = Vec::new();
+
+        if end == start + 1 && self.tokens[start] == Token::Atom {
+            let tree = Rc::new(Tree::Atom(start));
+            Self::add(
+                &mut parses,
+                Parse {
+                    tree,
+                    shape: Shape::Closed,
+                },
+            );
+        }
+
+        // Juxtaposition binds tighter than any operator.
+        if self.allow_apply {
+            for mid in start + 1..end {
+                let funcs = self.parse(start, mid);
+                if funcs.is_empty() {
+                    continue;
+                }
+                let args = self.parse(mid, end);
+                for func in funcs.iter() {
+                    if func.shape == Shape::Closed || func.shape == Shape::Apply {
+                        for arg in args.iter().filter(|arg| arg.shape == Shape::Closed) {
+                            let tree = Rc::new(Tree::Apply(func.tree.clone(), arg.tree.clone()));
+                            Self::add(
+                                &mut parses,
+                                Parse {
+                                    tree,
+                                    shape: Shape::Apply,
+                                },
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        for op_ix in 0..self.ops.len() {
+            let mut assignments = Vec::new();
+            self.match_op(op_ix, 0, 0, start, end, &mut Vec::new(), &mut assignments);
+            for holes in assignments {
+                self.fill_holes(op_ix, &holes, &mut parses);
+            }
+        }
+
+        let parses = Rc::new(parses);
+        self.chart.insert((start, end), parses.clone());
+        parses
+    }
+
+    // Find every way of placing the words of an operator within
+    // the span, recording the spans left over for its holes.
+    #[allow(clippy::too_many_arguments)]
+    fn match_op(
+        &self,
+        op_ix: usize,
+        part_ix: usize,
+        word_ix: usize,
+        pos: usize,
+        end: usize,
+        holes: &mut Vec<(usize, usize)>,
+        assignments: &mut Vec<Vec<(usize, usize)>>,
+    ) {
+        let sig = &self.ops[op_ix];
+        match sig.get(part_ix) {
+            None => {
+                if pos == end {
+                    assignments.push(holes.clone());
+                }
+            }
+            Some(Sign::Word(_)) => {
+                if pos < end && self.tokens[pos] == self.op_words[op_ix][word_ix] {
+                    self.match_op(
+                        op_ix,
+                        part_ix + 1,
+                        word_ix + 1,
+                        pos + 1,
+                        end,
+                        holes,
+                        assignments,
+                    );
+                }
+            }
+            Some(Sign::Patn(())) => {
+                for hole_end in pos + 1..=end {
+                    let fits = match sig.get(part_ix + 1) {
+                        None => hole_end == end,
+                        Some(Sign::Word(_)) => {
+                            hole_end < end && self.tokens[hole_end] == self.op_words[op_ix][word_ix]
+                        }
+                        Some(Sign::Patn(())) => true,
+                    };
+                    if fits {
+                        holes.push((pos, hole_end));
+                        self.match_op(
+                            op_ix,
+                            part_ix + 1,
+                            word_ix,
+                            hole_end,
+                            end,
+                            holes,
+                            assignments,
+                        );
+                        holes.pop();
+                    }
+                }
+            }
+        }
+    }
+
+    fn fill_holes(&mut self, op_ix: usize, holes: &[(usize, usize)], parses: &mut Vec<Parse>) {
+        let mut combos: Vec<Vec<Rc<Tree>>> = vec![Vec::new()];
+        for (hole_ix, &(start, end)) in holes.iter().enumerate() {
+            let options = self.parse(start, end);
+            let options: Vec<_> = options
+                .iter()
+                .filter(|option| self.fits(op_ix, hole_ix, holes.len(), option.shape))
+                .collect();
+            let mut next = Vec::new();
+            for combo in combos.iter() {
+                for option in options.iter() {
+                    let mut combo = combo.clone();
+                    combo.push(option.tree.clone());
+                    next.push(combo);
+                }
+            }
+            next.truncate(MAX_PARSES_PER_SHAPE);
+            combos = next;
+        }
+
+        let shape = if is_closed(&self.ops[op_ix]) {
+            Shape::Closed
+        } else {
+            Shape::Op(op_ix)
+        };
+
+        for args in combos {
+            let tree = Rc::new(Tree::Op(op_ix, args));
+            Self::add(parses, Parse { tree, shape });
+        }
+    }
+
+    // Whether an operator may appear unparenthesized in the given hole.
+    fn fits(&self, op_ix: usize, hole_ix: usize, hole_count: usize, child: Shape) -> bool {
+        let fixities = match self.fixities {
+            Some(fixities) => fixities,
+            None => return true,
+        };
+        let child_ix = match child {
+            Shape::Op(child_ix) => child_ix,
+            Shape::Closed | Shape::Apply => return true,
+        };
+
+        let sig = &self.ops[op_ix];
+        let leading = hole_ix == 0 && sig.first() == Some(&Sign::Patn(()));
+        let trailing = hole_ix + 1 == hole_count && sig.last() == Some(&Sign::Patn(()));
+
+        // Inner holes are delimited by words on both sides.
+        if !leading && !trailing {
+            return true;
+        }
+
+        let child_sig = &self.ops[child_ix];
+        let child_leading = child_sig.first() == Some(&Sign::Patn(()));
+        let child_trailing = child_sig.last() == Some(&Sign::Patn(()));
+
+        let outer = fixities[op_ix];
+        let inner = fixities[child_ix];
+        let same_level = inner.level == outer.level;
+
+        if leading {
+            if !child_trailing {
+                return inner.level >= outer.level;
+            }
+            inner.level > outer.level
+                || (same_level
+                    && child_leading
+                    && outer.assoc == Assoc::Left
+                    && inner.assoc == Assoc::Left)
+        } else {
+            if !child_leading {
+                return inner.level >= outer.level;
+            }
+            inner.level > outer.level
+                || (same_level
+                    && child_trailing
+                    && outer.assoc == Assoc::Right
+                    && inner.assoc == Assoc::Right)
+        }
+    }
+}
+
+// Show how a tree groups its input, parenthesizing every operator.
+pub fn render(
+    tree: &Tree,
+    ops: &[Vec<Sign>],
+    word: &dyn Fn(Ident) -> String,
+    atom: &dyn Fn(usize) -> String,
+) -> String {
+    fn go(
+        tree: &Tree,
+        ops: &[Vec<Sign>],
+        word: &dyn Fn(Ident) -> String,
+        atom: &dyn Fn(usize) -> String,
+        nested: bool,
+    ) -> String {
+        let text = match tree {
+            Tree::Atom(ix) => return atom(*ix),
+            Tree::Apply(func, arg) => {
+                let func = go(func, ops, word, atom, false);
+                let arg = go(arg, ops, word, atom, true);
+                format!("{} {}", func, arg)
+            }
+            Tree::Op(op_ix, args) => {
+                let mut args = args.iter();
+                let parts: Vec<_> = ops[*op_ix]
+                    .iter()
+                    .map(|sign| match sign {
+                        Sign::Word(id) => word(*id),
+                        Sign::Patn(()) => go(args.next().unwrap(), ops, word, atom, true),
+                    })
+                    .collect();
+                parts.join(" ")
+            }
+        };
+        if nested {
+            format!("({})", text)
+        } else {
+            text
+        }
+    }
+
+    go(tree, ops, word, atom, false)
+}
+
+// How many tokens of the input a tree was parsed from.
+pub fn width(tree: &Tree, ops: &[Vec<Sign>]) -> usize {
+    match tree {
+        Tree::Atom(_) => 1,
+        Tree::Apply(func, arg) => width(func, ops) + width(arg, ops),
+        Tree::Op(op_ix, args) => {
+            let words = words(&ops[*op_ix]).count();
+            words + args.iter().map(|arg| width(arg, ops)).sum::<usize>()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ctx::Context;
+    use crate::error;
+    use crate::phases;
+
+    use bumpalo::Bump;
+
+    // The words operators are made of, by their idents.
+    const WORDS: [&str; 4] = ["+", "-", "len", "fib"];
+
+    fn word(name: &str) -> Ident {
+        Ident::from(WORDS.iter().position(|&word| word == name).unwrap())
+    }
+
+    fn op(sig: &str) -> Vec<Sign> {
+        let parts = sig.split(' ');
+        parts
+            .map(|part| match part {
+                "_" => Sign::Patn(()),
+                name => Sign::Word(word(name)),
+            })
+            .collect()
+    }
+
+    // Shows how the input groups, where anything but a word is an atom.
+    fn group(sigs: &[&str], fixities: Option<&[Fixity]>, input: &str) -> Result<String, Error> {
+        let ops: Vec<_> = sigs.iter().map(|sig| op(sig)).collect();
+        let qualifiers = vec![None; ops.len()];
+        let parts: Vec<_> = input.split(' ').collect();
+        let tokens: Vec<_> = parts
+            .iter()
+            .map(|part| match WORDS.contains(part) {
+                true => Token::Word(word(part)),
+                false => Token::Atom,
+            })
+            .collect();
+        let tree = Resolver::new(&ops, &qualifiers, fixities, &tokens, true).resolve()?;
+        let word = |id: Ident| WORDS[usize::from(id)].to_owned();
+        Ok(render(&tree, &ops, &word, &|ix| parts[ix].to_owned()))
+    }
+
+    #[test]
+    fn operators_match_their_signatures() {
+        let sigs = ["_ + _", "len _"];
+        assert_eq!(group(&sigs, None, "1 + len xs").unwrap(), "1 + (len xs)");
+        // Applying binds tighter than any operator.
+        assert_eq!(group(&sigs, None, "len f xs").unwrap(), "len (f xs)");
+        assert!(matches!(group(&sigs, None, "len"), Err(Error::NoMatch)));
+        assert!(matches!(
+            group(&sigs, None, "1 + 2 + 3"),
+            Err(Error::Ambiguous(..))
+        ));
+    }
+
+    #[test]
+    fn fixities_group_operators() {
+        let sigs = ["_ + _", "_ - _", "fib _"];
+        let additive = Fixity {
+            assoc: Assoc::Left,
+            level: 6,
+        };
+        let fixities = [additive, additive, DEFAULT_FIXITY];
+        let group = |input| group(&sigs, Some(&fixities), input).unwrap();
+        assert_eq!(group("fib a + fib b"), "(fib a) + (fib b)");
+        assert_eq!(group("a - b - c"), "(a - b) - c");
+        assert_eq!(group("a - b + c"), "(a - b) + c");
+        assert_eq!(group("fib a - b"), "(fib a) - b");
+    }
+
+    #[test]
+    fn fixities_must_agree_and_allow_grouping() {
+        let sources = [
+            ("infixl 6 _ <> _ infixr 6 _ <> _", error::ConflictingFixity),
+            ("def x = 1 == 2 == 3", error::UngroupableOperators),
+            (
+                "def (a) ++ (b) = a def x = 1 ++ 2 ++ 3",
+                error::UngroupableOperators,
+            ),
+        ];
+        for &(source, code) in sources.iter() {
+            let arena = Bump::new();
+            let ctx = Context::new(&arena);
+            assert_eq!(phases::check_source(&ctx, source), [code], "{}", source);
+        }
+
+        let arena = Bump::new();
+        let ctx = Context::new(&arena);
+        let source = "def fib (n) = match n < 2 | True = n | False = fib (n - 1) + fib (n - 2) end";
+        phases::load_source(&ctx, source).unwrap();
+        assert_eq!(phases::reported(&ctx), []);
+    }
+
+    #[test]
+    fn unmatched_input_lists_the_candidates() {
+        let arena = Bump::new();
+        let ctx = Context::new(&arena);
+        let codes = phases::check_source(&ctx, "def len (l) = 0 def f = len");
+        assert_eq!(codes, [error::NoMatchingSignature]);
+        let diagnostics = ctx.diagnostics.borrow();
+        assert_eq!(diagnostics[0].notes, ["candidates are: len _ (infix 10)"]);
+    }
+}
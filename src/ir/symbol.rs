@@ -1,3 +1,6 @@
+use crate::ast::Fixity;
+use crate::id::Ident;
+use crate::ir::types::TypeCon;
 use crate::ir::{ConsRef, DeclRef, Sign};
 
 use std::collections::HashMap;
@@ -6,6 +9,8 @@ use std::collections::HashMap;
 pub struct SymbolTable {
     decl_signs: HashMap<Vec<Sign>, Vec<DeclRef>>,
     cons_signs: HashMap<Vec<Sign>, Vec<ConsRef>>,
+    fixities: HashMap<Vec<Sign>, Fixity>,
+    types: HashMap<Ident, TypeCon>,
 }
 
 impl SymbolTable {
@@ -13,6 +18,8 @@ impl SymbolTable {
         SymbolTable {
             decl_signs: HashMap::new(),
             cons_signs: HashMap::new(),
+            fixities: HashMap::new(),
+            types: HashMap::new(),
         }
     }
 
@@ -24,6 +31,28 @@ impl SymbolTable {
         self.cons_signs.values().flatten().copied()
     }
 
+    pub fn iter_decl_signs<'me>(
+        &'me self,
+    ) -> impl Iterator<Item = (&'me [Sign], &'me [DeclRef])> + 'me {
+        self.decl_signs
+            .iter()
+            .map(|(sign, refs)| (&sign[..], &refs[..]))
+    }
+
+    pub fn iter_cons_signs<'me>(
+        &'me self,
+    ) -> impl Iterator<Item = (&'me [Sign], &'me [ConsRef])> + 'me {
+        self.cons_signs
+            .iter()
+            .map(|(sign, refs)| (&sign[..], &refs[..]))
+    }
+
+    pub fn iter_fixities<'me>(&'me self) -> impl Iterator<Item = (&'me [Sign], Fixity)> + 'me {
+        self.fixities
+            .iter()
+            .map(|(sign, &fixity)| (&sign[..], fixity))
+    }
+
     pub fn lookup_decl(&self, sign: &[Sign]) -> &[DeclRef] {
         self.decl_signs.get(sign).map(AsRef::as_ref).unwrap_or(&[])
     }
@@ -47,4 +76,39 @@ impl SymbolTable {
             Entry::Vacant(vacant) => vacant.insert(Vec::new()).push(cons_ref),
         };
     }
+
+    pub fn lookup_fixity(&self, sign: &[Sign]) -> Option<Fixity> {
+        self.fixities.get(sign).copied()
+    }
+
+    // Returns any different fixity already declared for the signature.
+    pub fn new_fixity(&mut self, sign: Vec<Sign>, fixity: Fixity) -> Option<Fixity> {
+        match self.fixities.insert(sign.clone(), fixity) {
+            Some(previous) if previous != fixity => {
+                self.fixities.insert(sign, previous);
+                Some(previous)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn iter_types<'me>(&'me self) -> impl Iterator<Item = (Ident, TypeCon)> + 'me {
+        self.types.iter().map(|(&id, &con)| (id, con))
+    }
+
+    pub fn lookup_type(&self, id: Ident) -> Option<TypeCon> {
+        self.types.get(&id).copied()
+    }
+
+    // Returns the type already declared with the name, if any.
+    pub fn new_type(&mut self, id: Ident, con: TypeCon) -> Option<TypeCon> {
+        use std::collections::hash_map::Entry;
+        match self.types.entry(id) {
+            Entry::Occupied(occupied) => Some(*occupied.get()),
+            Entry::Vacant(vacant) => {
+                vacant.insert(con);
+                None
+            }
+        }
+    }
 }
@@ -0,0 +1,164 @@
+use crate::ctx::{Context, WithContext};
+use crate::refs::*;
+use crate::storage::*;
+
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
+pub type TypeVar = usize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    Var(TypeVar),
+    Con(TypeCon, Vec<Type>),
+    Func(Box<Type>, Box<Type>),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TypeCon {
+    Unit,
+    Number,
+    String,
+    Data(DataRef),
+}
+
+impl Type {
+    pub fn base(con: TypeCon) -> Self {
+        Type::Con(con, Vec::new())
+    }
+
+    pub fn func(params: impl IntoIterator<Item = Type>, result: Type) -> Self {
+        let params: Vec<_> = params.into_iter().collect();
+        params.into_iter().rev().fold(result, |result, param| {
+            Type::Func(Box::new(param), Box::new(result))
+        })
+    }
+}
+
+// A type which may be used at any instance of the variables listed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scheme {
+    pub vars: Vec<TypeVar>,
+    pub ty: Type,
+}
+
+#[derive(Debug, Clone)]
+pub struct TypeStorage {
+    pub decl: HashStorage<Scheme, DeclRef>,
+    pub cons: HashStorage<Scheme, ConsRef>,
+}
+
+impl TypeStorage {
+    pub fn new() -> Self {
+        TypeStorage {
+            decl: HashStorage::new(),
+            cons: HashStorage::new(),
+        }
+    }
+}
+
+// Where a type is shown decides whether it needs parentheses.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Position {
+    Whole,
+    Param,
+    Argument,
+}
+
+/*
+    Type variables are named by letter in the order they are first
+    shown, so types shown alongside each other agree about what
+    each letter stands for.
+*/
+#[derive(Debug, Default)]
+pub struct TypeNames(HashMap<TypeVar, usize>);
+
+impl TypeNames {
+    pub fn show<'ctx>(&mut self, ctx: &'ctx Context<'ctx>, ty: &Type) -> String {
+        let mut out = String::new();
+        self.write(ctx, ty, Position::Whole, &mut out).unwrap();
+        out
+    }
+
+    fn var(&mut self, var: TypeVar) -> String {
+        let next = self.0.len();
+        let ix = *self.0.entry(var).or_insert(next);
+        let letter = (b'a' + (ix % 26) as u8) as char;
+        match ix / 26 {
+            0 => letter.to_string(),
+            n => format!("{}{}", letter, n),
+        }
+    }
+
+    fn write<'ctx>(
+        &mut self,
+        ctx: &'ctx Context<'ctx>,
+        ty: &Type,
+        position: Position,
+        out: &mut String,
+    ) -> fmt::Result {
+        match ty {
+            Type::Var(var) => write!(out, "{}", self.var(*var)),
+            Type::Con(con, args) => {
+                let parens = position == Position::Argument && !args.is_empty();
+                if parens {
+                    write!(out, "(")?;
+                }
+                write_con(ctx, *con, out)?;
+                for arg in args.iter() {
+                    write!(out, " ")?;
+                    self.write(ctx, arg, Position::Argument, out)?;
+                }
+                if parens {
+                    write!(out, ")")?;
+                }
+                Ok(())
+            }
+            Type::Func(param, result) => {
+                let parens = position != Position::Whole;
+                if parens {
+                    write!(out, "(")?;
+                }
+                self.write(ctx, param, Position::Param, out)?;
+                write!(out, " -> ")?;
+                self.write(ctx, result, Position::Whole, out)?;
+                if parens {
+                    write!(out, ")")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn write_con<'ctx>(ctx: &'ctx Context<'ctx>, con: TypeCon, out: &mut String) -> fmt::Result {
+    match con {
+        TypeCon::Unit => write!(out, "()"),
+        TypeCon::Number => write!(out, "Num"),
+        TypeCon::String => write!(out, "Str"),
+        // Types without a name are shown as their constructors.
+        TypeCon::Data(data_ref) => {
+            let ir = ctx.ir.borrow();
+            let data = ir.data.get(data_ref).unwrap();
+            if let Some(name) = data.name {
+                return write!(out, "{}", ctx.names.borrow().get(name).unwrap());
+            }
+            let sigs: Vec<_> = data
+                .cons
+                .iter()
+                .map(|&cons_ref| {
+                    let sig = &ir.cons.get(cons_ref).unwrap().sig;
+                    ctx.wrap(&sig[..]).to_string()
+                })
+                .collect();
+            write!(out, "{{{}}}", sigs.join(" | "))
+        }
+    }
+}
+
+impl fmt::Display for WithContext<'_, &Scheme> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names = TypeNames::default();
+        write!(f, "{}", names.show(self.ctx, &self.val.ty))
+    }
+}
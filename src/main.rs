@@ -3,41 +3,234 @@ extern crate derive_more;
 extern crate lalrpop_util;
 
 mod ast;
+mod builtin;
+mod bytecode;
 mod ctx;
+mod doc;
 mod error;
+mod eval;
 mod id;
 mod ir;
 mod phases;
 mod refs;
+mod repl;
+mod span;
 mod storage;
+mod vm;
 
 pub use ast::parser;
 
 use bumpalo::Bump;
 
 use ctx::*;
+use error::Diagnostic;
 use ir::*;
+use storage::*;
 
 fn main() {
-    match _main() {
-        Ok(()) => (),
-        Err(err) => eprintln!("{}", err),
+    let arena = Bump::new();
+    let ctx = Context::new(&arena);
+    if let Err(err) = _main(&ctx) {
+        ctx.report(err);
+    }
+
+    for diag in ctx.diagnostics.borrow().iter() {
+        eprintln!("{}", ctx.wrap(diag));
+    }
+
+    let errors = ctx.error_count();
+    if errors > 0 {
+        let plural = if errors == 1 { "" } else { "s" };
+        eprintln!("error: aborting due to {} error{}", errors, plural);
+        std::process::exit(1);
     }
 }
 
-fn _main() -> error::Result<()> {
-    println!("tgif");
+/*
+    `friday FILE...` checks the files and shows what they lower to,
+    while `friday run FILE... [--eval NAME]` evaluates the definition
+    named `NAME`, or `main` if there's no `--eval`, and prints it.
+    With `--vm` it's compiled to bytecode and run on the machine
+    instead of by the interpreter. `friday build FILE... [-o OUT]`
+    compiles the files' closed definitions to a bytecode file, named
+    after the first file unless there's an `-o`, which `friday run`
+    can then run on its own. `friday fmt FILE... [--check]` lays the
+    files out in the usual way, or with `--check` only reports the
+    ones that aren't already. Any of them also takes `--verbose`,
+    to have each phase say what it's doing.
+    `friday repl FILE...` loads the files and reads definitions
+    and expressions to evaluate interactively.
+*/
+struct Options {
+    repl: bool,
+    vm: bool,
+    build: bool,
+    doc: bool,
+    format: bool,
+    check: bool,
+    verbose: bool,
+    output: Option<String>,
+    entry: Option<String>,
+    files: Vec<String>,
+}
 
-    let arena = Bump::new();
-    let ctx = Context::new(&arena);
+fn parse_args(mut args: impl Iterator<Item = String>) -> error::Result<Options> {
+    let mut args = args.by_ref().peekable();
+    let mut entry = None;
+    let mut repl = false;
+    let mut vm = false;
+    let mut build = false;
+    let mut doc = false;
+    let mut format = false;
+    let mut check = false;
+    let mut verbose = false;
+    let mut output = None;
+    match args.peek().map(String::as_str) {
+        Some("run") => {
+            args.next();
+            entry = Some("main".to_owned());
+        }
+        Some("repl") => {
+            args.next();
+            repl = true;
+        }
+        Some("build") => {
+            args.next();
+            build = true;
+        }
+        Some("doc") => {
+            args.next();
+            doc = true;
+        }
+        Some("fmt") => {
+            args.next();
+            format = true;
+        }
+        _ => {}
+    }
+
+    let mut files = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--eval" {
+            let name = args.next().ok_or_else(|| {
+                Diagnostic::new(error::InvalidArguments, "Expected a name after --eval")
+            })?;
+            entry = Some(name);
+        } else if arg == "-o" {
+            let file = args.next().ok_or_else(|| {
+                Diagnostic::new(error::InvalidArguments, "Expected a file name after -o")
+            })?;
+            output = Some(file);
+        } else if arg == "--vm" {
+            vm = true;
+        } else if arg == "--check" {
+            check = true;
+        } else if arg == "--verbose" {
+            verbose = true;
+        } else {
+            files.push(arg);
+        }
+    }
+
+    Ok(Options {
+        repl,
+        vm,
+        build,
+        doc,
+        format,
+        check,
+        verbose,
+        output,
+        entry,
+        files,
+    })
+}
 
-    for arg in std::env::args().skip(1) {
-        println!("--- {} ---", arg);
-        phases::process_file(&ctx, &arg)?;
+fn _main<'ctx>(ctx: &'ctx Context<'ctx>) -> error::Result<()> {
+    let options = parse_args(std::env::args().skip(1))?;
+    ctx.verbose.set(options.verbose);
+    ctx.trace(format_args!("tgif"));
+    if options.repl {
+        return repl::run(ctx.arena, options.files);
+    }
+    if options.format {
+        for file in options.files.iter() {
+            ctx.trace(format_args!("--- {} ---", file));
+            if let Err(err) = format_file(ctx, file, options.check) {
+                ctx.report(err);
+            }
+        }
+        return Ok(());
+    }
+    if options
+        .files
+        .iter()
+        .any(|file| bytecode::file::is_bytecode(file))
+    {
+        return run_bytecode(ctx, options);
     }
 
-    println!("--- resolving aliases ---");
-    phases::process_aliases(&ctx)?;
+    let mut files = Vec::new();
+    for arg in options.files.iter() {
+        ctx.trace(format_args!("--- {} ---", arg));
+        match phases::process_file(ctx, arg) {
+            Ok(modl_ref) => files.push(modl_ref),
+            Err(err) => ctx.report(err),
+        }
+    }
+
+    ctx.trace(format_args!("--- resolving aliases ---"));
+    phases::process_aliases(ctx)?;
+
+    ctx.trace(format_args!("--- lowering ---"));
+    lower::lower_modls(ctx, &files)?;
+
+    ctx.trace(format_args!("--- inferring types ---"));
+    infer::infer_types(ctx);
+
+    // Nothing can be run or built until everything has lowered successfully.
+    if (options.entry.is_some() || options.build) && ctx.error_count() > 0 {
+        return Ok(());
+    }
+
+    if options.doc {
+        let output = options.output.unwrap_or_else(|| "doc".to_owned());
+        ctx.trace(format_args!("--- writing {} ---", output));
+        return doc::generate(ctx, &output);
+    }
+
+    if options.build {
+        let output = match options.output {
+            Some(output) => output,
+            None => {
+                let first = options.files.first().ok_or_else(|| {
+                    Diagnostic::new(error::InvalidArguments, "Expected files to build")
+                })?;
+                std::path::Path::new(first)
+                    .with_extension("fbc")
+                    .to_string_lossy()
+                    .into_owned()
+            }
+        };
+        ctx.trace(format_args!("--- writing {} ---", output));
+        let program = bytecode::compile::compile(ctx, &ctx.ir.borrow(), &files);
+        return bytecode::file::save(ctx, &program, &output);
+    }
+
+    if let Some(name) = options.entry {
+        let ir = ctx.ir.borrow();
+        if options.vm {
+            let program = bytecode::compile::compile(ctx, &ir, &files);
+            let global = program.find_entry(ctx, &name)?;
+            let value = vm::Machine::new(ctx, &program).run(global)?;
+            println!("{}", ctx.wrap((&program, &value)));
+        } else {
+            let decl_ref = eval::find_entry(ctx, &files, &name)?;
+            let value = eval::Interpreter::new(ctx, &ir).eval_decl(decl_ref)?;
+            println!("{}", ctx.wrap(&value));
+        }
+        return Ok(());
+    }
 
     println!("--- all modules: ---");
     let ir = ctx.ir.borrow();
@@ -45,5 +238,81 @@ fn _main() -> error::Result<()> {
         println!("{:?} = {:?}", modl_ref, modl);
     }
 
+    println!("--- all declarations: ---");
+    for (decl_ref, decl) in &ir.decl {
+        let body = ir.expr.get(decl.body).unwrap();
+        println!("{:?} {:?} = {:?}", decl_ref, decl.sig, body);
+        if let Some(scheme) = ir.types.decl.get(decl_ref) {
+            println!("{:?} : {}", decl_ref, ctx.wrap(scheme));
+        }
+    }
+
+    Ok(())
+}
+
+// A bytecode file is already compiled, so it can only be run, and on its own.
+fn run_bytecode<'ctx>(ctx: &'ctx Context<'ctx>, options: Options) -> error::Result<()> {
+    let (file, name) = match (&options.files[..], options.entry) {
+        ([file], Some(name)) if !options.build => (file, name),
+        ([_], _) => Err(Diagnostic::new(
+            error::InvalidArguments,
+            "Bytecode files can only be run",
+        )
+        .with_help("Try `friday run FILE.fbc`."))?,
+        _ => Err(Diagnostic::new(
+            error::InvalidArguments,
+            "Bytecode files must be run on their own",
+        ))?,
+    };
+
+    ctx.trace(format_args!("--- {} ---", file));
+    let program = bytecode::file::load(ctx, file)?;
+    let global = program.find_entry(ctx, &name)?;
+    let value = vm::Machine::new(ctx, &program).run(global)?;
+    println!("{}", ctx.wrap((&program, &value)));
+    Ok(())
+}
+
+// Only writes the file if formatting it gives back the same declarations.
+fn format_file<'ctx>(ctx: &'ctx Context<'ctx>, file: &str, check: bool) -> error::Result<()> {
+    let (file_ref, decls) = phases::parse_file(ctx, file)?;
+    let files = ctx.files.borrow();
+    let file_data = files.get(file_ref).unwrap();
+    let (source, trivia) = (&file_data.text, &file_data.trivia);
+    let formatted = ast::format::format(source, decls, trivia);
+    if formatted == *source {
+        return Ok(());
+    }
+
+    // Comments have to come out the same as well.
+    let comments = |text, comments: &[ast::trivia::Comment]| {
+        let texts = comments.iter().map(|comment| comment.text(text));
+        texts.collect::<Vec<_>>()
+    };
+    let reparsed = phases::parse_text(ctx, file_ref, &formatted);
+    if !reparsed.is_ok_and(|(reparsed, recomments)| {
+        ast::format::same_structure(decls, &reparsed)
+            && comments(source, &trivia.comments) == comments(&formatted, &recomments)
+    }) {
+        Err(Diagnostic::new(
+            error::UnstableFormat,
+            format!("Formatting {} would change what it means", file),
+        )
+        .with_note("The file was left as it is."))?
+    }
+
+    if check {
+        Err(
+            Diagnostic::new(error::UnformattedFile, format!("{} is not formatted", file))
+                .with_help(format!("Run `friday fmt {}` to format it.", file)),
+        )?
+    }
+
+    std::fs::write(file, formatted).map_err(|err| {
+        Diagnostic::new(
+            error::UnreadableFile,
+            format!("Could not write {}: {}", file, err),
+        )
+    })?;
     Ok(())
 }
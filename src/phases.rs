@@ -1,8 +1,11 @@
 use crate::ast;
 use crate::ctx::*;
-use crate::error;
-use crate::ir;
+use crate::error::{self, Diagnostic};
+use crate::id::Ident;
+use crate::ir::types::TypeCon;
+use crate::ir::{self, mixfix};
 use crate::refs::*;
+use crate::span::{SourceFile, Span, Spanned};
 use crate::storage::*;
 
 use std::collections::{HashSet, VecDeque};
@@ -13,31 +16,122 @@ pub fn verify_file_path(path: &std::path::Path) -> error::Result<String> {
 
     if file_ext != Some("fri") {
         let path_str = path.to_str().unwrap();
-        return Err(error::FridayError::InvalidFilename(path_str.to_owned()))?;
+        Err(Diagnostic::new(
+            error::InvalidFilename,
+            format!("Filename was invalid: {}", path_str),
+        )
+        .with_help("Source files must have the extension `.fri`."))?;
     }
 
     Ok(file_stem.unwrap().to_owned())
 }
 
-pub fn process_file(ctx: &Context<'_>, file_name: &str) -> error::Result<ModlRef> {
+pub type ParseError =
+    lalrpop_util::ParseError<usize, ast::OwnedToken, (usize, usize, &'static str)>;
+
+pub fn parse_error(file: FileRef, err: ParseError) -> Diagnostic {
+    use lalrpop_util::ParseError::*;
+
+    let span = |start, end| Span { file, start, end };
+    let expected = |expected: Vec<String>| format!("Expected one of: {}", expected.join(", "));
+    match err {
+        InvalidToken { location } => Diagnostic::new(error::InvalidSyntax, "Invalid token")
+            .with_primary(span(location, location + 1), "not part of any token"),
+        UnrecognizedEOF {
+            location,
+            expected: exp,
+        } => Diagnostic::new(error::InvalidSyntax, "Unexpected end of file")
+            .with_primary(span(location, location), "the file ends here")
+            .with_note(expected(exp)),
+        UnrecognizedToken {
+            token: (start, tok, end),
+            expected: exp,
+        } => Diagnostic::new(error::InvalidSyntax, format!("Unexpected token `{}`", tok))
+            .with_primary(span(start, end), "unexpected token")
+            .with_note(expected(exp)),
+        ExtraToken {
+            token: (start, tok, end),
+        } => Diagnostic::new(error::InvalidSyntax, format!("Extra token `{}`", tok))
+            .with_primary(span(start, end), "expected nothing more"),
+        User {
+            error: (start, end, message),
+        } => Diagnostic::new(error::InvalidSyntax, message).with_primary(span(start, end), message),
+    }
+}
+
+// Parses declarations, with the comments that were taken out of them.
+pub fn parse_text<'ctx>(
+    ctx: &'ctx Context<'ctx>,
+    file_ref: FileRef,
+    text: &str,
+) -> Result<(Vec<Spanned<ast::Decl<'ctx>>>, Vec<ast::trivia::Comment>), ParseError> {
     use crate::ast::OwnedToken;
     use crate::parser::SequenceParser;
-    use std::fs;
-    use std::path::Path;
 
-    let parse_sequence = SequenceParser::new();
-    let file_path = Path::new(file_name);
+    let (stripped, comments) = ast::trivia::strip(file_ref, text)?;
+    let decls = SequenceParser::new()
+        .parse(ctx.arena, file_ref, &stripped)
+        .map_err(|e| e.map_token(OwnedToken::from))?;
+    Ok((decls, comments))
+}
 
-    let modl_name = verify_file_path(file_path)?;
+fn read_file(file_name: &str) -> error::Result<String> {
+    Ok(std::fs::read_to_string(file_name).map_err(|err| {
+        Diagnostic::new(
+            error::UnreadableFile,
+            format!("Could not read {}: {}", file_name, err),
+        )
+    })?)
+}
 
-    let file_text = fs::read_to_string(file_name)?;
+// Reads and parses a file, keeping its text and comments for diagnostics.
+pub fn parse_file<'ctx>(
+    ctx: &'ctx Context<'ctx>,
+    file_name: &str,
+) -> error::Result<(FileRef, &'ctx [Spanned<ast::Decl<'ctx>>])> {
+    parse_source(ctx, file_name, read_file(file_name)?)
+}
+
+pub fn parse_source<'ctx>(
+    ctx: &'ctx Context<'ctx>,
+    file_name: &str,
+    file_text: String,
+) -> error::Result<(FileRef, &'ctx [Spanned<ast::Decl<'ctx>>])> {
+    let file_ref = ctx.refs.borrow_mut().file.make_ref();
 
-    let decls = match parse_sequence.parse(ctx.arena, &file_text) {
-        Err(e) => Err(e.map_token(OwnedToken::from))?,
-        Ok(decls) => decls,
+    let parsed = parse_text(ctx, file_ref, &file_text);
+    let trivia = match parsed {
+        Ok((ref decls, ref comments)) => ast::trivia::attach(&file_text, comments.clone(), decls),
+        Err(_) => Default::default(),
     };
 
-    let decls = ctx.arena.alloc_slice_copy(&decls);
+    ctx.files.borrow_mut().set(
+        file_ref,
+        SourceFile {
+            name: file_name.to_owned(),
+            text: file_text,
+            trivia,
+        },
+    );
+
+    let (decls, _) = parsed.map_err(|e| parse_error(file_ref, e))?;
+    Ok((file_ref, ctx.arena.alloc_slice_copy(&decls)))
+}
+
+pub fn process_file<'ctx>(ctx: &'ctx Context<'ctx>, file_name: &str) -> error::Result<ModlRef> {
+    verify_file_path(std::path::Path::new(file_name))?;
+    process_source(ctx, file_name, read_file(file_name)?)
+}
+
+// Processes the text as if it were in the file, without reading it.
+pub fn process_source<'ctx>(
+    ctx: &'ctx Context<'ctx>,
+    file_name: &str,
+    file_text: String,
+) -> error::Result<ModlRef> {
+    let modl_name = verify_file_path(std::path::Path::new(file_name))?;
+    let (file_ref, decls) = parse_source(ctx, file_name, file_text)?;
+    let file_len = ctx.files.borrow().get(file_ref).unwrap().text.len();
 
     let modl_ref = {
         let mut refs = ctx.refs.borrow_mut();
@@ -55,6 +149,13 @@ pub fn process_file(ctx: &Context<'_>, file_name: &str) -> error::Result<ModlRef
         let arena_modl_name = ctx.arena.alloc_slice_copy(modl_name.as_bytes());
         let arena_modl_name = unsafe { std::str::from_utf8_unchecked(arena_modl_name) };
 
+        let span = Span {
+            file: file_ref,
+            start: 0,
+            end: file_len,
+        };
+        ir.spans.modl.set(modl_ref, span);
+
         let global_ir = ir
             .modl
             .get_mut(ctx.global_modl())
@@ -65,25 +166,72 @@ pub fn process_file(ctx: &Context<'_>, file_name: &str) -> error::Result<ModlRef
             .insert(names.make_ident(arena_modl_name), modl_ref);
     }
 
+    process_modl_tree(
+        ctx,
+        DeferredModl {
+            modl_ref,
+            name: modl_name,
+            parent: ctx.global_modl(),
+        },
+    )?;
+
+    Ok(modl_ref)
+}
+
+pub fn process_modl_tree<'ctx>(ctx: &'ctx Context<'ctx>, root: DeferredModl) -> error::Result<()> {
     let mut modules = VecDeque::new();
-    modules.push_back(DeferredModl {
-        modl_ref,
-        name: modl_name,
-        parent: ctx.global_modl(),
-    });
+    modules.push_back(root);
 
     // Using a VecDeque here changes this to a more
     // natural fill order in ir storage.
     while let Some(deferred) = modules.pop_front() {
-        modules.extend(process_modl_ast(&ctx, deferred)?);
+        modules.extend(process_modl_ast(ctx, deferred)?);
     }
 
+    Ok(())
+}
+
+pub fn process_scoped_modl<'ctx>(
+    ctx: &'ctx Context<'ctx>,
+    parent: ModlRef,
+    decls: &'ctx [Spanned<ast::Decl<'ctx>>],
+    span: Span,
+) -> error::Result<ModlRef> {
+    let modl_ref = {
+        let mut refs = ctx.refs.borrow_mut();
+        let mut ast = ctx.ast.borrow_mut();
+
+        let modl_ref = refs.modl.make_ref();
+        ast.modl.set(modl_ref, ast::Modl::ModExp(decls));
+        ctx.ir.borrow_mut().spans.modl.set(modl_ref, span);
+        modl_ref
+    };
+
+    let name = {
+        let ir = ctx.ir.borrow();
+        let parent_name = ir.modl.get(parent).unwrap().name();
+        format!("{}.<scope{}>", parent_name, usize::from(modl_ref))
+    };
+
+    process_modl_tree(
+        ctx,
+        DeferredModl {
+            modl_ref,
+            name,
+            parent,
+        },
+    )?;
+
+    // Any aliases inside the new scope still need resolving.
+    process_aliases(ctx)?;
+
     Ok(modl_ref)
 }
 
 pub fn process_aliases<'ctx>(ctx: &'ctx Context<'ctx>) -> error::Result<()> {
     let mut aliases = Vec::new();
 
+    let ir = ctx.ir.borrow();
     for (modl_ref, modl_ast) in &ctx.ast.borrow().modl {
         // Ignore module records, we're just handling aliases here.
         match modl_ast {
@@ -91,6 +239,12 @@ pub fn process_aliases<'ctx>(ctx: &'ctx Context<'ctx>) -> error::Result<()> {
             _ => aliases.push(modl_ref),
         };
     }
+    // Those resolved by an earlier pass needn't be looked at again.
+    aliases.retain(|&modl_ref| match ir.modl.get(modl_ref) {
+        Some(ir::Modl::Alias(alias)) => alias.aliased.is_none(),
+        _ => true,
+    });
+    drop(ir);
 
     /*
         First, find another module (whether it's an alias or a record)
@@ -98,13 +252,13 @@ pub fn process_aliases<'ctx>(ctx: &'ctx Context<'ctx>) -> error::Result<()> {
         tree of modules and their children (and outer scopes, etc.)
     */
     for &modl_ref in &aliases {
-        let aliased_ref = resolve_alias_path(ctx, modl_ref)?;
+        let aliased_ref = resolve_alias_path(ctx, modl_ref, &mut Vec::new())?;
         let ir = ctx.ir.borrow();
-        println!(
+        ctx.trace(format_args!(
             "--- alias {} -> {:?}",
             ir.modl.get(aliased_ref).unwrap().name(),
             aliased_ref
-        );
+        ));
     }
 
     /*
@@ -117,7 +271,10 @@ pub fn process_aliases<'ctx>(ctx: &'ctx Context<'ctx>) -> error::Result<()> {
         or unresolvable alias formulations.
     */
     for &modl_ref in &aliases {
-        let aliased_target_ref = resolve_alias_target(ctx, modl_ref)?;
+        let aliased_target_ref = resolve_alias_target(ctx, modl_ref).unwrap_or_else(|err| {
+            ctx.report(err);
+            ctx.poisoned_modl()
+        });
         let mut ir = ctx.ir.borrow_mut();
         let modl_ir = ir.modl.get_mut(modl_ref).unwrap();
         let alias = modl_ir.as_alias_mut().unwrap();
@@ -147,63 +304,124 @@ fn resolve_alias_target_check_loops<'ctx>(
     };
 
     let aliased_ref = alias.aliased.ok_or_else(|| {
-        error::UnresolvableModulePath(format!("Alias {} does not resolve!", ctx.wrap(alias)))
+        path_error(
+            ctx,
+            modl_ref,
+            format!("Alias {} does not resolve!", ctx.wrap(alias)),
+            "this path",
+        )
     })?;
 
     if seen_refs.contains(&aliased_ref) {
-        Err(error::UnresolvableModulePath(format!(
-            "Alias {} is self referential!",
-            ctx.wrap(alias)
-        )))?
+        Err(path_error(
+            ctx,
+            modl_ref,
+            format!("Alias {} is self referential!", ctx.wrap(alias)),
+            "refers back to itself",
+        ))?
     } else {
         seen_refs.insert(aliased_ref);
         resolve_alias_target_check_loops(ctx, aliased_ref, seen_refs)
     }
 }
 
-fn resolve_alias_path<'ctx>(ctx: &'ctx Context<'ctx>, modl_ref: ModlRef) -> error::Result<ModlRef> {
-    let (full_scope, alias) = {
+fn path_error<'ctx>(
+    ctx: &'ctx Context<'ctx>,
+    modl_ref: ModlRef,
+    message: String,
+    label: &str,
+) -> Box<Diagnostic> {
+    let diag = Diagnostic::new(error::UnresolvableModulePath, message);
+    Box::new(match ctx.ir.borrow().spans.modl.get(modl_ref) {
+        Some(&span) => diag.with_primary(span, label),
+        None => diag,
+    })
+}
+
+/*
+    An alias which can't be resolved is reported and then
+    poisoned, so that it's only ever reported once, and so
+    that anything found through it resolves to nothing
+    without causing any further errors.
+*/
+fn resolve_alias_path<'ctx>(
+    ctx: &'ctx Context<'ctx>,
+    modl_ref: ModlRef,
+    resolving: &mut Vec<ModlRef>,
+) -> error::Result<ModlRef> {
+    let alias = {
         let ir = ctx.ir.borrow();
         let modl_ir = ir.modl.get(modl_ref).unwrap();
         let alias = modl_ir.as_alias()?;
 
-        let scope_ir = ir.modl.get(alias.scope).unwrap();
-        let scope_record = scope_ir.as_record()?;
-        let mut full_scope = Vec::new();
-        full_scope.push(alias.scope);
-        full_scope.extend(scope_record.scope.iter());
-
         match alias.aliased {
             Some(aliased) => return Ok(aliased),
-            None => (full_scope, alias.clone()),
+            None => alias.clone(),
         }
     };
 
-    println!("resolving: {}", ctx.wrap(&alias));
+    let result = if resolving.contains(&modl_ref) {
+        Err(path_error(
+            ctx,
+            modl_ref,
+            format!("Alias {} is self referential!", ctx.wrap(&alias)),
+            "refers back to itself",
+        ))
+    } else {
+        resolving.push(modl_ref);
+        let result = follow_alias_path(ctx, modl_ref, &alias, resolving);
+        resolving.pop();
+        result
+    };
+
+    let aliased = result.unwrap_or_else(|err| {
+        ctx.report(err);
+        ctx.poisoned_modl()
+    });
+    let mut ir = ctx.ir.borrow_mut();
+    let alias = ir.modl.get_mut(modl_ref).unwrap().as_alias_mut()?;
+    alias.aliased = Some(aliased);
+
+    Ok(aliased)
+}
+
+fn follow_alias_path<'ctx>(
+    ctx: &'ctx Context<'ctx>,
+    modl_ref: ModlRef,
+    alias: &ir::ModlAlias,
+    resolving: &mut Vec<ModlRef>,
+) -> error::Result<ModlRef> {
+    ctx.trace(format_args!("resolving: {}", ctx.wrap(alias)));
 
     let first = alias.path[0];
-    let mut scope_ref = 'outer: loop {
-        let ir = ctx.ir.borrow();
-        for &scope_ref in full_scope.iter() {
-            let record = ir.modl.get(scope_ref).unwrap().as_record()?;
-            println!("looking for {} in {}", ctx.wrap(&alias), &record.name);
-            match record.children.get(&first) {
-                Some(&child_ref) if child_ref != modl_ref => break 'outer scope_ref,
-                _ => continue,
-            }
+    let mut scope_ref = match find_path_head(ctx, alias.scope, first, modl_ref, resolving)? {
+        Some(scope_ref) => scope_ref,
+        None => {
+            let names = ctx.names.borrow();
+            let first = names.get(first).unwrap();
+            let visible = ctx.ir.borrow().visible_children(alias.scope);
+            let similar =
+                error::similar_names(first, visible.iter().map(|&id| names.get(id).unwrap()));
+            Err(path_error(
+                ctx,
+                modl_ref,
+                format!("No such module in enclosing scope: {}", first),
+                &format!("`{}` is not visible here", first),
+            )
+            .with_note(format!("When resolving path: {}", ctx.wrap(alias)))
+            .with_suggestions(&similar))?
         }
-        Err(error::UnresolvableModulePath(format!(
-            "No such module in enclosing scope: {}\nWhen resolving path: {}",
-            ctx.names.borrow().get(first).unwrap(),
-            ctx.wrap(&alias)
-        )))?
     };
 
     for &path_elt in alias.path.iter() {
         loop {
+            // Whatever went wrong has already been reported.
+            if scope_ref == ctx.poisoned_modl() {
+                return Ok(scope_ref);
+            }
             let ir = ctx.ir.borrow();
             let scope_modl = ir.modl.get(scope_ref).unwrap();
-            print!(
+            let looking = format!(
                 "looking for {} in {}...",
                 ctx.names.borrow().get(path_elt).unwrap(),
                 scope_modl.name()
@@ -211,38 +429,303 @@ fn resolve_alias_path<'ctx>(ctx: &'ctx Context<'ctx>, modl_ref: ModlRef) -> erro
             match scope_modl {
                 ir::Modl::Record(scope_record) => match scope_record.children.get(&path_elt) {
                     Some(&child_ref) if child_ref != modl_ref => {
-                        println!(" found: {:?}", &child_ref);
+                        ctx.trace(format_args!("{} found: {:?}", looking, &child_ref));
                         scope_ref = child_ref;
                         break;
                     }
-                    Some(_) => Err(error::UnresolvableModulePath(format!(
-                        "Alias {} is self-referential: {} = {}",
-                        ctx.wrap(&alias),
-                        ctx.names.borrow().get(path_elt).unwrap(),
-                        &scope_record.name
-                    )))?,
-                    None => Err(error::UnresolvableModulePath(format!(
-                        "No such module: {}.{}\nWhen resolving path: {}",
-                        &scope_record.name,
-                        ctx.names.borrow().get(path_elt).unwrap(),
-                        ctx.wrap(&alias)
-                    )))?,
+                    Some(_) => Err(path_error(
+                        ctx,
+                        modl_ref,
+                        format!(
+                            "Alias {} is self-referential: {} = {}",
+                            ctx.wrap(alias),
+                            ctx.names.borrow().get(path_elt).unwrap(),
+                            &scope_record.name
+                        ),
+                        "refers back to itself",
+                    ))?,
+                    None => {
+                        let names = ctx.names.borrow();
+                        let path_elt = names.get(path_elt).unwrap();
+                        let children = scope_record.children.keys();
+                        let similar = error::similar_names(
+                            path_elt,
+                            children.map(|&id| names.get(id).unwrap()),
+                        );
+                        Err(path_error(
+                            ctx,
+                            modl_ref,
+                            format!("No such module: {}.{}", &scope_record.name, path_elt),
+                            &format!("not found in {}", &scope_record.name),
+                        )
+                        .with_note(format!("When resolving path: {}", ctx.wrap(alias)))
+                        .with_suggestions(&similar))?
+                    }
                 },
                 ir::Modl::Alias(_) => {
-                    println!(" {:?} = {} is an alias.", &scope_ref, scope_modl.name());
+                    ctx.trace(format_args!(
+                        "{} {:?} = {} is an alias.",
+                        looking,
+                        &scope_ref,
+                        scope_modl.name()
+                    ));
                     drop(ir);
-                    scope_ref = resolve_alias_path(ctx, scope_ref)?;
+                    scope_ref = resolve_alias_path(ctx, scope_ref, resolving)?;
                 }
             }
         }
     }
-    let mut ir = ctx.ir.borrow_mut();
-    let alias = ir.modl.get_mut(modl_ref).unwrap().as_alias_mut()?;
-    alias.aliased = Some(scope_ref);
 
     Ok(scope_ref)
 }
 
+/*
+    The first module named by a path is looked up in each
+    enclosing record in turn, first among its own children
+    and then among those of the modules it opens.
+
+    A `use` only sees through the `use` declarations which
+    precede it in the same record, so that an import can
+    never be needed to resolve itself.
+*/
+fn find_path_head<'ctx>(
+    ctx: &'ctx Context<'ctx>,
+    mut scope_ref: ModlRef,
+    first: Ident,
+    alias_ref: ModlRef,
+    resolving: &mut Vec<ModlRef>,
+) -> error::Result<Option<ModlRef>> {
+    loop {
+        let (found, uses, parent) = {
+            let ir = ctx.ir.borrow();
+            let record = ir.modl.get(scope_ref).unwrap().as_record()?;
+            ctx.trace(format_args!(
+                "looking for {} in {}",
+                ctx.names.borrow().get(first).unwrap(),
+                &record.name
+            ));
+            let found = matches!(
+                record.children.get(&first),
+                Some(&child_ref) if child_ref != alias_ref
+            );
+            let uses = match record.uses.iter().position(|u| u.modl == alias_ref) {
+                Some(ix) => record.uses[..ix].to_vec(),
+                None => record.uses.clone(),
+            };
+            (found, uses, record.scope.first().copied())
+        };
+
+        if found {
+            return Ok(Some(scope_ref));
+        }
+
+        for modl_use in uses {
+            if resolving.contains(&modl_use.modl) || !modl_use.filter.admits_child(first) {
+                continue;
+            }
+            let opened_ref = resolve_opened(ctx, modl_use.modl, resolving)?;
+            let ir = ctx.ir.borrow();
+            let opened = ir.modl.get(opened_ref).unwrap().as_record()?;
+            if opened.children.contains_key(&first) {
+                return Ok(Some(opened_ref));
+            }
+        }
+
+        match parent {
+            Some(parent) => scope_ref = parent,
+            None => return Ok(None),
+        }
+    }
+}
+
+fn resolve_opened<'ctx>(
+    ctx: &'ctx Context<'ctx>,
+    mut modl_ref: ModlRef,
+    resolving: &mut Vec<ModlRef>,
+) -> error::Result<ModlRef> {
+    let mut seen_refs = HashSet::new();
+    loop {
+        let is_alias = ctx
+            .ir
+            .borrow()
+            .modl
+            .get(modl_ref)
+            .unwrap()
+            .as_alias()
+            .is_ok();
+        if !is_alias {
+            return Ok(modl_ref);
+        }
+        if !seen_refs.insert(modl_ref) {
+            let ir = ctx.ir.borrow();
+            let alias = ir.modl.get(modl_ref).unwrap().as_alias()?;
+            Err(path_error(
+                ctx,
+                modl_ref,
+                format!("Alias {} is self referential!", ctx.wrap(alias)),
+                "refers back to itself",
+            ))?
+        }
+        modl_ref = resolve_alias_path(ctx, modl_ref, resolving)?;
+    }
+}
+
+/*
+    Names brought in by `use` are shadowed by the importing
+    record's own definitions, and themselves shadow anything
+    from enclosing scopes. Two modules opened into the same
+    record have no such order, so they may not both export
+    a signature or child module, nor disagree on a fixity.
+*/
+// Checks a module and every module nested within it.
+pub fn check_imports<'ctx>(ctx: &'ctx Context<'ctx>, root: ModlRef) -> error::Result<()> {
+    let ir = ctx.ir.borrow();
+    let mut pending = vec![root];
+    while let Some(modl_ref) = pending.pop() {
+        let record = match ir.modl.get(modl_ref).unwrap() {
+            ir::Modl::Record(record) => record,
+            ir::Modl::Alias(_) => continue,
+        };
+        pending.extend(record.children.values().copied());
+        let opened = ir.opened(modl_ref);
+        let use_span = |modl_use: &ir::ModlUse| ir.spans.modl.get(modl_use.modl).copied();
+
+        // Every name listed in an import must refer to something.
+        for &(target_ref, modl_use) in opened.iter() {
+            let target = ir.modl.get(target_ref).unwrap().as_record()?;
+            for item in modl_use.filter.items() {
+                let exists = target
+                    .symbols
+                    .iter_decl_signs()
+                    .map(|(sig, _)| sig)
+                    .chain(target.symbols.iter_cons_signs().map(|(sig, _)| sig))
+                    .any(|sig| item.matches_sig(sig))
+                    || target
+                        .symbols
+                        .iter_types()
+                        .any(|(id, _)| item.matches_sig(&[ir::Sign::Word(id)]))
+                    || target.children.keys().any(|&id| item.matches_child(id));
+                if !exists {
+                    let names = ctx.names.borrow();
+                    let exported = target.first_words().chain(target.children.keys().copied());
+                    let similar = error::similar_names(
+                        names.get(item.word).unwrap(),
+                        exported.map(|id| names.get(id).unwrap()),
+                    );
+                    let diag = Diagnostic::new(
+                        error::UnknownImport,
+                        format!("No such name to import: {}", ctx.wrap(item)),
+                    )
+                    .with_suggestions(&similar);
+                    ctx.report(match use_span(modl_use) {
+                        Some(span) => {
+                            diag.with_primary(span, format!("{} has no such name", &target.name))
+                        }
+                        None => diag,
+                    });
+                }
+            }
+        }
+
+        for (ix, &(first_ref, first_use)) in opened.iter().enumerate() {
+            for &(second_ref, second_use) in opened[ix + 1..].iter() {
+                if first_ref == second_ref {
+                    continue;
+                }
+                let first = ir.modl.get(first_ref).unwrap().as_record()?;
+                let second = ir.modl.get(second_ref).unwrap().as_record()?;
+                let (first_filter, second_filter) = (&first_use.filter, &second_use.filter);
+
+                // Point at both of the offending imports.
+                let conflict = |diag: Diagnostic| {
+                    let diag = match use_span(second_use) {
+                        Some(span) => diag.with_primary(span, format!("opens {}", &second.name)),
+                        None => diag,
+                    };
+                    let diag = match use_span(first_use) {
+                        Some(span) => diag.with_label(span, format!("opens {}", &first.name)),
+                        None => diag,
+                    };
+                    diag.with_help("Use `hiding` or a list of names to import only one of them.")
+                };
+                let exported_by = |what: String| {
+                    conflict(Diagnostic::new(
+                        error::ConflictingImports,
+                        format!(
+                            "{} is exported by both {} and {}, which are opened in {}",
+                            what, &first.name, &second.name, &record.name
+                        ),
+                    ))
+                };
+                let both_admit = |sig: &[ir::Sign]| {
+                    first_filter.admits_sig(sig) && second_filter.admits_sig(sig)
+                };
+
+                let sigs = first
+                    .symbols
+                    .iter_decl_signs()
+                    .map(|(sig, _)| sig)
+                    .chain(first.symbols.iter_cons_signs().map(|(sig, _)| sig));
+                for sig in sigs.filter(|sig| both_admit(sig)) {
+                    let defined = !second.symbols.lookup_decl(sig).is_empty()
+                        || !second.symbols.lookup_cons(sig).is_empty();
+                    if defined {
+                        ctx.report(exported_by(ctx.wrap(sig).to_string()));
+                    }
+                }
+
+                for (sig, fixity) in first.symbols.iter_fixities() {
+                    match second.symbols.lookup_fixity(sig) {
+                        Some(other) if other != fixity && both_admit(sig) => {
+                            ctx.report(conflict(Diagnostic::new(
+                                error::ConflictingFixity,
+                                format!(
+                                    "Conflicting fixity declarations: {} {} from {}, but {} from {}",
+                                    fixity,
+                                    ctx.wrap(sig),
+                                    &first.name,
+                                    other,
+                                    &second.name,
+                                ),
+                            )))
+                        }
+                        _ => continue,
+                    }
+                }
+
+                for (type_id, con) in first.symbols.iter_types() {
+                    if !both_admit(&[ir::Sign::Word(type_id)]) {
+                        continue;
+                    }
+                    match second.symbols.lookup_type(type_id) {
+                        Some(other) if other != con => {
+                            let type_name = ctx.names.borrow().get(type_id).unwrap().to_owned();
+                            ctx.report(exported_by(format!("The type {}", type_name)))
+                        }
+                        _ => continue,
+                    }
+                }
+
+                for (&child_id, &child_ref) in first.children.iter() {
+                    if !first_filter.admits_child(child_id) || !second_filter.admits_child(child_id)
+                    {
+                        continue;
+                    }
+                    match second.children.get(&child_id) {
+                        Some(&other_ref) if ir.target(other_ref) != ir.target(child_ref) => {
+                            let child_name = ctx.names.borrow().get(child_id).unwrap().to_owned();
+                            ctx.report(exported_by(format!("Module {}", child_name)))
+                        }
+                        _ => continue,
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, Clone)]
 pub struct DeferredModl {
     pub name: String,
@@ -250,14 +733,27 @@ pub struct DeferredModl {
     pub modl_ref: ModlRef,
 }
 
-pub fn process_modl_ast(ctx: &Context<'_>, modl: DeferredModl) -> error::Result<Vec<DeferredModl>> {
+pub fn process_modl_ast<'ctx>(
+    ctx: &'ctx Context<'ctx>,
+    modl: DeferredModl,
+) -> error::Result<Vec<DeferredModl>> {
     let mut deferred = Vec::new();
 
     let mut anon_modl_counter = 0;
 
     let mut ir = ctx.ir.borrow_mut();
+    let ir = &mut *ir;
     let mut ast = ctx.ast.borrow_mut();
     let mut refs = ctx.refs.borrow_mut();
+    let files = ctx.files.borrow();
+    let doc = |span: Span| {
+        let file = files.get(span.file)?;
+        file.trivia.doc(&file.text, span)
+    };
+    let cons_doc = |span: Span| {
+        let file = files.get(span.file)?;
+        file.trivia.cons_doc(&file.text, span)
+    };
 
     let DeferredModl {
         name,
@@ -268,8 +764,8 @@ pub fn process_modl_ast(ctx: &Context<'_>, modl: DeferredModl) -> error::Result<
     let &modl_ast = ast.modl.get(modl_ref).expect("Module has no ast!");
     match modl_ast {
         ast::Modl::Named(modl_path) => {
-            println!("--- modl: {}", &name);
-            println!("alias: {}", &modl_path);
+            ctx.trace(format_args!("--- modl: {}", &name));
+            ctx.trace(format_args!("alias: {}", &modl_path));
 
             let ast::ModlPath { path, absolute } = modl_path;
             let parent = if absolute { ctx.global_modl() } else { parent };
@@ -281,21 +777,30 @@ pub fn process_modl_ast(ctx: &Context<'_>, modl: DeferredModl) -> error::Result<
                 .extend(path.iter().map(|id| names.make_ident(id.0)));
         }
         ast::Modl::ModExp(decls) => {
-            println!("--- modl: {}", &name);
+            ctx.trace(format_args!("--- modl: {}", &name));
+            let mut new_cons = Vec::new();
+            let mut new_data = Vec::new();
+            let mut loose_cons = Vec::new();
+            let loose_data = refs.data.make_ref();
+            let mut use_spans = Vec::new();
             let modl_ir = ir.modl.set(modl_ref, ir::Modl::new(name.clone()));
             let record = modl_ir.as_record_mut()?;
             // record.scope.push(modl_ref);
             record.scope.push(parent);
 
             for decl in decls.iter() {
-                println!("decl: {}", decl);
-                match decl {
-                    ast::Decl::Def(sig, _) => {
+                ctx.trace(format_args!("decl: {}", decl));
+                match decl.node {
+                    ast::Decl::Def(sig, ..) => {
                         let ir_sig: Vec<_> =
                             sig.iter().map(|sign| sign.from_ast(ctx).forget()).collect();
 
                         let decl_ref = refs.decl.make_ref();
-                        ast.decl.set(decl_ref, *decl);
+                        ast.decl.set(decl_ref, decl.node);
+                        ir.spans.decl.set(decl_ref, decl.span);
+                        if let Some(doc) = doc(decl.span) {
+                            ir.docs.decl.set(decl_ref, doc);
+                        }
 
                         record.symbols.new_decl(decl_ref, ir_sig);
                         record.decls.push(decl_ref);
@@ -306,24 +811,108 @@ pub fn process_modl_ast(ctx: &Context<'_>, modl: DeferredModl) -> error::Result<
                             sig.iter().map(|sign| sign.from_ast(ctx).forget()).collect();
 
                         let cons_ref = refs.cons.make_ref();
-                        ast.cons.set(cons_ref, *decl);
+                        ast.cons.set(cons_ref, decl.node);
+                        ir.spans.cons.set(cons_ref, decl.span);
+                        if let Some(doc) = doc(decl.span) {
+                            ir.docs.cons.set(cons_ref, doc);
+                        }
 
+                        new_cons.push((
+                            cons_ref,
+                            ir::Cons {
+                                sig: ir_sig.clone(),
+                                data: loose_data,
+                                fields: None,
+                            },
+                        ));
                         record.symbols.new_cons(cons_ref, ir_sig);
                         record.cons.push(cons_ref);
+                        loose_cons.push(cons_ref);
+                    }
+
+                    ast::Decl::Data(id, params, cons) => {
+                        let data_ref = refs.data.make_ref();
+                        ast.data.set(data_ref, decl.node);
+                        ir.spans.data.set(data_ref, decl.span);
+                        if let Some(doc) = doc(decl.span) {
+                            ir.docs.data.set(data_ref, doc);
+                        }
+
+                        let mut names = ctx.names.borrow_mut();
+                        let mut data = ir::Data {
+                            name: Some(names.make_ident(id.0)),
+                            params: params
+                                .iter()
+                                .map(|param| names.make_ident(param.0))
+                                .collect(),
+                            cons: Vec::new(),
+                        };
+                        drop(names);
+                        for data_cons in cons.iter() {
+                            let ir_sig: Vec<_> = data_cons
+                                .sig
+                                .iter()
+                                .map(|sign| sign.from_ast(ctx).forget())
+                                .collect();
+
+                            let cons_ref = refs.cons.make_ref();
+                            ast.cons.set(cons_ref, ast::Decl::Con(data_cons.sig));
+                            ir.spans.cons.set(cons_ref, data_cons.span);
+                            if let Some(doc) = cons_doc(data_cons.span) {
+                                ir.docs.cons.set(cons_ref, doc);
+                            }
+
+                            new_cons.push((
+                                cons_ref,
+                                ir::Cons {
+                                    sig: ir_sig.clone(),
+                                    data: data_ref,
+                                    fields: None,
+                                },
+                            ));
+                            record.symbols.new_cons(cons_ref, ir_sig);
+                            record.cons.push(cons_ref);
+                            data.cons.push(cons_ref);
+                        }
+
+                        let con = TypeCon::Data(data_ref);
+                        if let Some(TypeCon::Data(previous)) =
+                            record.symbols.new_type(data.name.unwrap(), con)
+                        {
+                            let diag = Diagnostic::new(
+                                error::DuplicateDefinition,
+                                format!("The type {} is defined more than once", id),
+                            )
+                            .with_primary(decl.span, "defined again here");
+                            ctx.report(match ir.spans.data.get(previous) {
+                                Some(&span) => diag.with_label(span, "first defined here"),
+                                None => diag,
+                            });
+                        }
+                        record.data.push(data_ref);
+                        new_data.push((data_ref, data));
                     }
 
                     ast::Decl::Let(..) => {
                         let let_ref = refs.decl.make_ref();
-                        ast.decl.set(let_ref, *decl);
+                        ast.decl.set(let_ref, decl.node);
+                        ir.spans.decl.set(let_ref, decl.span);
+                        if let Some(doc) = doc(decl.span) {
+                            ir.docs.decl.set(let_ref, doc);
+                        }
                         record.decls.push(let_ref);
                     }
 
-                    ast::Decl::Mod(id, &ast_modl) => {
+                    ast::Decl::Mod(id, ast_modl) => {
                         let mut names = ctx.names.borrow_mut();
                         let child_id = names.make_ident(id.0);
                         let child_modl = refs.modl.make_ref();
                         record.children.insert(child_id, child_modl);
-                        ast.modl.set(child_modl, ast_modl);
+                        ast.modl.set(child_modl, ast_modl.node);
+                        ir.spans.modl.set(child_modl, ast_modl.span);
+                        if let Some(doc) = doc(decl.span) {
+                            ir.docs.modl.set(child_modl, doc);
+                        }
 
                         deferred.push(DeferredModl {
                             name: format!("{}.{}", name, id.0.to_owned()),
@@ -331,11 +920,49 @@ pub fn process_modl_ast(ctx: &Context<'_>, modl: DeferredModl) -> error::Result<
                             modl_ref: child_modl,
                         });
                     }
-                    ast::Decl::Use(&ast_modl) => {
+                    // Renaming a module only gives it a new name, like `mod`.
+                    ast::Decl::Use(ast_modl, ast::Import::As(id)) => {
+                        use_spans.push(decl.span);
+                        let mut names = ctx.names.borrow_mut();
+                        let child_id = names.make_ident(id.0);
+                        let child_modl = refs.modl.make_ref();
+                        record.children.insert(child_id, child_modl);
+                        ast.modl.set(child_modl, ast_modl.node);
+                        ir.spans.modl.set(child_modl, ast_modl.span);
+
+                        deferred.push(DeferredModl {
+                            name: format!("{}.{}", name, id.0.to_owned()),
+                            parent: modl_ref,
+                            modl_ref: child_modl,
+                        });
+                    }
+                    ast::Decl::Use(ast_modl, import) => {
+                        use_spans.push(decl.span);
                         let new_modl = refs.modl.make_ref();
-                        // record.scope.push(new_modl);
+                        let mut names = ctx.names.borrow_mut();
+                        let items = match import {
+                            ast::Import::Only(items) | ast::Import::Hiding(items) => items
+                                .iter()
+                                .map(|item| ir::ImportItem {
+                                    leading: item.leading,
+                                    word: names.make_ident(item.word.0),
+                                    trailing: item.trailing,
+                                })
+                                .collect(),
+                            _ => Vec::new(),
+                        };
+                        let filter = match import {
+                            ast::Import::Only(_) => ir::ImportFilter::Only(items),
+                            ast::Import::Hiding(_) => ir::ImportFilter::Hiding(items),
+                            _ => ir::ImportFilter::All,
+                        };
+                        record.uses.push(ir::ModlUse {
+                            modl: new_modl,
+                            filter,
+                        });
 
-                        ast.modl.set(new_modl, ast_modl);
+                        ast.modl.set(new_modl, ast_modl.node);
+                        ir.spans.modl.set(new_modl, ast_modl.span);
                         deferred.push(DeferredModl {
                             name: format!("{}.<anon{}>", name, anon_modl_counter),
                             parent: modl_ref,
@@ -343,10 +970,325 @@ pub fn process_modl_ast(ctx: &Context<'_>, modl: DeferredModl) -> error::Result<
                         });
                         anon_modl_counter += 1;
                     }
+
+                    ast::Decl::Fixity(fixity, sig) => {
+                        let ir_sig: Vec<_> =
+                            sig.iter().map(|sign| sign.from_ast(ctx).forget()).collect();
+
+                        if let Some(previous) = record.symbols.new_fixity(ir_sig, fixity) {
+                            ctx.report(
+                                Diagnostic::new(
+                                    error::ConflictingFixity,
+                                    format!("Conflicting fixity declarations in {}", &name),
+                                )
+                                .with_primary(decl.span, format!("already declared {}", previous)),
+                            );
+                        }
+                    }
                 }
             }
+
+            // Constructors declared on their own together make up a type.
+            if !loose_cons.is_empty() {
+                record.data.push(loose_data);
+                let data = ir::Data {
+                    name: None,
+                    params: Vec::new(),
+                    cons: loose_cons,
+                };
+                new_data.push((loose_data, data));
+            }
+
+            for (cons_ref, cons) in new_cons {
+                ir.cons.set(cons_ref, cons);
+            }
+            for (data_ref, data) in new_data {
+                ir.data.set(data_ref, data);
+            }
+
+            group_clauses(ctx, ir, modl_ref, &use_spans);
         }
     }
 
     Ok(deferred)
 }
+
+/*
+    Definitions sharing a signature within a module are the
+    clauses of a single function, tried in the order written.
+    They have to be written together, and only functions can
+    have more than one.
+*/
+fn group_clauses<'ctx>(
+    ctx: &'ctx Context<'ctx>,
+    ir: &mut ir::IrStorage,
+    modl_ref: ModlRef,
+    uses: &[Span],
+) {
+    let record = match ir.modl.get(modl_ref) {
+        Some(ir::Modl::Record(record)) => record,
+        _ => return,
+    };
+    let span = |decl_ref| *ir.spans.decl.get(decl_ref).unwrap();
+    let arity = |sig: &[ir::Sign]| {
+        sig.iter()
+            .filter(|&sign| *sign == ir::Sign::Patn(()))
+            .count()
+    };
+
+    let mut sigs: Vec<_> = record.symbols.iter_decl_signs().collect();
+    sigs.sort_by_key(|&(_, decls)| usize::from(decls[0]));
+
+    let mut groups = Vec::new();
+    for &(sig, decls) in sigs.iter() {
+        let (&first, rest) = match decls.split_first() {
+            Some(split) if !split.1.is_empty() => split,
+            _ => continue,
+        };
+
+        if arity(sig) == 0 {
+            for &decl_ref in rest.iter() {
+                ctx.report(
+                    Diagnostic::new(
+                        error::DuplicateDefinition,
+                        format!("{} is defined more than once", ctx.wrap(sig)),
+                    )
+                    .with_primary(span(decl_ref), "defined again here")
+                    .with_label(span(first), "first defined here")
+                    .with_note("Only definitions with parameters can have several clauses."),
+                );
+            }
+            continue;
+        }
+
+        for pair in decls.windows(2) {
+            let (before, after) = (span(pair[0]), span(pair[1]));
+            let between = uses
+                .iter()
+                .find(|use_span| before.end <= use_span.start && use_span.end <= after.start);
+            if let Some(&use_span) = between {
+                ctx.report(
+                    Diagnostic::new(
+                        error::SplitClauses,
+                        format!("Clauses of {} are separated by a `use`", ctx.wrap(sig)),
+                    )
+                    .with_primary(use_span, "this `use` comes between them")
+                    .with_label(before, "an earlier clause")
+                    .with_label(after, "a later clause")
+                    .with_help("Move the `use` before or after every clause."),
+                );
+            }
+        }
+
+        groups.push((first, decls.to_vec()));
+    }
+
+    // Functions named by the same words should take as many arguments,
+    // although operators may still be used both prefix and infix.
+    let prefix = sigs
+        .iter()
+        .filter(|(sig, _)| matches!(sig.first(), Some(ir::Sign::Word(_))));
+    for (ix, &(sig, decls)) in prefix.clone().enumerate() {
+        let words: Vec<_> = mixfix::words(sig).collect();
+        let earlier = prefix
+            .clone()
+            .take(ix)
+            .find(|(other, _)| mixfix::words(other).eq(words.iter().copied()));
+        if let Some(&(other, other_decls)) = earlier {
+            ctx.report(
+                Diagnostic::new(
+                    error::MismatchedArity,
+                    format!(
+                        "{} takes {} arguments, but {} takes {}",
+                        ctx.wrap(sig),
+                        arity(sig),
+                        ctx.wrap(other),
+                        arity(other)
+                    ),
+                )
+                .with_primary(span(decls[0]), format!("takes {}", arity(sig)))
+                .with_label(span(other_decls[0]), format!("takes {}", arity(other))),
+            );
+        }
+    }
+
+    for (first, decls) in groups {
+        ir.clauses.set(first, decls);
+    }
+}
+
+// Takes source through every phase the way `friday FILE` does, for tests.
+#[cfg(test)]
+pub fn load_source<'ctx>(ctx: &'ctx Context<'ctx>, source: &str) -> error::Result<ModlRef> {
+    let modl_ref = process_source(ctx, "test.fri", source.to_owned())?;
+    process_aliases(ctx)?;
+    ir::lower::lower_modls(ctx, &[modl_ref])?;
+    ir::infer::infer_types(ctx);
+    Ok(modl_ref)
+}
+
+// Loads source with mistakes in it, giving the codes of what was wrong.
+#[cfg(test)]
+pub fn check_source<'ctx>(ctx: &'ctx Context<'ctx>, source: &str) -> Vec<error::ErrorCode> {
+    if let Err(err) = load_source(ctx, source) {
+        ctx.report(err);
+    }
+    reported(ctx)
+}
+
+// What `main` comes to in source without mistakes.
+#[cfg(test)]
+pub fn evaluate(source: &str) -> String {
+    use crate::eval;
+
+    let arena = bumpalo::Bump::new();
+    let ctx = Context::new(&arena);
+    let modl_ref = load_source(&ctx, source).unwrap();
+    assert_eq!(reported(&ctx), [], "{}", source);
+    let ir = ctx.ir.borrow();
+    let decl_ref = eval::find_entry(&ctx, &[modl_ref], "main").unwrap();
+    let value = eval::Interpreter::new(&ctx, &ir)
+        .eval_decl(decl_ref)
+        .unwrap();
+    ctx.wrap(&value).to_string()
+}
+
+// The first declaration in a module with a signature shown as given, like `len _`.
+#[cfg(test)]
+pub fn find_decl<'ctx>(ctx: &'ctx Context<'ctx>, modl_ref: ModlRef, sig: &str) -> DeclRef {
+    let ir = ctx.ir.borrow();
+    let record = ir.modl.get(modl_ref).unwrap().as_record().unwrap();
+    let mut decls = record.symbols.iter_decl_signs();
+    let decls = decls.find(|&(found, _)| ctx.wrap(found).to_string() == sig);
+    decls
+        .unwrap_or_else(|| panic!("No {} in the module.", sig))
+        .1[0]
+}
+
+// The codes of what the phases reported, in order.
+#[cfg(test)]
+pub fn reported(ctx: &Context<'_>) -> Vec<error::ErrorCode> {
+    let diagnostics = ctx.diagnostics.borrow();
+    diagnostics.iter().map(|diag| diag.code).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bumpalo::Bump;
+
+    fn check(source: &str) -> Vec<error::ErrorCode> {
+        let arena = Bump::new();
+        let ctx = Context::new(&arena);
+        check_source(&ctx, source)
+    }
+
+    #[test]
+    fn use_opens_modules() {
+        let list = "mod List = mod def len (l) = 1 end";
+        let source = format!("{} def main = len 0", list);
+        assert_eq!(check(&source), [error::UnresolvedName]);
+        assert_eq!(
+            evaluate(&format!("{} use List def main = len 0", list)),
+            "1"
+        );
+
+        // A record's own definitions come first, then what it opens,
+        // then what's in the scopes around it.
+        let source = "mod M = mod def x = 1 end def main = x use M def x = 2";
+        assert_eq!(evaluate(source), "2");
+        let source = "
+            mod M = mod def x = 3 end
+            def x = 1
+            mod Inner = mod use M def y = x end
+            use Inner (y)
+            def main = y
+        ";
+        assert_eq!(evaluate(source), "3");
+
+        let source = "mod A = mod def x = 1 end mod B = mod def x = 2 end use A use B";
+        assert_eq!(check(source), [error::ConflictingImports]);
+        assert_eq!(check(&format!("{} hiding (x)", source)), []);
+    }
+
+    #[test]
+    fn imports_can_be_chosen_and_renamed() {
+        let list = "mod L = mod def len (l) = 1 def size (l) = 2 con Cons _, _ con Nil end";
+        let source =
+            |import: &str, main: &str| format!("{} use L{} def main = {}", list, import, main);
+        assert_eq!(evaluate(&source(" (len)", "len 0")), "1");
+        assert_eq!(check(&source(" (len)", "size 0")), [error::UnresolvedName]);
+        assert_eq!(evaluate(&source(" hiding (len)", "size 0")), "2");
+        assert_eq!(
+            check(&source(" hiding (len)", "len 0")),
+            [error::UnresolvedName]
+        );
+        assert_eq!(
+            evaluate(&source(" (Cons _ _, Nil)", "Cons 1, Nil")),
+            "Cons 1 , Nil"
+        );
+        assert_eq!(evaluate(&source(" as List", "List.size 0")), "2");
+        assert_eq!(
+            check(&source(" as List", "size 0")),
+            [error::UnresolvedName]
+        );
+
+        let arena = Bump::new();
+        let ctx = Context::new(&arena);
+        let codes = check_source(&ctx, &source(" (lem)", "0"));
+        assert_eq!(codes, [error::UnknownImport]);
+        let help = ctx.diagnostics.borrow()[0].help.clone();
+        assert_eq!(help.unwrap(), "Did you mean `len`?");
+    }
+
+    // Aliases found through ones already reported aren't reported again.
+    #[test]
+    fn errors_accumulate_across_aliases() {
+        let arena = Bump::new();
+        let ctx = Context::new(&arena);
+        let source = "
+            mod A = B.C
+            mod D = Be
+            mod F = A.X
+            mod G = F
+            def main = nope
+            mod Bee = mod end
+        ";
+        let codes = check_source(&ctx, source);
+        let unresolvable = error::UnresolvableModulePath;
+        assert_eq!(codes, [unresolvable, unresolvable, error::UnresolvedName]);
+        assert_eq!(ctx.error_count(), 3);
+        let diagnostics = ctx.diagnostics.borrow();
+        let messages: Vec<_> = diagnostics.iter().map(|diag| &diag.message[..]).collect();
+        assert_eq!(
+            messages,
+            [
+                "No such module in enclosing scope: B",
+                "No such module in enclosing scope: Be",
+                "Unresolved name: nope",
+            ]
+        );
+    }
+
+    #[test]
+    fn clauses_make_one_function() {
+        let list = "con Nil con Cons _, _";
+        let source = format!(
+            "{} def len (Nil) = 0 def len (Cons _, xs) = 1 + len xs
+             def main = len (Cons 1, (Cons 2, Nil))",
+            list
+        );
+        assert_eq!(evaluate(&source), "2");
+        // Clauses are tried in the order they're written.
+        let source = "def f (1) = 1 def f (n) = n + 1 def main = f 1 + f 2";
+        assert_eq!(evaluate(source), "4");
+
+        let source = "mod M = mod end def f (0) = 1 use M def f (n) = 2";
+        assert_eq!(check(source), [error::SplitClauses]);
+        let source = "def f = 1 def f (x) = 2";
+        assert_eq!(check(source), [error::MismatchedArity]);
+        let source = "def x = 1 def x = 2";
+        assert_eq!(check(source), [error::DuplicateDefinition]);
+    }
+}
@@ -18,9 +18,9 @@ impl<T> From<usize> for Ref<T> {
     }
 }
 
-impl<T> Into<usize> for Ref<T> {
-    fn into(self) -> usize {
-        self.1
+impl<T> From<Ref<T>> for usize {
+    fn from(r: Ref<T>) -> usize {
+        r.1
     }
 }
 
@@ -40,17 +40,27 @@ pub type DeclRef = Ref<Decl>;
 pub struct Cons;
 pub type ConsRef = Ref<Cons>;
 
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Data;
+pub type DataRef = Ref<Data>;
+
 #[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Modl;
 pub type ModlRef = Ref<Modl>;
 
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct File;
+pub type FileRef = Ref<File>;
+
 #[derive(Debug, Clone)]
 pub struct IdCounter {
     pub expr: RefCounter<ExprRef>,
     pub patn: RefCounter<PatnRef>,
     pub decl: RefCounter<DeclRef>,
     pub cons: RefCounter<ConsRef>,
+    pub data: RefCounter<DataRef>,
     pub modl: RefCounter<ModlRef>,
+    pub file: RefCounter<FileRef>,
 }
 
 impl IdCounter {
@@ -60,7 +70,9 @@ impl IdCounter {
             patn: RefCounter::new(),
             decl: RefCounter::new(),
             cons: RefCounter::new(),
+            data: RefCounter::new(),
             modl: RefCounter::new(),
+            file: RefCounter::new(),
         }
     }
 }
@@ -0,0 +1,516 @@
+use crate::ast::{self, OwnedToken, Spanned};
+use crate::ctx::Context;
+use crate::error::{self, Diagnostic, Severity};
+use crate::eval;
+use crate::id::Ident;
+use crate::ir::types::TypeCon;
+use crate::ir::{self, infer, lower, ImportFilter, Modl, ModlUse, Sign};
+use crate::parser::{ExprParser, SequenceParser};
+use crate::phases::{self, DeferredModl};
+use crate::refs::*;
+use crate::span::{SourceFile, Span};
+use crate::storage::*;
+
+use bumpalo::Bump;
+use std::io::{self, BufRead, Write};
+
+/*
+    Every line entered is a module of its own, scoped within the
+    last one kept, so it can use anything defined before it or
+    shadow it with a new definition. Earlier lines keep referring
+    to whatever they saw when they were entered. A line with any
+    errors is dropped, so later lines never hear about them again.
+*/
+pub struct Repl<'ctx> {
+    arena: &'ctx Bump,
+    ctx: &'ctx Context<'ctx>,
+    files: Vec<String>,
+    // Holds every line as a child, and opens each of the files.
+    root: ModlRef,
+    last: ModlRef,
+    lines: usize,
+}
+
+enum Input<'ctx> {
+    Decls(Vec<Spanned<ast::Decl<'ctx>>>),
+    Expr(Spanned<ast::Expr<'ctx>>),
+}
+
+// Whether a parse failed only because the input stopped too soon.
+struct Failed {
+    incomplete: bool,
+    diag: Box<Diagnostic>,
+}
+
+const HELP: &str = "\
+Enter declarations to define them, or an expression to evaluate it as `it`.
+  :load FILE...  start over with the files loaded
+  :reload        start over, loading the same files again
+  :browse PATH   list what a module or type defines
+  :type EXPR     show the type of an expression
+  :ast INPUT     show how declarations or an expression parse
+  :quit          leave";
+
+pub fn run(arena: &Bump, files: Vec<String>) -> error::Result<()> {
+    let mut repl = Repl::new(arena, files);
+    let stdin = io::stdin();
+    let mut input = String::new();
+    loop {
+        print!("{}", if input.is_empty() { "> " } else { "| " });
+        let mut line = String::new();
+        let read = io::stdout()
+            .flush()
+            .and_then(|()| stdin.lock().read_line(&mut line))
+            .map_err(|err| {
+                Diagnostic::new(
+                    error::UnreadableFile,
+                    format!("Could not read the input: {}", err),
+                )
+            })?;
+        if read == 0 {
+            println!();
+            return Ok(());
+        }
+
+        let line = line.trim_end();
+        if input.is_empty() {
+            match line.trim_start() {
+                "" => continue,
+                command if command.starts_with(':') => {
+                    if !repl.command(&command[1..]) {
+                        return Ok(());
+                    }
+                    repl.report();
+                    continue;
+                }
+                _ => {}
+            }
+        }
+
+        // A blank line gives up on finishing an incomplete input.
+        let give_up = line.trim().is_empty();
+        input.push_str(line);
+        input.push('\n');
+        match repl.parse(&input) {
+            Err(failed) if failed.incomplete && !give_up => continue,
+            Err(failed) => repl.ctx.report(failed.diag),
+            Ok((Input::Decls(decls), span)) => {
+                repl.define(decls, span, true);
+            }
+            Ok((Input::Expr(expr), span)) => {
+                if let Some(line) = repl.define(vec![repl.it(expr)], span, true) {
+                    repl.evaluate(line);
+                }
+            }
+        }
+        repl.report();
+        input.clear();
+    }
+}
+
+// Prints and forgets any diagnostics, and says whether there were errors.
+fn report<'ctx>(ctx: &'ctx Context<'ctx>) -> bool {
+    let diagnostics: Vec<_> = ctx.diagnostics.borrow_mut().drain(..).collect();
+    for diag in diagnostics.iter() {
+        eprintln!("{}", ctx.wrap(diag));
+    }
+    diagnostics
+        .iter()
+        .any(|diag| diag.severity == Severity::Error)
+}
+
+fn parse_start(err: &phases::ParseError) -> usize {
+    use lalrpop_util::ParseError::*;
+    match *err {
+        InvalidToken { location } | UnrecognizedEOF { location, .. } => location,
+        UnrecognizedToken {
+            token: (start, ..), ..
+        }
+        | ExtraToken { token: (start, ..) }
+        | User { error: (start, ..) } => start,
+    }
+}
+
+impl<'ctx> Repl<'ctx> {
+    pub fn new(arena: &'ctx Bump, files: Vec<String>) -> Self {
+        let session = |files: &[String]| {
+            let ctx: &'ctx Context<'ctx> = arena.alloc(Context::new(arena));
+            let mut loaded = Vec::new();
+            for file in files.iter() {
+                match phases::process_file(ctx, file) {
+                    Ok(modl_ref) => loaded.push(modl_ref),
+                    Err(err) => ctx.report(err),
+                }
+            }
+            let lowered =
+                phases::process_aliases(ctx).and_then(|()| lower::lower_modls(ctx, &loaded));
+            if let Err(err) = lowered {
+                ctx.report(err);
+            }
+            infer::infer_types(ctx);
+            (ctx, loaded)
+        };
+
+        // Files with errors would spoil every line after them.
+        let (mut ctx, mut loaded) = session(&files);
+        if report(ctx) {
+            eprintln!("No files were loaded.");
+            let (empty, _) = session(&[]);
+            ctx = empty;
+            loaded.clear();
+        }
+
+        let root = {
+            let mut refs = ctx.refs.borrow_mut();
+            let mut ir = ctx.ir.borrow_mut();
+            let root = refs.modl.make_ref();
+            let mut modl = Modl::new("<repl>".to_owned());
+            let record = modl.as_record_mut().unwrap();
+            record.scope.push(ctx.global_modl());
+            record.uses.extend(loaded.iter().map(|&modl| ModlUse {
+                modl,
+                filter: ImportFilter::All,
+            }));
+            ir.modl.set(root, modl);
+
+            let id = ctx.names.borrow_mut().make_ident("<repl>");
+            let global = ir.modl.get_mut(ctx.global_modl()).unwrap();
+            global.as_record_mut().unwrap().children.insert(id, root);
+            root
+        };
+
+        Repl {
+            arena,
+            ctx,
+            files,
+            root,
+            last: root,
+            lines: 0,
+        }
+    }
+
+    fn report(&self) -> bool {
+        report(self.ctx)
+    }
+
+    // Returns false once it's time to leave.
+    fn command(&mut self, command: &str) -> bool {
+        let (name, arg) = match command.find(char::is_whitespace) {
+            Some(ix) => (&command[..ix], command[ix..].trim()),
+            None => (command, ""),
+        };
+        match name {
+            "q" | "quit" => return false,
+            "?" | "help" => println!("{}", HELP),
+            "load" => {
+                let files = arg.split_whitespace().map(str::to_owned).collect();
+                *self = Repl::new(self.arena, files);
+            }
+            "reload" => *self = Repl::new(self.arena, self.files.clone()),
+            "browse" => self.browse(arg),
+            "type" => self.show_type(arg),
+            "ast" => match self.parse(arg) {
+                Ok((Input::Decls(decls), _)) => {
+                    for decl in decls.iter() {
+                        println!("{}", decl);
+                    }
+                }
+                Ok((Input::Expr(expr), _)) => println!("{}", expr),
+                Err(failed) => self.ctx.report(failed.diag),
+            },
+            _ => self.ctx.report(
+                Diagnostic::new(
+                    error::InvalidArguments,
+                    format!("Unknown command :{}", name),
+                )
+                .with_help("Enter :help to list the commands."),
+            ),
+        }
+        true
+    }
+
+    // Input is read as declarations if it can be, and otherwise as an
+    // expression. When neither works, whichever got further is reported.
+    fn parse(&self, text: &str) -> Result<(Input<'ctx>, Span), Failed> {
+        let ctx = self.ctx;
+        let file = ctx.refs.borrow_mut().file.make_ref();
+        ctx.files.borrow_mut().set(
+            file,
+            SourceFile {
+                name: "<repl>".to_owned(),
+                text: text.to_owned(),
+                trivia: Default::default(),
+            },
+        );
+        let span = Span {
+            file,
+            start: text.len() - text.trim_start().len(),
+            end: text.trim_end().len(),
+        };
+
+        let stripped = match ast::trivia::strip(file, text) {
+            Ok((stripped, _)) => stripped,
+            Err(err) => {
+                return Err(Failed {
+                    incomplete: true,
+                    diag: phases::parse_error(file, err).into(),
+                })
+            }
+        };
+        let text = &stripped;
+        let decls_err = match SequenceParser::new().parse(ctx.arena, file, text) {
+            Ok(decls) => return Ok((Input::Decls(decls), span)),
+            Err(err) => err.map_token(OwnedToken::from),
+        };
+        let expr_err = match ExprParser::new().parse(ctx.arena, file, text) {
+            Ok(node) => return Ok((Input::Expr(Spanned { span, node }), span)),
+            Err(err) => err.map_token(OwnedToken::from),
+        };
+        let err = if parse_start(&expr_err) > parse_start(&decls_err) {
+            expr_err
+        } else {
+            decls_err
+        };
+        Err(Failed {
+            incomplete: matches!(err, lalrpop_util::ParseError::UnrecognizedEOF { .. }),
+            diag: phases::parse_error(file, err).into(),
+        })
+    }
+
+    // An expression is defined under a name nobody can write, so that
+    // it can still refer to the previous `it`, which it then replaces.
+    fn it(&self, expr: Spanned<ast::Expr<'ctx>>) -> Spanned<ast::Decl<'ctx>> {
+        let word = Spanned {
+            span: expr.span,
+            node: ast::Sign::Word(ast::Ident("<it>")),
+        };
+        let sig = self.arena.alloc_slice_copy(&[word]);
+        Spanned {
+            span: expr.span,
+            node: ast::Decl::Def(sig, None, self.arena.alloc(expr)),
+        }
+    }
+
+    // Returns the line's module unless it had errors. Those
+    // which aren't kept can be looked at, but not used again.
+    fn define(
+        &mut self,
+        decls: Vec<Spanned<ast::Decl<'ctx>>>,
+        span: Span,
+        keep: bool,
+    ) -> Option<ModlRef> {
+        let ctx = self.ctx;
+        self.lines += 1;
+        let name = format!("<line {}>", self.lines);
+        let name = self.arena.alloc_slice_copy(name.as_bytes());
+        let name = unsafe { std::str::from_utf8_unchecked(name) };
+        let id = ctx.names.borrow_mut().make_ident(name);
+
+        let line = ctx.refs.borrow_mut().modl.make_ref();
+        let decls = self.arena.alloc_slice_copy(&decls);
+        ctx.ast
+            .borrow_mut()
+            .modl
+            .set(line, ast::Modl::ModExp(decls));
+        {
+            let mut ir = ctx.ir.borrow_mut();
+            ir.spans.modl.set(line, span);
+            let root = ir.modl.get_mut(self.root).unwrap();
+            root.as_record_mut().unwrap().children.insert(id, line);
+        }
+
+        let deferred = DeferredModl {
+            name: "<repl>".to_owned(),
+            parent: self.last,
+            modl_ref: line,
+        };
+        let lowered = phases::process_modl_tree(ctx, deferred)
+            .and_then(|()| phases::process_aliases(ctx))
+            .and_then(|()| lower::lower_modls(ctx, &[line]));
+        if let Err(err) = lowered {
+            ctx.report(err);
+        }
+        infer::infer_types(ctx);
+
+        let failed = ctx.error_count() > 0;
+        if failed || !keep {
+            // Nothing refers to a module which isn't anyone's child.
+            let mut ir = ctx.ir.borrow_mut();
+            let root = ir.modl.get_mut(self.root).unwrap();
+            root.as_record_mut().unwrap().children.remove(&id);
+        } else {
+            self.last = line;
+        }
+        if failed {
+            None
+        } else {
+            Some(line)
+        }
+    }
+
+    fn evaluate(&self, line: ModlRef) {
+        let ctx = self.ctx;
+        let it = ctx.names.borrow_mut().make_ident("it");
+        let mut ir = ctx.ir.borrow_mut();
+        let record = ir.modl.get_mut(line).unwrap().as_record_mut().unwrap();
+        let decl_ref = record.decls[0];
+        record.symbols.new_decl(decl_ref, vec![Sign::Word(it)]);
+        drop(ir);
+
+        let ir = ctx.ir.borrow();
+        match eval::Interpreter::new(ctx, &ir).eval_decl(decl_ref) {
+            Ok(value) => {
+                let scheme = ir.types.decl.get(decl_ref).unwrap();
+                println!("{} : {}", ctx.wrap(&value), ctx.wrap(scheme));
+            }
+            Err(err) => ctx.report(err),
+        }
+    }
+
+    fn show_type(&mut self, text: &str) {
+        let ctx = self.ctx;
+        let expr = match self.parse(text) {
+            Ok((Input::Expr(expr), _)) => expr,
+            Ok((Input::Decls(_), span)) => {
+                let diag = Diagnostic::new(error::InvalidArguments, "Expected an expression")
+                    .with_primary(span, "only expressions have types");
+                return ctx.report(diag);
+            }
+            Err(failed) => return ctx.report(failed.diag),
+        };
+        let span = expr.span;
+        if let Some(line) = self.define(vec![self.it(expr)], span, false) {
+            let ir = ctx.ir.borrow();
+            let record = ir.modl.get(line).unwrap().as_record().unwrap();
+            let decl_ref = record.decls[0];
+            let scheme = ir.types.decl.get(decl_ref).unwrap();
+            println!("{} : {}", text, ctx.wrap(scheme));
+        }
+    }
+
+    // Follows a module path from the latest line, like a qualified name.
+    fn resolve(&self, ir: &ir::IrStorage, path: &[Ident]) -> Option<ModlRef> {
+        let child = |modl_ref: ModlRef, id: Ident| match ir.modl.get(ir.target(modl_ref)?) {
+            Some(Modl::Record(record)) => record.children.get(&id).copied(),
+            _ => None,
+        };
+        let (&head, rest) = path.split_first()?;
+        let head = ir
+            .scope_levels(self.last)
+            .iter()
+            .flatten()
+            .filter(|(_, filter)| filter.admits_child(head))
+            .find_map(|&(scope_ref, _)| child(scope_ref, head))?;
+        let modl_ref = rest
+            .iter()
+            .try_fold(head, |modl_ref, &id| child(modl_ref, id))?;
+        ir.target(modl_ref)
+    }
+
+    // Lists what a module defines, or failing that,
+    // the constructors of a data type by that name.
+    fn browse(&self, text: &str) {
+        let ir = self.ctx.ir.borrow();
+        let names = self.ctx.names.borrow();
+        // Names never seen before can't refer to anything.
+        let path: Option<Vec<Ident>> = text.split('.').map(|id| names.get_ident(id)).collect();
+        drop(names);
+        let path = match path {
+            Some(path) => path,
+            None => return self.browse_missing(text),
+        };
+
+        if let Some(modl_ref) = self.resolve(&ir, &path) {
+            return self.browse_modl(&ir, modl_ref);
+        }
+        let (&last, init) = path.split_last().unwrap();
+        let scopes: Vec<_> = if init.is_empty() {
+            let levels = ir.scope_levels(self.last);
+            levels
+                .iter()
+                .flatten()
+                .filter(|(_, filter)| filter.admits_sig(&[Sign::Word(last)]))
+                .map(|&(scope_ref, _)| scope_ref)
+                .collect()
+        } else {
+            self.resolve(&ir, init).into_iter().collect()
+        };
+        let data_ref = scopes.into_iter().find_map(|scope_ref| {
+            match ir
+                .modl
+                .get(scope_ref)?
+                .as_record()
+                .ok()?
+                .symbols
+                .lookup_type(last)
+            {
+                Some(TypeCon::Data(data_ref)) => Some(data_ref),
+                _ => None,
+            }
+        });
+        match data_ref {
+            Some(data_ref) => self.browse_data(&ir, data_ref),
+            None => self.browse_missing(text),
+        }
+    }
+
+    fn browse_missing(&self, text: &str) {
+        self.ctx.report(Diagnostic::new(
+            error::UnresolvableModulePath,
+            format!("No module or type named {} is in scope", text),
+        ));
+    }
+
+    fn browse_modl(&self, ir: &ir::IrStorage, modl_ref: ModlRef) {
+        let ctx = self.ctx;
+        let record = ir.modl.get(modl_ref).unwrap().as_record().unwrap();
+        let mut children: Vec<_> = record.children.iter().collect();
+        children.sort_by_key(|&(_, &child_ref)| usize::from(child_ref));
+        for (&id, _) in children {
+            println!("mod {}", ctx.names.borrow().get(id).unwrap());
+        }
+
+        for &data_ref in record.data.iter() {
+            self.browse_data(ir, data_ref);
+        }
+
+        let mut sigs: Vec<_> = record.symbols.iter_decl_signs().collect();
+        sigs.sort_by_key(|&(_, decls)| usize::from(decls[0]));
+        for (sig, decls) in sigs {
+            let decl = ir.decl.get(decls[0]).unwrap();
+            let scheme = ir.types.decl.get(decls[0]).unwrap();
+            match (decl.is_let(), decl.binds_one(ir)) {
+                (false, _) => println!("def {} : {}", ctx.wrap(sig), ctx.wrap(scheme)),
+                (true, true) => println!("let {} : {}", ctx.wrap(sig), ctx.wrap(scheme)),
+                (true, false) => println!("let {}", ctx.wrap(sig)),
+            }
+        }
+    }
+
+    fn browse_data(&self, ir: &ir::IrStorage, data_ref: DataRef) {
+        let ctx = self.ctx;
+        let data = ir.data.get(data_ref).unwrap();
+        let indent = match data.name {
+            Some(name) => {
+                let names = ctx.names.borrow();
+                print!("data {}", names.get(name).unwrap());
+                for &param in data.params.iter() {
+                    print!(" {}", names.get(param).unwrap());
+                }
+                println!();
+                "  "
+            }
+            None => "",
+        };
+        for &cons_ref in data.cons.iter() {
+            let sig = &ir.cons.get(cons_ref).unwrap().sig;
+            let scheme = ir.types.cons.get(cons_ref).unwrap();
+            println!(
+                "{}con {} : {}",
+                indent,
+                ctx.wrap(&sig[..]),
+                ctx.wrap(scheme)
+            );
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use crate::ast::trivia::Trivia;
+use crate::refs::*;
+use crate::storage::*;
+
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub file: FileRef,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    // The smallest span covering both, which must be in the same file.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            file: self.file,
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Spanned<T> {
+    pub span: Span,
+    pub node: T,
+}
+
+impl<T> std::ops::Deref for Spanned<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.node
+    }
+}
+
+impl<T: Display> Display for Spanned<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.node.fmt(f)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub name: String,
+    pub text: String,
+    pub trivia: Trivia,
+}
+
+impl SourceFile {
+    // The one-based line and column of a byte offset.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let before = &self.text[..offset.min(self.text.len())];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |ix| ix + 1);
+        (line, before[line_start..].chars().count() + 1)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpanStorage {
+    pub decl: HashStorage<Span, DeclRef>,
+    pub cons: HashStorage<Span, ConsRef>,
+    pub data: HashStorage<Span, DataRef>,
+    pub modl: HashStorage<Span, ModlRef>,
+}
+
+impl SpanStorage {
+    pub fn new() -> Self {
+        SpanStorage {
+            decl: HashStorage::new(),
+            cons: HashStorage::new(),
+            data: HashStorage::new(),
+            modl: HashStorage::new(),
+        }
+    }
+}
+
+impl Display for crate::ctx::WithContext<'_, Span> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let files = self.files.borrow();
+        let file = files.get(self.val.file).unwrap();
+        let (line, col) = file.line_col(self.val.start);
+        write!(f, "{}:{}:{}", &file.name, line, col)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ctx::Context;
+    use crate::phases;
+
+    use bumpalo::Bump;
+
+    const SOURCE: &str = "mod M = mod\n  con Nil\nend\ndef len (l) = 0\ndef x = nope";
+
+    #[test]
+    fn spans_point_into_the_source() {
+        let arena = Bump::new();
+        let ctx = Context::new(&arena);
+        phases::load_source(&ctx, SOURCE).unwrap();
+        let files = ctx.files.borrow();
+        let text = |span: &Span| &files.get(span.file).unwrap().text[span.start..span.end];
+        let texts = |spans: Vec<&Span>| {
+            let mut texts: Vec<_> = spans.into_iter().map(text).collect();
+            texts.sort();
+            texts
+        };
+
+        let ir = ctx.ir.borrow();
+        let decls = texts((&ir.spans.decl).into_iter().map(|(_, span)| span).collect());
+        assert_eq!(decls, ["def len (l) = 0", "def x = nope"]);
+        let cons = texts((&ir.spans.cons).into_iter().map(|(_, span)| span).collect());
+        assert_eq!(cons, ["con Nil"]);
+        let modls = texts((&ir.spans.modl).into_iter().map(|(_, span)| span).collect());
+        assert_eq!(modls, ["mod\n  con Nil\nend", SOURCE]);
+
+        let diagnostics = ctx.diagnostics.borrow();
+        let span = diagnostics[0].primary.as_ref().unwrap().span;
+        assert_eq!(text(&span), "nope");
+        assert_eq!(ctx.wrap(span).to_string(), "test.fri:5:9");
+    }
+}
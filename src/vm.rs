@@ -0,0 +1,441 @@
+use crate::builtin::{Operand, Outcome, Prim, View};
+use crate::bytecode::{Op, Program};
+use crate::ctx::{Context, WithContext};
+use crate::error::{self, Diagnostic};
+use crate::ir::{Literal, Sign};
+use crate::span::Span;
+use crate::storage::*;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Unit,
+    Number(f64),
+    String(Rc<str>),
+    Data(u32, Rc<[Value]>),
+    Func(Rc<Closure>),
+    Scope(Rc<Env>),
+}
+
+impl Operand for Value {
+    type Cons = u32;
+    fn view(&self) -> View<'_, Self, u32> {
+        match self {
+            Value::Unit => View::Unit,
+            Value::Number(n) => View::Number(*n),
+            Value::String(s) => View::String(s),
+            Value::Data(cons, fields) => View::Data(*cons, fields),
+            // Scopes are never seen by primitives.
+            Value::Func(_) | Value::Scope(_) => View::Func,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    code: u32,
+    env: Rc<Env>,
+    args: Vec<Value>,
+}
+
+// What a closure or scope captured, and the values of
+// a scope's members once they've been made.
+#[derive(Debug, Default)]
+pub struct Env {
+    values: Vec<Value>,
+    cache: RefCell<Vec<Option<Value>>>,
+}
+
+#[derive(Debug)]
+enum Update {
+    Global(u32),
+    Member(Rc<Env>, u32),
+}
+
+/*
+    What becomes of a call's value: it's given the arguments it was
+    passed beyond what its code takes, and then kept if it belongs
+    to a declaration without arguments. A tail call hands its own
+    continuation on to the code it calls, so it needs no new frame.
+*/
+#[derive(Debug, Default)]
+struct Cont {
+    extra: Vec<Value>,
+    update: Option<Update>,
+}
+
+#[derive(Debug)]
+struct Frame {
+    code: u32,
+    pc: u32,
+    base: usize,
+    env: Rc<Env>,
+    cont: Cont,
+    // Where the code came from, or else the code a tail call replaced.
+    span: Option<Span>,
+}
+
+pub struct Machine<'ctx, 'p> {
+    ctx: &'ctx Context<'ctx>,
+    program: &'p Program,
+    consts: Vec<Value>,
+    globals: Vec<Option<Value>>,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+}
+
+type Result<T = ()> = error::Result<T>;
+
+impl<'ctx, 'p> Machine<'ctx, 'p> {
+    pub fn new(ctx: &'ctx Context<'ctx>, program: &'p Program) -> Self {
+        let consts = program
+            .consts
+            .iter()
+            .map(|lit| match lit {
+                Literal::Unit => Value::Unit,
+                Literal::Number(n) => Value::Number(*n),
+                Literal::String(s) => Value::String(s.as_str().into()),
+            })
+            .collect();
+        Machine {
+            ctx,
+            program,
+            consts,
+            globals: vec![None; program.globals.len()],
+            stack: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn run(&mut self, global: u32) -> Result<Value> {
+        self.global(global);
+        self.execute()?;
+        Ok(self.stack.pop().unwrap())
+    }
+
+    fn error(&self, code: error::ErrorCode, message: String) -> Box<Diagnostic> {
+        let diag = Diagnostic::new(code, message);
+        // Lambdas and builtins have no span, so point at whatever called them.
+        let span = self.frames.iter().rev().find_map(|frame| frame.span);
+        Box::new(match span {
+            Some(span) => diag.with_primary(span, "while evaluating this"),
+            None => diag,
+        })
+    }
+
+    // The loader checks all it can, but which value reaches an instruction
+    // is only known here, so a file made by hand can still get it wrong.
+    fn malformed(&self) -> Box<Diagnostic> {
+        self.error(
+            error::InvalidBytecode,
+            "The program's bytecode is malformed".into(),
+        )
+    }
+
+    fn show(&self, value: &Value) -> String {
+        self.ctx.wrap((self.program, value)).to_string()
+    }
+
+    fn enter(&mut self, code: u32, env: Rc<Env>, args: Vec<Value>, cont: Cont, span: Option<Span>) {
+        let base = self.stack.len();
+        let slots = self.program.codes[code as usize].slots as usize;
+        let span = self.program.codes[code as usize].span.or(span);
+        self.stack.extend(args);
+        self.stack.resize(base + slots, Value::Unit);
+        self.frames.push(Frame {
+            code,
+            pc: 0,
+            base,
+            env,
+            cont,
+            span,
+        });
+    }
+
+    fn global(&mut self, global: u32) {
+        let code = self.program.globals[global as usize];
+        if self.program.codes[code as usize].arity > 0 {
+            self.stack.push(Value::Func(Rc::new(Closure {
+                code,
+                env: Rc::default(),
+                args: Vec::new(),
+            })));
+        } else if let Some(value) = &self.globals[global as usize] {
+            self.stack.push(value.clone());
+        } else {
+            let cont = Cont {
+                extra: Vec::new(),
+                update: Some(Update::Global(global)),
+            };
+            self.enter(code, Rc::default(), Vec::new(), cont, None);
+        }
+    }
+
+    fn member(&mut self, member: u32, env: Rc<Env>) -> Result {
+        let member = self.program.members[member as usize];
+        if self.program.codes[member.code as usize].arity > 0 {
+            self.stack.push(Value::Func(Rc::new(Closure {
+                code: member.code,
+                env,
+                args: Vec::new(),
+            })));
+            return Ok(());
+        }
+        let cached = match env.cache.borrow().get(member.slot as usize) {
+            Some(cached) => cached.clone(),
+            None => return Err(self.malformed()),
+        };
+        match cached {
+            Some(value) => self.stack.push(value),
+            None => {
+                let cont = Cont {
+                    extra: Vec::new(),
+                    update: Some(Update::Member(env.clone(), member.slot)),
+                };
+                self.enter(member.code, env, Vec::new(), cont, None);
+            }
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, func: Value, args: Vec<Value>, cont: Cont, span: Option<Span>) -> Result {
+        let closure = match func {
+            Value::Func(closure) => closure,
+            other => {
+                return Err(self.error(
+                    error::InvalidOperands,
+                    format!("Applied {}, which is not a function", self.show(&other)),
+                ))
+            }
+        };
+
+        let arity = self.program.codes[closure.code as usize].arity as usize;
+        let mut args = [&closure.args[..], &args[..]].concat();
+        if args.len() < arity {
+            let partial = Value::Func(Rc::new(Closure {
+                code: closure.code,
+                env: closure.env.clone(),
+                args,
+            }));
+            return self.finish(partial, cont);
+        }
+
+        let mut extra = args.split_off(arity);
+        extra.extend(cont.extra);
+        let cont = Cont {
+            extra,
+            update: cont.update,
+        };
+        self.enter(closure.code, closure.env.clone(), args, cont, span);
+        Ok(())
+    }
+
+    fn finish(&mut self, value: Value, cont: Cont) -> Result {
+        if !cont.extra.is_empty() {
+            let rest = Cont {
+                extra: Vec::new(),
+                update: cont.update,
+            };
+            return self.call(value, cont.extra, rest, None);
+        }
+        match cont.update {
+            Some(Update::Global(global)) => self.globals[global as usize] = Some(value.clone()),
+            Some(Update::Member(env, slot)) => {
+                env.cache.borrow_mut()[slot as usize] = Some(value.clone())
+            }
+            None => {}
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn operands(&mut self, count: u32) -> Vec<Value> {
+        self.stack.split_off(self.stack.len() - count as usize)
+    }
+
+    fn execute(&mut self) -> Result {
+        let program = self.program;
+        while let Some(frame) = self.frames.last_mut() {
+            let code = &program.codes[frame.code as usize];
+            let op = code.ops[frame.pc as usize];
+            frame.pc += 1;
+            let base = frame.base;
+
+            match op {
+                Op::Const(lit) => self.stack.push(self.consts[lit as usize].clone()),
+                Op::Load(slot) => {
+                    let value = self.stack[base + slot as usize].clone();
+                    self.stack.push(value);
+                }
+                Op::Store(slot) => {
+                    let value = self.stack.pop().unwrap();
+                    self.stack[base + slot as usize] = value;
+                }
+                Op::Capture(ix) => match frame.env.values.get(ix as usize) {
+                    Some(value) => self.stack.push(value.clone()),
+                    None => return Err(self.malformed()),
+                },
+                Op::ThisScope => self.stack.push(Value::Scope(frame.env.clone())),
+                Op::Global(global) => self.global(global),
+                Op::Member(member) => match self.stack.pop().unwrap() {
+                    Value::Scope(env) => self.member(member, env)?,
+                    _ => return Err(self.malformed()),
+                },
+                Op::Closure(code, count) => {
+                    let values = self.operands(count);
+                    self.stack.push(Value::Func(Rc::new(Closure {
+                        code,
+                        env: Rc::new(Env {
+                            values,
+                            cache: RefCell::default(),
+                        }),
+                        args: Vec::new(),
+                    })));
+                }
+                Op::Scope(scope, count) => {
+                    let values = self.operands(count);
+                    let members = program.scopes[scope as usize] as usize;
+                    self.stack.push(Value::Scope(Rc::new(Env {
+                        values,
+                        cache: RefCell::new(vec![None; members]),
+                    })));
+                }
+                Op::Data(cons, count) => {
+                    let fields = self.operands(count);
+                    self.stack.push(Value::Data(cons, fields.into()));
+                }
+                Op::Field(field) => match self.stack.pop().unwrap() {
+                    Value::Data(_, fields) if (field as usize) < fields.len() => {
+                        self.stack.push(fields[field as usize].clone())
+                    }
+                    _ => return Err(self.malformed()),
+                },
+                Op::Switch(table) => {
+                    let tag = match self.stack.pop().unwrap() {
+                        Value::Data(tag, _) => Some(tag),
+                        _ => None,
+                    };
+                    let table = &code.tables[table as usize];
+                    frame.pc = table
+                        .cases
+                        .iter()
+                        .find(|&&(cons, _)| Some(cons) == tag)
+                        .map_or(table.default, |&(_, target)| target);
+                }
+                Op::JumpIf(lit, target) => {
+                    let equal = match (self.stack.pop().unwrap(), &self.consts[lit as usize]) {
+                        (Value::Unit, Value::Unit) => true,
+                        (Value::Number(a), Value::Number(b)) => a == *b,
+                        (Value::String(a), Value::String(b)) => a == *b,
+                        _ => false,
+                    };
+                    if equal {
+                        frame.pc = target;
+                    }
+                }
+                Op::Jump(target) => frame.pc = target,
+                Op::Call(count) => {
+                    let args = self.operands(count);
+                    let func = self.stack.pop().unwrap();
+                    self.call(func, args, Cont::default(), None)?;
+                }
+                Op::TailCall(count) => {
+                    let args = self.operands(count);
+                    let func = self.stack.pop().unwrap();
+                    let frame = self.frames.pop().unwrap();
+                    self.stack.truncate(frame.base);
+                    self.call(func, args, frame.cont, frame.span)?;
+                }
+                Op::Return => {
+                    let value = self.stack.pop().unwrap();
+                    let frame = self.frames.pop().unwrap();
+                    self.stack.truncate(frame.base);
+                    self.finish(value, frame.cont)?;
+                }
+                Op::Prim(prim) => {
+                    let rhs = self.stack.pop().unwrap();
+                    let lhs = self.stack.pop().unwrap();
+                    let value = self.prim(prim, lhs, rhs)?;
+                    self.stack.push(value);
+                }
+                Op::Hole => return Err(self.error(error::UnfilledHole, "Evaluated a hole".into())),
+                Op::NoClause => {
+                    let args = &self.stack[base..base + code.arity as usize];
+                    let args: Vec<_> = args.iter().map(|arg| self.show(arg)).collect();
+                    return Err(self.error(
+                        error::NoMatchingPattern,
+                        format!("No clause matches {}", args.join(", ")),
+                    ));
+                }
+                Op::NoMatch(slot) => {
+                    let value = self.show(&self.stack[base + slot as usize]);
+                    return Err(self.error(
+                        error::NoMatchingPattern,
+                        format!("No pattern matches {}", value),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn prim(&self, prim: Prim, lhs: Value, rhs: Value) -> Result<Value> {
+        Ok(match prim.apply(&lhs, &rhs) {
+            Some(Outcome::Number(n)) => Value::Number(n),
+            Some(Outcome::Truth(b)) => {
+                let cons = if b {
+                    self.program.truth.0
+                } else {
+                    self.program.truth.1
+                };
+                Value::Data(cons, Rc::new([]))
+            }
+            None => Err(self.invalid_operands(prim, &lhs, &rhs))?,
+        })
+    }
+
+    fn invalid_operands(&self, prim: Prim, lhs: &Value, rhs: &Value) -> Box<Diagnostic> {
+        self.error(
+            error::InvalidOperands,
+            prim.invalid_operands(self.show(lhs), self.show(rhs)),
+        )
+    }
+}
+
+impl fmt::Display for WithContext<'_, (&Program, &Value)> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (program, value) = self.val;
+        match value {
+            Value::Unit => write!(f, "()"),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::Func(_) => write!(f, "<function>"),
+            Value::Scope(_) => write!(f, "<scope>"),
+            Value::Data(cons, args) => {
+                let names = self.names.borrow();
+                let mut args = args.iter();
+                for (ix, sign) in program.cons[*cons as usize].iter().enumerate() {
+                    if ix > 0 {
+                        write!(f, " ")?;
+                    }
+                    match sign {
+                        Sign::Word(id) => write!(f, "{}", names.get(*id).unwrap())?,
+                        Sign::Patn(()) => {
+                            let arg = args.next().unwrap();
+                            match arg {
+                                Value::Data(_, nested) if !nested.is_empty() => {
+                                    write!(f, "({})", self.wrap((program, arg)))?
+                                }
+                                _ => write!(f, "{}", self.wrap((program, arg)))?,
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}